@@ -0,0 +1,7 @@
+#version 120
+
+varying vec3 v_color;
+
+void main(void) {
+	gl_FragColor = vec4(v_color, 1.0);
+}
@@ -0,0 +1,13 @@
+#version 120
+
+attribute vec3 position;
+attribute vec3 color;
+
+uniform mat4 model_view_perspective_matrix;
+
+varying vec3 v_color;
+
+void main() {
+	v_color = color;
+	gl_Position = model_view_perspective_matrix * vec4(position, 1.0);
+}
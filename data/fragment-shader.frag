@@ -1,28 +1,111 @@
 #version 120
 
+// Must match MAX_DIRECTIONAL_LIGHTS and MAX_POINT_LIGHTS in renderable.rs
+const int MAX_DIRECTIONAL_LIGHTS = 4;
+const int MAX_POINT_LIGHTS = 8;
+
+struct DirectionalLight {
+	vec3 direction;
+	vec3 color;
+};
+
+struct PointLight {
+	vec3 position;
+	vec3 color;
+	float constant;
+	float linear;
+	float quadratic;
+};
+
 uniform vec3 u_mat_specular;
 uniform vec3 u_mat_ambient;
-uniform vec3 u_light_color;
+uniform vec3 u_mat_diffuse;
+uniform float u_mat_shininess;
+uniform vec3 u_mat_emissive;
 uniform sampler2D u_mat_texture;
+uniform sampler2D u_mat_normal_map;
+uniform bool u_has_normal_map;
+uniform DirectionalLight u_directional_lights[MAX_DIRECTIONAL_LIGHTS];
+uniform int u_directional_light_count;
+uniform PointLight u_point_lights[MAX_POINT_LIGHTS];
+uniform int u_point_light_count;
+// Must match DebugMode::uniform in renderable.rs: 1 colors by normal
+uniform int u_debug_mode;
+uniform float u_alpha;
+// Fog is disabled unless u_fog_end > u_fog_start
+uniform vec3 u_fog_color;
+uniform float u_fog_start;
+uniform float u_fog_end;
 
 varying vec3 v_position;
+varying vec3 v_eye_position;
 varying vec3 v_normal;
+varying vec3 v_tangent;
 varying vec2 v_tex_uv;
-varying vec3 v_light_pos;
+varying vec3 v_color;
 
 void main(void) {
 
-	float brightness = dot(normalize(v_normal), normalize(v_light_pos));
+	vec3 normal = normalize(v_normal);
+	if (u_has_normal_map) {
+		// Tangent space: U along the tangent, V along the bitangent, and the
+		// geometric normal out of the surface
+		vec3 tangent = normalize(v_tangent - dot(v_tangent, normal) * normal);
+		vec3 bitangent = cross(normal, tangent);
+		vec3 mapped = texture2D(u_mat_normal_map, v_tex_uv).xyz * 2.0 - 1.0;
+		normal = normalize(mat3(tangent, bitangent, normal) * mapped);
+	}
+	if (u_debug_mode == 1) {
+		gl_FragColor = vec4(normal * 0.5 + 0.5, 1.0);
+		return;
+	}
+	vec3 tex_color = texture2D(u_mat_texture, v_tex_uv).xyz * v_color * u_mat_diffuse;
+	vec3 camera_dir = normalize(-v_position);
+	vec3 color = u_mat_ambient * tex_color;
 
-	vec3 tex_color = texture2D(u_mat_texture, v_tex_uv).xyz;
-	vec3 matte_color = mix(u_mat_ambient * tex_color,
-	                       u_light_color * tex_color,
-	                       brightness);
+	for (int i = 0; i < MAX_DIRECTIONAL_LIGHTS; i++) {
+		if (i >= u_directional_light_count) {
+			break;
+		}
+		DirectionalLight light = u_directional_lights[i];
+		vec3 light_dir = normalize(light.direction);
+		float brightness = dot(normal, light_dir);
+		// Each light mixes from ambient towards its own color, so a single
+		// light is the same as mix(ambient, light, brightness).
+		color += (light.color - u_mat_ambient) * tex_color * brightness;
 
-	vec3 camera_dir = normalize(-v_position);
-	vec3 half_direction = normalize(normalize(v_light_pos) + camera_dir);
-	float specular = pow(max(dot(half_direction, normalize(v_normal)), 0.0), 64.0);
+		vec3 half_direction = normalize(light_dir + camera_dir);
+		float specular = pow(max(dot(half_direction, normal), 0.0), u_mat_shininess);
+		color += specular * u_mat_specular;
+	}
 
-	gl_FragColor = vec4(matte_color + (specular * u_mat_specular), 1.0);
-}
+	// Point lights add to the global light
+	vec3 eye_dir = normalize(-v_eye_position);
+	for (int i = 0; i < MAX_POINT_LIGHTS; i++) {
+		if (i >= u_point_light_count) {
+			break;
+		}
+		PointLight light = u_point_lights[i];
+		vec3 to_light = light.position - v_eye_position;
+		float distance = length(to_light);
+		vec3 light_dir = to_light / distance;
+		float attenuation = 1.0 / (light.constant +
+		                           light.linear * distance +
+		                           light.quadratic * distance * distance);
+		float diffuse = max(dot(normal, light_dir), 0.0);
+		vec3 light_half = normalize(light_dir + eye_dir);
+		float light_specular = pow(max(dot(light_half, normal), 0.0), u_mat_shininess);
+		color += attenuation * light.color *
+		         (diffuse * tex_color + light_specular * u_mat_specular);
+	}
+
+	color += u_mat_emissive;
 
+	if (u_fog_end > u_fog_start) {
+		float fog = clamp((length(v_eye_position) - u_fog_start) / (u_fog_end - u_fog_start),
+		                  0.0, 1.0);
+		color = mix(color, u_fog_color, fog);
+	}
+
+	gl_FragColor = vec4(color, u_alpha);
+}
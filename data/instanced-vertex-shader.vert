@@ -0,0 +1,30 @@
+#version 120
+
+attribute vec3 position;
+attribute vec3 normal;
+attribute vec3 tangent;
+attribute vec2 tex_uv;
+attribute vec3 color;
+attribute mat4 instance_model_matrix;
+
+uniform mat4 view_matrix;
+uniform mat4 perspective_matrix;
+
+varying vec3 v_position;
+varying vec3 v_eye_position;
+varying vec3 v_normal;
+varying vec3 v_tangent;
+varying vec2 v_tex_uv;
+varying vec3 v_color;
+
+void main() {
+	mat4 model_view_matrix = view_matrix * instance_model_matrix;
+	vec4 eye_position = model_view_matrix * vec4(position, 1.0);
+	v_position = vec3(perspective_matrix * eye_position);
+	v_eye_position = vec3(eye_position);
+	v_normal = mat3(model_view_matrix) * normal;
+	v_tangent = mat3(model_view_matrix) * tangent;
+	v_tex_uv = tex_uv;
+	v_color = color;
+	gl_Position = perspective_matrix * eye_position;
+}
@@ -0,0 +1,21 @@
+#version 120
+
+uniform sampler2D u_texture;
+// Daylight tint, so the sky follows the time of day
+uniform vec3 u_tint;
+// Haze towards the horizon, matching the fog on distant surfaces. There is
+// no haze unless u_haze_height > 0.
+uniform vec3 u_fog_color;
+uniform float u_haze_height;
+
+varying vec2 v_tex_uv;
+varying vec3 v_direction;
+
+void main(void) {
+	vec3 color = texture2D(u_texture, v_tex_uv).rgb * u_tint;
+	if (u_haze_height > 0.0) {
+		float elevation = abs(normalize(v_direction).y);
+		color = mix(u_fog_color, color, smoothstep(0.0, u_haze_height, elevation));
+	}
+	gl_FragColor = vec4(color, 1.0);
+}
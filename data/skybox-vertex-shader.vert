@@ -0,0 +1,15 @@
+#version 120
+
+attribute vec3 position;
+attribute vec2 tex_uv;
+
+uniform mat4 view_perspective_matrix;
+
+varying vec2 v_tex_uv;
+varying vec3 v_direction;
+
+void main() {
+	v_tex_uv = tex_uv;
+	v_direction = position;
+	gl_Position = view_perspective_matrix * vec4(position, 1.0);
+}
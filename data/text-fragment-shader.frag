@@ -0,0 +1,10 @@
+#version 120
+
+uniform sampler2D u_font;
+uniform vec4 u_color;
+
+varying vec2 v_tex_uv;
+
+void main(void) {
+	gl_FragColor = texture2D(u_font, v_tex_uv) * u_color;
+}
@@ -0,0 +1,13 @@
+#version 120
+
+// Corner of a glyph's quad on screen, in clip space
+attribute vec2 position;
+// The same corner of the glyph in the font texture, in UV
+attribute vec2 tex_uv;
+
+varying vec2 v_tex_uv;
+
+void main() {
+	v_tex_uv = tex_uv;
+	gl_Position = vec4(position, 0.0, 1.0);
+}
@@ -2,23 +2,27 @@
 
 attribute vec3 position;
 attribute vec3 normal;
+attribute vec3 tangent;
 attribute vec2 tex_uv;
+attribute vec3 color;
 
+uniform mat4 model_view_matrix;
 uniform mat4 model_view_perspective_matrix;
 uniform mat3 normal_matrix;
-uniform mat3 light_matrix;
-uniform vec3 u_light_pos;
 
 varying vec3 v_position;
+varying vec3 v_eye_position;
 varying vec3 v_normal;
+varying vec3 v_tangent;
 varying vec2 v_tex_uv;
-varying vec3 v_light_pos;
+varying vec3 v_color;
 
 void main() {
 	v_position = vec3(model_view_perspective_matrix * vec4(position, 1.0));
+	v_eye_position = vec3(model_view_matrix * vec4(position, 1.0));
 	v_normal = normal_matrix * normal;
+	v_tangent = normal_matrix * tangent;
 	v_tex_uv = tex_uv;
-	v_light_pos = light_matrix * u_light_pos;
+	v_color = color;
 	gl_Position = model_view_perspective_matrix * vec4(position, 1.0);
 }
-
@@ -0,0 +1,135 @@
+//! Module for parsing command-line arguments.
+
+use errors::*;
+use log::LevelFilter;
+
+/// Directory assets and the config file are loaded from by default.
+pub const DEFAULT_DATA_DIR: &'static str = "data";
+
+/// Option list, printed for `--help` and after invalid arguments.
+pub const USAGE: &'static str =
+		"Usage: gl-demo [options]\n\
+		\n\
+		Options:\n\
+		\x20 --width <pixels>      initial window width (default 1024)\n\
+		\x20 --height <pixels>     initial window height (default 768)\n\
+		\x20 --fullscreen          borderless fullscreen on the primary monitor\n\
+		\x20 --no-vsync            don't wait for vertical sync\n\
+		\x20 --data-dir <path>     load assets and config.toml from <path> (default data)\n\
+		\x20 --log-level <level>   off, error, warn, info, debug or trace (default info)\n\
+		\x20 -h, --help            show this list\n";
+
+/// Settings from the command line.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Args {
+	/// Initial window width.
+	pub width: u32,
+	/// Initial window height.
+	pub height: u32,
+	/// Whether to go fullscreen on the primary monitor.
+	pub fullscreen: bool,
+	/// Whether to wait for vertical sync.
+	pub vsync: bool,
+	/// Directory assets and the config file are loaded from.
+	pub data_dir: String,
+	/// Most verbose log messages shown.
+	pub log_level: LevelFilter,
+	/// Whether to print the option list and exit.
+	pub help: bool,
+}
+
+impl Default for Args {
+	fn default() -> Args {
+		Args {
+			width: 1024,
+			height: 768,
+			fullscreen: false,
+			vsync: true,
+			data_dir: DEFAULT_DATA_DIR.to_string(),
+			log_level: LevelFilter::Info,
+			help: false,
+		}
+	}
+}
+
+impl Args {
+
+	/// Parse arguments, not including the program name. Options which take a
+	/// value accept it either as the next argument or after an `=`, like
+	/// `--width=800`.
+	pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Args> {
+		let mut parsed = Args::default();
+		let mut args = args.into_iter();
+		while let Some(arg) = args.next() {
+			let (name, inline_value) = match arg.find('=') {
+				Some(i) => (arg[..i].to_string(), Some(arg[i + 1..].to_string())),
+				None => (arg.clone(), None),
+			};
+			let mut value = || match inline_value.clone().or_else(|| args.next()) {
+				Some(value) => Ok(value),
+				None => Err(Error::from(format!("Missing value for {}", name))),
+			};
+			match name.as_ref() {
+				"--width" => parsed.width = try!{ parse_size(&name, try!{ value() }) },
+				"--height" => parsed.height = try!{ parse_size(&name, try!{ value() }) },
+				"--data-dir" => parsed.data_dir = try!{ value() },
+				"--log-level" => {
+					let level = try!{ value() };
+					parsed.log_level = try!{ level.parse()
+							.chain_err(|| format!("Unknown log level \"{}\"", level)) };
+				},
+				"--fullscreen" | "--no-vsync" | "--help" if inline_value.is_some() =>
+					bail!("{} doesn't take a value", name),
+				"--fullscreen" => parsed.fullscreen = true,
+				"--no-vsync" => parsed.vsync = false,
+				"--help" | "-h" => parsed.help = true,
+				_ => bail!("Unknown argument \"{}\"", arg),
+			}
+		}
+		Ok(parsed)
+	}
+}
+
+/// Parse a window dimension, which must be a positive number of pixels.
+fn parse_size(name: &str, value: String) -> Result<u32> {
+	match value.parse() {
+		Ok(size) if size > 0 => Ok(size),
+		_ => bail!("Expected a number of pixels for {}, found \"{}\"", name, value),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use log::LevelFilter;
+	use super::Args;
+
+	fn parse(args: &[&str]) -> ::errors::Result<Args> {
+		Args::parse(args.iter().map(|arg| arg.to_string()))
+	}
+
+	#[test]
+	fn test_parse() {
+		assert_eq!(Args::default(), parse(&[]).unwrap());
+		assert_eq!(Args {
+				width: 800,
+				height: 600,
+				fullscreen: true,
+				vsync: false,
+				data_dir: "/opt/gl-demo".to_string(),
+				log_level: LevelFilter::Debug,
+				help: false,
+			},
+			parse(&["--width", "800", "--height=600", "--fullscreen", "--no-vsync",
+				"--data-dir", "/opt/gl-demo", "--log-level", "debug"]).unwrap());
+		assert!(parse(&["--help"]).unwrap().help);
+		assert!(parse(&["-h"]).unwrap().help);
+
+		assert!(parse(&["--width"]).is_err());
+		assert!(parse(&["--width", "wide"]).is_err());
+		assert!(parse(&["--height", "0"]).is_err());
+		assert!(parse(&["--log-level", "loud"]).is_err());
+		assert!(parse(&["--fullscreen=yes"]).is_err());
+		assert!(parse(&["--fly"]).is_err());
+		assert!(parse(&["teapot.obj"]).is_err());
+	}
+}
@@ -0,0 +1,351 @@
+//! Module for loading settings from `data/config.toml`.
+//!
+//! Everything in the file is optional: settings which aren't given keep the
+//! compiled-in defaults.
+
+use args::DEFAULT_DATA_DIR;
+use display_math;
+use errors::*;
+use input::KeyBindings;
+use model::heightmap::simpleheightmap;
+use std::io::BufRead;
+use std::path::Path;
+use toml;
+
+/// Name of the config file in the data directory.
+pub const CONFIG_FILE: &'static str = "config.toml";
+
+/// Character movement settings, from the `[physics]` section.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PhysicsConfig {
+	/// Top walking speed.
+	pub max_speed: f32,
+	/// Deceleration when no movement keys are held.
+	pub decel: f32,
+	/// Initial upwards speed of a jump.
+	pub max_jump: f32,
+	/// Downwards acceleration.
+	pub gravity: f32,
+	/// Steepest slope, in degrees, the character can walk up.
+	pub max_slope: f32,
+	/// Height below which the character respawns.
+	pub kill_plane: f32,
+	/// Seconds after walking off the ground during which the character can
+	/// still jump.
+	pub coyote_time: f32,
+	/// Seconds before landing during which a jump press is remembered.
+	pub jump_buffer: f32,
+}
+
+impl Default for PhysicsConfig {
+	fn default() -> PhysicsConfig {
+		PhysicsConfig {
+			max_speed: 12.0,
+			decel: 180.0,
+			max_jump: 12.0,
+			gravity: 72.0,
+			max_slope: 45.0,
+			kill_plane: -200.0,
+			coyote_time: 0.1,
+			jump_buffer: 0.1,
+		}
+	}
+}
+
+/// Camera settings, from the `[view]` section.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ViewConfig {
+	/// Initial field of view, in degrees.
+	pub fov: f32,
+	/// Near clip plane distance.
+	pub znear: f32,
+	/// Far clip plane distance.
+	pub zfar: f32,
+	/// Models further than this from the camera aren't drawn.
+	pub max_draw_distance: f32,
+	/// Distance over which models fade out before `max_draw_distance`.
+	pub fade_width: f32,
+	/// Maximum anisotropic filtering level for the terrain texture; 1
+	/// disables it.
+	pub anisotropy: f32,
+	/// Distance at which fog starts.
+	pub fog_start: f32,
+	/// Distance beyond which everything is hidden by fog. Fog is disabled if
+	/// this isn't greater than `fog_start`.
+	pub fog_end: f32,
+	/// Number of terrain tile meshes kept for reuse, besides those being
+	/// drawn.
+	pub lod_cache_size: usize,
+}
+
+impl Default for ViewConfig {
+	fn default() -> ViewConfig {
+		ViewConfig {
+			fov: 90.0,
+			znear: display_math::DEFAULT_ZNEAR,
+			zfar: display_math::DEFAULT_ZFAR,
+			max_draw_distance: display_math::DEFAULT_ZFAR,
+			fade_width: 16.0,
+			anisotropy: 4.0,
+			fog_start: 150.0,
+			fog_end: 500.0,
+			lod_cache_size: simpleheightmap::DEFAULT_LOD_CACHE_SIZE,
+		}
+	}
+}
+
+/// Asset file locations, from the `[paths]` section.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PathConfig {
+	/// The teapot model.
+	pub teapot: String,
+	/// The terrain heightmap. Terrain is generated if it's missing.
+	pub floor_heightmap: String,
+	/// Materials for the terrain.
+	pub floor_materials: String,
+	/// The font texture.
+	pub font: String,
+	/// Vertex shader for models and terrain.
+	pub vertex_shader: String,
+	/// Fragment shader for models and terrain.
+	pub fragment_shader: String,
+	/// Vertex shader for instanced models.
+	pub instanced_vertex_shader: String,
+	/// Vertex shader for debug lines.
+	pub debug_vertex_shader: String,
+	/// Fragment shader for debug lines.
+	pub debug_fragment_shader: String,
+	/// Vertex shader for the skybox.
+	pub skybox_vertex_shader: String,
+	/// Fragment shader for the skybox.
+	pub skybox_fragment_shader: String,
+	/// Vertex shader for text.
+	pub text_vertex_shader: String,
+	/// Fragment shader for text.
+	pub text_fragment_shader: String,
+	/// Skybox face towards +X. The sky is a flat color if any face is
+	/// missing.
+	pub skybox_posx: String,
+	/// Skybox face towards -X.
+	pub skybox_negx: String,
+	/// Skybox face towards +Y.
+	pub skybox_posy: String,
+	/// Skybox face towards -Y.
+	pub skybox_negy: String,
+	/// Skybox face towards +Z.
+	pub skybox_posz: String,
+	/// Skybox face towards -Z.
+	pub skybox_negz: String,
+}
+
+impl Default for PathConfig {
+	fn default() -> PathConfig {
+		PathConfig::in_dir(DEFAULT_DATA_DIR)
+	}
+}
+
+impl PathConfig {
+	/// The default asset file names, in `dir`.
+	pub fn in_dir(dir: &str) -> PathConfig {
+		let path = |name: &str| Path::new(dir).join(name).to_string_lossy().into_owned();
+		PathConfig {
+			teapot: path("wt-teapot.obj"),
+			floor_heightmap: path("heightmap.png"),
+			floor_materials: path("materials.mtl"),
+			font: path("font-texture.png"),
+			vertex_shader: path("vertex-shader.vert"),
+			fragment_shader: path("fragment-shader.frag"),
+			instanced_vertex_shader: path("instanced-vertex-shader.vert"),
+			debug_vertex_shader: path("debug-vertex-shader.vert"),
+			debug_fragment_shader: path("debug-fragment-shader.frag"),
+			skybox_vertex_shader: path("skybox-vertex-shader.vert"),
+			skybox_fragment_shader: path("skybox-fragment-shader.frag"),
+			text_vertex_shader: path("text-vertex-shader.vert"),
+			text_fragment_shader: path("text-fragment-shader.frag"),
+			skybox_posx: path("skybox-posx.png"),
+			skybox_negx: path("skybox-negx.png"),
+			skybox_posy: path("skybox-posy.png"),
+			skybox_negy: path("skybox-negy.png"),
+			skybox_posz: path("skybox-posz.png"),
+			skybox_negz: path("skybox-negz.png"),
+		}
+	}
+
+	/// The skybox faces, in the order `renderable::Skybox` expects.
+	pub fn skybox_faces(&self) -> [&str; 6] {
+		[&self.skybox_posx, &self.skybox_negx, &self.skybox_posy,
+			&self.skybox_negy, &self.skybox_posz, &self.skybox_negz]
+	}
+}
+
+/// All settings read from the config file.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+	/// Character movement.
+	pub physics: PhysicsConfig,
+	/// Camera.
+	pub view: ViewConfig,
+	/// Asset file locations.
+	pub paths: PathConfig,
+	/// Key bindings, from the `[keys]` section; see `KeyBindings::from_toml`.
+	pub keys: KeyBindings,
+}
+
+impl Config {
+
+	/// The default settings, with the default asset paths in `data_dir`.
+	pub fn in_dir(data_dir: &str) -> Config {
+		Config { paths: PathConfig::in_dir(data_dir), .. Config::default() }
+	}
+
+	/// Load settings from a TOML file, like:
+	///
+	/// ```toml
+	/// [physics]
+	/// gravity = 36.0
+	///
+	/// [view]
+	/// fov = 75
+	///
+	/// [paths]
+	/// teapot = "data/other-teapot.obj"
+	/// skybox_posx = "data/night-posx.png"
+	///
+	/// [keys]
+	/// jump = "LControl"
+	/// ```
+	///
+	/// Keys in each section are the names of the fields of `PhysicsConfig`,
+	/// `ViewConfig` and `PathConfig`. Settings which aren't given keep their
+	/// defaults. Unknown sections and settings are errors.
+	pub fn from_toml(read: &mut BufRead) -> Result<Config> {
+		Config::from_toml_in(read, DEFAULT_DATA_DIR)
+	}
+
+	/// Load settings from a TOML file, like `from_toml`, but with the default
+	/// asset paths in `data_dir`.
+	pub fn from_toml_in(read: &mut BufRead, data_dir: &str) -> Result<Config> {
+		let mut text = String::new();
+		try!{ read.read_to_string(&mut text).chain_err(|| "Could not read config") };
+		let mut value: toml::Value = try!{ toml::from_str(&text).chain_err(|| "Invalid config") };
+
+		// Paths which aren't given default to the data directory, so fill them
+		// in before `PathConfig::default` gets a chance to.
+		let defaults = try!{ toml::Value::try_from(PathConfig::in_dir(data_dir))
+				.chain_err(|| "Could not build default paths") };
+		if let Some(table) = value.as_table_mut() {
+			let paths = table.entry("paths".to_string())
+					.or_insert_with(|| toml::Value::Table(toml::value::Table::new()));
+			if let (Some(paths), Some(defaults)) = (paths.as_table_mut(), defaults.as_table()) {
+				for (key, path) in defaults.iter() {
+					paths.entry(key.clone()).or_insert_with(|| path.clone());
+				}
+			}
+		}
+		value.try_into().chain_err(|| "Invalid config")
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use glium::glutin::VirtualKeyCode;
+	use input::KeyBindings;
+	use super::{Config, PathConfig, PhysicsConfig, ViewConfig};
+
+	#[test]
+	fn test_from_toml() {
+		let config = b"# Low gravity\n\
+			[physics]\n\
+			gravity = 36.0\n\
+			max_slope = 30\n\
+			kill_plane = -50\n\
+			coyote_time = 0.25\n\
+			\n\
+			[view]\n\
+			fov = 75\n\
+			max_draw_distance = 500\n\
+			anisotropy = 8\n\
+			fog_end = 0\n\
+			lod_cache_size = 16\n\
+			\n\
+			[paths]\n\
+			teapot = \"data/other-teapot.obj\"\n\
+			skybox_negy = 'data/night-negy.png'\n\
+			\n\
+			[keys]\n\
+			jump = \"LControl\"\n";
+		let config = Config::from_toml(&mut &config[..]).unwrap();
+		assert_eq!(PhysicsConfig {
+			gravity: 36.0,
+			max_slope: 30.0,
+			kill_plane: -50.0,
+			coyote_time: 0.25,
+			.. PhysicsConfig::default()
+		}, config.physics);
+		assert_eq!(ViewConfig {
+				fov: 75.0,
+				max_draw_distance: 500.0,
+				anisotropy: 8.0,
+				fog_end: 0.0,
+				lod_cache_size: 16,
+				.. ViewConfig::default()
+			},
+			config.view);
+		let mut paths = PathConfig::default();
+		paths.teapot = "data/other-teapot.obj".to_string();
+		paths.skybox_negy = "data/night-negy.png".to_string();
+		assert_eq!(paths, config.paths);
+		assert_eq!(KeyBindings { jump: VirtualKeyCode::LControl, .. KeyBindings::default() },
+			config.keys);
+
+		assert_eq!(Config::default(), Config::from_toml(&mut &b""[..]).unwrap());
+		assert!(Config::from_toml(&mut &b"[physics]\ngravity = \"up\"\n"[..]).is_err());
+		assert!(Config::from_toml(&mut &b"[view]\nzoom = 2\n"[..]).is_err());
+		assert!(Config::from_toml(&mut &b"[view]\nlod_cache_size = 1.5\n"[..]).is_err());
+		assert!(Config::from_toml(&mut &b"[paths]\nteapot\n"[..]).is_err());
+		assert!(Config::from_toml(&mut &b"[keys]\nfly = \"F\"\n"[..]).is_err());
+		assert!(Config::from_toml(&mut &b"[window]\nwidth = 640\n"[..]).is_err());
+		assert!(Config::from_toml(&mut &b"gravity = 36\n"[..]).is_err());
+
+		let config = Config::from_toml_in(&mut &b"[paths]\nfont = \"font.png\"\n"[..], "assets")
+			.unwrap();
+		assert_eq!("assets/wt-teapot.obj", config.paths.teapot);
+		assert_eq!("assets/skybox-negz.png", config.paths.skybox_negz);
+		assert_eq!("font.png", config.paths.font);
+		assert_eq!("data/wt-teapot.obj", PathConfig::default().teapot);
+	}
+
+	#[test]
+	fn test_comments() {
+		let config = b"[physics] # Low gravity\n\
+			gravity = 36.0 # half the default\n\
+			\n\
+			[paths]\n\
+			teapot = \"data/#1 teapot.obj\" # not a comment inside quotes\n";
+		let config = Config::from_toml(&mut &config[..]).unwrap();
+		assert_eq!(36.0, config.physics.gravity);
+		assert_eq!("data/#1 teapot.obj", config.paths.teapot);
+	}
+
+	#[test]
+	fn test_strings() {
+		let config = Config::from_toml(&mut &b"[paths]\nteapot = \"tea\\\"pot\\u00e9.obj\"\n"[..])
+			.unwrap();
+		assert_eq!("tea\"pot\u{e9}.obj", config.paths.teapot);
+		assert!(Config::from_toml(&mut &b"[paths]\nteapot = \"teapot.obj'\n"[..]).is_err());
+		assert!(Config::from_toml(&mut &b"[paths]\nteapot = teapot.obj\n"[..]).is_err());
+	}
+
+	#[test]
+	fn test_malformed_sections() {
+		for header in ["[physics", "[physics]]", "[]", "[phys]ics]", "[physics] gravity = 1"].iter() {
+			let config = format!("{}\ngravity = 36.0\n", header);
+			assert!(Config::from_toml(&mut config.as_bytes()).is_err(), "{}", header);
+		}
+		assert!(Config::from_toml(&mut &b"[ physics ]\ngravity = 36.0\n"[..]).is_ok());
+	}
+}
@@ -0,0 +1,181 @@
+//! Module for the in-game debugging console.
+//!
+//! The console collects typed characters into a command line, and parses
+//! entered lines into `Command`s for the main loop to carry out.
+
+/// Number of lines of output the console keeps.
+const MAX_OUTPUT_LINES: usize = 100;
+
+/// A command entered at the console.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+	/// Move the character to the given location.
+	Teleport(f32, f32, f32),
+	/// Set the field of view, in degrees.
+	Fov(f32),
+	/// Spawn a model at the character's location.
+	Spawn,
+	/// Toggle a debug view, by name.
+	Toggle(String),
+	/// Show the value of a variable, by name.
+	Get(String),
+	/// List the available commands.
+	Help,
+}
+
+/// Help text for the console's commands.
+pub const HELP: &'static str =
+		"teleport <x> <y> <z>: move the character\n\
+		fov <degrees>: set the field of view\n\
+		spawn: spawn a teapot here\n\
+		toggle overlay|thirdperson: toggle a debug view\n\
+		get loc|fov|lod: show a value";
+
+/// Parse a line of console input into a `Command`.
+///
+/// Returns a message describing the problem if the line isn't a valid
+/// command.
+pub fn parse_command(line: &str) -> Result<Command, String> {
+	let mut words = line.split_whitespace();
+	let name = match words.next() {
+		Some(name) => name,
+		None => return Err("No command given".to_string()),
+	};
+	let args: Vec<&str> = words.collect();
+	let numbers = |count: usize| -> Result<Vec<f32>, String> {
+		if args.len() != count {
+			return Err(format!("{} takes {} arguments, got {}", name, count, args.len()));
+		}
+		args.iter()
+			.map(|arg| arg.parse::<f32>().map_err(|_| format!("Not a number: {}", arg)))
+			.collect()
+	};
+	let word = || -> Result<String, String> {
+		match args.len() {
+			1 => Ok(args[0].to_string()),
+			n => Err(format!("{} takes 1 argument, got {}", name, n)),
+		}
+	};
+	match name {
+		"teleport" => {
+			let xyz = try!{ numbers(3) };
+			Ok(Command::Teleport(xyz[0], xyz[1], xyz[2]))
+		},
+		"fov" => Ok(Command::Fov(try!{ numbers(1) }[0])),
+		"spawn" => { try!{ numbers(0) }; Ok(Command::Spawn) },
+		"toggle" => Ok(Command::Toggle(try!{ word() })),
+		"get" => Ok(Command::Get(try!{ word() })),
+		"help" => Ok(Command::Help),
+		_ => Err(format!("Unknown command: {}", name)),
+	}
+}
+
+/// The console's state: whether it's open, the line being typed, and previous
+/// output.
+#[derive(Debug)]
+pub struct Console {
+	open: bool,
+	input: String,
+	output: Vec<String>,
+}
+
+impl Console {
+
+	/// Create a new, closed Console.
+	pub fn new() -> Console {
+		Console {
+			open: false,
+			input: String::new(),
+			output: Vec::new(),
+		}
+	}
+
+	/// Whether the console is open, and so taking keyboard input.
+	pub fn is_open(&self) -> bool {
+		self.open
+	}
+
+	/// Open the console if it's closed, or close it if it's open.
+	pub fn toggle(&mut self) {
+		self.open = !self.open;
+	}
+
+	/// Handle a typed character.
+	///
+	/// Returns the line typed so far when Enter is pressed.
+	pub fn handle_char(&mut self, c: char) -> Option<String> {
+		match c {
+			'\r' | '\n' => {
+				let line = self.input.split_off(0);
+				self.print(&format!("> {}", line));
+				Some(line)
+			},
+			// Backspace
+			'\u{8}' | '\u{7f}' => {
+				self.input.pop();
+				None
+			},
+			c if !c.is_control() => {
+				self.input.push(c);
+				None
+			},
+			_ => None,
+		}
+	}
+
+	/// Add a message, which may be several lines, to the console's output.
+	pub fn print(&mut self, message: &str) {
+		self.output.extend(message.lines().map(|line| line.to_string()));
+		if self.output.len() > MAX_OUTPUT_LINES {
+			let excess = self.output.len() - MAX_OUTPUT_LINES;
+			self.output.drain(..excess);
+		}
+	}
+
+	/// The console's text for display: the last `lines - 1` lines of output,
+	/// followed by the input line.
+	pub fn text(&self, lines: usize) -> String {
+		let shown = lines.saturating_sub(1);
+		let start = self.output.len().saturating_sub(shown);
+		let mut text = String::new();
+		for line in self.output[start..].iter() {
+			text.push_str(line);
+			text.push('\n');
+		}
+		text.push_str("> ");
+		text.push_str(&self.input);
+		text.push('_');
+		text
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Command, Console, parse_command};
+
+	#[test]
+	fn test_parse_command() {
+		assert_eq!(Ok(Command::Teleport(1.0, -2.5, 30.0)), parse_command("teleport 1 -2.5 30"));
+		assert_eq!(Ok(Command::Fov(75.0)), parse_command("  fov 75 "));
+		assert_eq!(Ok(Command::Toggle("overlay".to_string())), parse_command("toggle overlay"));
+		assert_eq!(Ok(Command::Spawn), parse_command("spawn"));
+		assert!(parse_command("teleport 1 2").is_err());
+		assert!(parse_command("teleport 1 2 x").is_err());
+		assert!(parse_command("spawn 1").is_err());
+		assert!(parse_command("fly").is_err());
+		assert!(parse_command("").is_err());
+	}
+
+	#[test]
+	fn test_console_input() {
+		let mut console = Console::new();
+		for c in "tele".chars() {
+			assert_eq!(None, console.handle_char(c));
+		}
+		console.handle_char('\u{8}');
+		assert_eq!("> tel_", console.text(1));
+		assert_eq!(Some("tel".to_string()), console.handle_char('\r'));
+		console.print("one\ntwo");
+		assert_eq!("one\ntwo\n> _", console.text(3));
+	}
+}
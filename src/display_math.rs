@@ -1,106 +1,870 @@
 //! Vector math for display transformations.
 
 use errors::*;
-use glium::glutin::Window;
-use linear_algebra::{Mat4, Vec3};
+use glium::glutin::{ElementState, MouseScrollDelta, Window};
+use linear_algebra::{Aabb, Float, Mat4, Ray, Vec3, Vec4};
+use model::gpu::ModelInstance;
 
 /// Representation of a camera: location and direction.
+///
+/// The location and direction can be `f32` or `f64`; see `Float`.
 #[derive(Debug)]
-pub struct Camera {
+pub struct Camera<T: Copy = f32> {
 	/// Location of this camera.
-	pub loc: Vec3<f32>,
-	/// Direction of this camera.
-	pub dir: Vec3<f32>,
+	pub loc: Vec3<T>,
+	/// Direction of this camera. Rotated cameras keep the XZ component at
+	/// unit length, with Y the tangent of the pitch; see `direction`.
+	pub dir: Vec3<T>,
+	/// Radians the camera turns per unit of mouse movement; see
+	/// `handle_mouse_move`.
+	pub sensitivity: f32,
 }
 
-/// Compute a view transformation matrix based on the given parameters.
+/// Maximum angle, in radians, the camera can pitch above or below the
+/// horizon.
+pub const MAX_PITCH: f32 = 89.0 * ::std::f32::consts::PI / 180.0;
+
+/// Default `Camera::sensitivity`.
+pub const DEFAULT_MOUSE_SENSITIVITY: f32 = 0.005;
+
+/// Get the camera direction for a yaw, in radians about the Y axis from +X
+/// towards +Z, and a pitch, in radians above the horizon.
 ///
-/// This transformation is mostly standard; see [OpenGL
-/// `gluLookAt`](https://www.opengl.org/sdk/docs/man2/xhtml/gluLookAt.xml) for
-/// a detailed description of what it does and how it works.
-pub fn view_matrix(position: Vec3<f32>, direction: Vec3<f32>, up: Vec3<f32>) -> Mat4<f32> {
+/// The XZ component is unit length, and Y is the tangent of the pitch.
+pub fn direction<T: Float>(yaw: T, pitch: T) -> Vec3<T> {
+	Vec3::from([yaw.cos(), pitch.tan(), yaw.sin()])
+}
+
+impl<T: Float> Camera<T> {
+	/// Create a camera at `eye` looking towards `target`.
+	///
+	/// The camera doesn't store an up vector, so `up` is only checked to not
+	/// be parallel to the view direction, which would leave the view
+	/// undefined.
+	pub fn look_at(eye: Vec3<T>, target: Vec3<T>, up: Vec3<T>) -> Camera<T> {
+		let dir = (target - eye).normalize();
+		debug_assert!(up.cross(dir).length() > T::from_f32(0.0), "Camera up is parallel to view direction");
+		Camera {
+			loc: eye,
+			dir: dir,
+			sensitivity: DEFAULT_MOUSE_SENSITIVITY,
+		}
+	}
+
+	/// Get the angle, in radians, of the view direction about the Y axis, from
+	/// +X towards +Z.
+	pub fn yaw(&self) -> T {
+		self.dir[2].atan2(self.dir[0])
+	}
+
+	/// Get the angle, in radians, of the view direction above the horizon.
+	pub fn pitch(&self) -> T {
+		self.dir[1].atan2(self.dir[0].hypot(self.dir[2]))
+	}
+
+	/// Move this camera a fraction `alpha` of the way towards `target`.
+	///
+	/// Location is interpolated linearly and direction spherically, so
+	/// calling this every frame gives a lagged follow camera. `alpha` should be
+	/// between 0 (don't move) and 1 (snap to `target`).
+	pub fn smooth_follow(&mut self, target: &Camera<T>, alpha: T) {
+		let one = T::from_f32(1.0);
+		self.loc = self.loc.lerp(target.loc, alpha);
+		let from = self.dir.normalize();
+		let to = target.dir.normalize();
+		let angle = from.dot(to).max(-one).min(one).acos();
+		self.dir = if angle.sin() < T::from_f32(1e-4) {
+			// Nearly parallel (or opposite, where any path is as good as any
+			// other), so the interpolation is nearly linear anyway
+			from.lerp(to, alpha).normalize()
+		} else {
+			(from * ((one - alpha) * angle).sin() + to * (alpha * angle).sin()) / angle.sin()
+		};
+	}
+
+	/// Rotate this camera by `yaw` radians about the Y axis and `pitch` radians
+	/// towards the zenith.
+	///
+	/// `dir` is recomputed from the new angles, so its XZ component is unit
+	/// length, and the pitch is clamped to `MAX_PITCH` so the camera never
+	/// flips over the zenith or nadir.
+	pub fn rotate(&mut self, yaw: T, pitch: T) {
+		let max_pitch = T::from_f32(MAX_PITCH);
+		let new_pitch = (self.pitch() + pitch).max(-max_pitch).min(max_pitch);
+		self.dir = direction(self.yaw() + yaw, new_pitch);
+	}
+}
 
-	let f = direction.normalize();
+/// Minimum distance from an `OrbitCamera` to its center.
+pub const MIN_ORBIT_RADIUS: f32 = 0.5;
 
-	let s = up.cross(f).normalize();
+/// A camera which orbits around a fixed point, e.g. for inspecting a scene.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OrbitCamera {
+	/// The point this camera looks at.
+	pub center: Vec3<f32>,
+	/// Distance from the camera to the center.
+	pub radius: f32,
+	/// Angle, in radians, of the view direction about the Y axis, from +X
+	/// towards +Z.
+	pub yaw: f32,
+	/// Angle, in radians, of the view direction above the horizon.
+	pub pitch: f32,
+}
 
-	let u = f.cross(s);
+impl OrbitCamera {
+	/// Get a `Camera` at this orbit camera's position looking at its center.
+	pub fn to_camera(self) -> Camera {
+		let dir = direction(self.yaw, self.pitch);
+		Camera {
+			loc: self.center - dir.normalize() * self.radius,
+			dir: dir,
+			sensitivity: DEFAULT_MOUSE_SENSITIVITY,
+		}
+	}
+
+	/// Move this camera `delta` closer to its center, down to
+	/// `MIN_ORBIT_RADIUS`.
+	pub fn zoom(self, delta: f32) -> OrbitCamera {
+		OrbitCamera {
+			radius: (self.radius - delta).max(MIN_ORBIT_RADIUS),
+			.. self
+		}
+	}
 
-	let p = [-position[0] * s[0] - position[1] * s[1] - position[2] * s[2],
-	         -position[0] * u[0] - position[1] * u[1] - position[2] * u[2],
-	         -position[0] * f[0] - position[1] * f[1] - position[2] * f[2]];
+	/// Orbit this camera by `dyaw` radians about the Y axis and `dpitch`
+	/// radians towards the zenith.
+	///
+	/// As with `Camera::rotate`, pitch is clamped to `MAX_PITCH`.
+	pub fn rotate(self, dyaw: f32, dpitch: f32) -> OrbitCamera {
+		OrbitCamera {
+			yaw: (self.yaw + dyaw) % (2.0 * ::std::f32::consts::PI),
+			pitch: (self.pitch + dpitch).max(-MAX_PITCH).min(MAX_PITCH),
+			.. self
+		}
+	}
+}
+
+/// How the camera moves.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CameraMode {
+	/// The camera is at the character's eyes.
+	FirstPerson,
+	/// The camera sits `distance` behind and `height` above the character,
+	/// and orbits around it.
+	ThirdPerson {
+		/// Distance from the camera to its pivot above the character.
+		distance: f32,
+		/// Height of the pivot above the character.
+		height: f32,
+	},
+	/// The camera orbits a fixed point, independent of the character.
+	Orbit(OrbitCamera),
+}
+
+/// Compute the location of a camera on a boom looking at `target` along `dir`.
+///
+/// The boom pivots `height` above `target` and extends `distance` back along
+/// `dir`, so rotating `dir` orbits the camera around the target.
+pub fn boom_position(target: Vec3<f32>, dir: Vec3<f32>, distance: f32, height: f32) -> Vec3<f32> {
+	target + Vec3::from([0.0, height, 0.0]) - dir.normalize() * distance
+}
+
+/// Compute a view transformation matrix based on the given parameters.
+///
+/// This transformation is mostly standard; see [OpenGL
+/// `gluLookAt`](https://www.opengl.org/sdk/docs/man2/xhtml/gluLookAt.xml) for
+/// a detailed description of what it does and how it works. This crate uses
+/// left-handed eye space; see `Mat4::look_at_lh`.
+pub fn view_matrix<T: Float>(position: Vec3<T>, direction: Vec3<T>, up: Vec3<T>) -> Mat4<T> {
+	Mat4::look_at_lh(position, direction, up)
+}
 
-	Mat4::from([
-		[s[0], u[0], f[0], 0.0],
-		[s[1], u[1], f[1], 0.0],
-		[s[2], u[2], f[2], 0.0],
-		[p[0], p[1], p[2], 1.0],
-	])
+/// Default distance to the near clip plane.
+pub const DEFAULT_ZNEAR: f32 = 0.1;
+/// Default distance to the far clip plane.
+pub const DEFAULT_ZFAR: f32 = 1048576.0;
 
+/// Compute a perspective matrix based on the given parameters, with the
+/// default near and far clip planes.
+///
+/// See `perspective_matrix_full`.
+pub fn perspective_matrix<T: Float>(width: u32, height: u32, fov: T) -> Mat4<T> {
+	perspective_matrix_full(width, height, fov,
+		T::from_f32(DEFAULT_ZNEAR), T::from_f32(DEFAULT_ZFAR))
 }
 
 /// Compute a perspective matrix based on the given parameters.
 ///
 /// This transformation is mostly standard; see [OpenGL
 /// `gluPerspective`](https://www.opengl.org/sdk/docs/man2/xhtml/gluPerspective.xml)
-/// for a detailed description of what it does and how it works.
-pub fn perspective_matrix(width: u32, height: u32, fov: f32) -> Mat4<f32> {
-	let aspect_ratio = height as f32 / width as f32;
+/// for a detailed description of what it does and how it works. Points at
+/// `znear` map to NDC Z -1, and points at `zfar` to NDC Z 1. This crate uses
+/// left-handed eye space; see `Mat4::perspective_lh`.
+pub fn perspective_matrix_full<T: Float>(width: u32,
+		height: u32,
+		fov: T,
+		znear: T,
+		zfar: T) -> Mat4<T> {
+	Mat4::perspective_lh(fov, T::from_f32(width as f32 / height as f32), znear, zfar)
+}
+
+/// Compute an orthographic projection matrix based on the given parameters.
+///
+/// This is the standard OpenGL orthographic projection (see [OpenGL
+/// `glOrtho`](https://www.opengl.org/sdk/docs/man2/xhtml/glOrtho.xml)), but in
+/// this crate's left-handed eye space (see `Mat4::orthographic_lh`), so points
+/// at Z = `near` map to NDC Z -1 and points at Z = `far` to NDC Z 1.
+///
+/// Used with an identity view matrix in a `DefaultRenderState`, this allows
+/// 2D overlays to be rendered through the same pipeline as 3D objects.
+pub fn orthographic_matrix(left: f32,
+		right: f32,
+		bottom: f32,
+		top: f32,
+		near: f32,
+		far: f32) -> Mat4<f32> {
+	Mat4::orthographic_lh(left, right, bottom, top, near, far)
+}
+
+/// Compute an orthographic projection matrix mapping window pixel
+/// coordinates to the screen.
+///
+/// The origin is the top left of a window `width` by `height` pixels in size,
+/// with Y increasing downwards, and Z from -1 to 1 is visible. This is
+/// convenient for HUD elements and other 2D overlays.
+pub fn orthographic_2d(width: u32, height: u32) -> Mat4<f32> {
+	orthographic_matrix(0.0, width as f32, height as f32, 0.0, -1.0, 1.0)
+}
+
+/// Convert a point on the screen to a ray in world space, e.g. for picking.
+///
+/// `screen_x` and `screen_y` are in pixels from the top left of a window
+/// `width` by `height` pixels in size. The returned ray starts on the near
+/// clip plane and its direction is normalized.
+///
+/// This will panic if `view * perspective` is not invertible, which should
+/// never be the case for matrices built with this module.
+pub fn screen_to_world_ray(screen_x: f32,
+		screen_y: f32,
+		width: u32,
+		height: u32,
+		view: Mat4<f32>,
+		perspective: Mat4<f32>) -> Ray<f32> {
+	let ndc_x = 2.0 * screen_x / width as f32 - 1.0;
+	let ndc_y = 1.0 - 2.0 * screen_y / height as f32;
+	// Clip space to eye space to world space
+	let inverse = (view * perspective).inverse()
+		.expect("View/perspective matrix is not invertible");
+	let unproject = |ndc_z: f32| {
+		let p = Vec4::from([ndc_x, ndc_y, ndc_z, 1.0]) * inverse;
+		Vec3::from(p) / p[3]
+	};
+	// The far plane can be far enough away to lose precision, so take the
+	// direction from the near plane to the middle of the depth range instead.
+	let near = unproject(-1.0);
+	let mid = unproject(0.0);
+	Ray {
+		origin: near,
+		direction: (mid - near).normalize(),
+	}
+}
+
+/// A view frustum, as six planes facing inwards.
+///
+/// Each plane is `[a, b, c, d]` such that points with `ax + by + cz + d >= 0`
+/// are on the inside. Planes are normalized, so this is the distance from the
+/// plane.
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+	/// The left, right, bottom, top, near and far planes, in that order.
+	pub planes: [Vec4<f32>; 6],
+}
+
+impl Frustum {
+	/// Extract the frustum from a combined transformation matrix.
+	///
+	/// This is the Gribb-Hartmann method. With a view/perspective matrix, the
+	/// frustum is in world space; with a model/view/perspective matrix, it's
+	/// in that model's space.
+	pub fn from_view_projection(mvp: Mat4<f32>) -> Frustum {
+		// Points transform as row vectors, so clip space components are the
+		// columns of the matrix.
+		let col = |j: usize| Vec4::from([mvp[0][j], mvp[1][j], mvp[2][j], mvp[3][j]]);
+		let (x, y, z, w) = (col(0), col(1), col(2), col(3));
+		let normalize = |p: Vec4<f32>| p / Vec3::from(p).length();
+		Frustum {
+			planes: [
+				normalize(w + x),
+				normalize(w - x),
+				normalize(w + y),
+				normalize(w - y),
+				normalize(w + z),
+				normalize(w - z),
+			],
+		}
+	}
+
+	/// Check whether any part of an axis-aligned bounding box may be inside
+	/// this frustum.
+	///
+	/// This is conservative: boxes touching the frustum are inside, and some
+	/// boxes near the frustum's corners may be reported inside when they
+	/// aren't.
+	pub fn contains_aabb(self, aabb: Aabb<f32>) -> bool {
+		self.planes.iter().all(|plane| {
+			// The corner of the box furthest along the plane's normal
+			let mut corner = aabb.min;
+			for i in 0..3 {
+				if plane[i] >= 0.0 {
+					corner[i] = aabb.max[i];
+				}
+			}
+			Vec4::from([corner[0], corner[1], corner[2], 1.0]).dot(*plane) >= 0.0
+		})
+	}
+}
+
+/// Convert a mouse position to a world space ray origin and normalized
+/// direction.
+///
+/// `mouse` is as reported by window cursor events, in pixels from the top left
+/// of a window of `window_size`. See `screen_to_world_ray`.
+pub fn screen_to_ray(mouse: (f64, f64),
+		window_size: (u32, u32),
+		view: Mat4<f32>,
+		perspective: Mat4<f32>) -> (Vec3<f32>, Vec3<f32>) {
+	let ray = screen_to_world_ray(mouse.0 as f32, mouse.1 as f32,
+			window_size.0, window_size.1, view, perspective);
+	(ray.origin, ray.direction)
+}
+
+/// Compute the world-space corners of the frustum of a view/perspective
+/// matrix.
+///
+/// Corners are ordered as in `Aabb::corners`, with the near plane first, so
+/// corners which differ in only one bit of their index share an edge.
+///
+/// This will panic if `view_projection` is not invertible.
+pub fn frustum_corners(view_projection: Mat4<f32>) -> [Vec3<f32>; 8] {
+	let inverse = view_projection.inverse()
+		.expect("View/perspective matrix is not invertible");
+	let ndc = Aabb {
+		min: Vec3::from([-1.0, -1.0, -1.0]),
+		max: Vec3::from([1.0, 1.0, 1.0]),
+	}.corners();
+	let mut corners = ndc;
+	for (corner, n) in corners.iter_mut().zip(ndc.iter()) {
+		let p = Vec4::from([n[0], n[1], n[2], 1.0]) * inverse;
+		*corner = Vec3::from(p) / p[3];
+	}
+	corners
+}
+
+/// Narrowest field of view `handle_scroll` will zoom to.
+pub const MIN_FOV: f32 = ::std::f32::consts::PI / 12.0;
+/// Widest field of view `handle_scroll` will zoom to.
+pub const MAX_FOV: f32 = ::std::f32::consts::PI * 0.9;
+/// Pixels of touchpad scrolling equivalent to one line of mouse wheel
+/// scrolling.
+pub const PIXELS_PER_LINE: f32 = 120.0;
+
+/// Convert a scroll event's delta to lines, whether it's in lines (mouse
+/// wheels) or pixels (touchpads).
+pub fn scroll_lines(delta: MouseScrollDelta) -> (f32, f32) {
+	match delta {
+		MouseScrollDelta::LineDelta(x, y) => (x, y),
+		MouseScrollDelta::PixelDelta(pos) =>
+			(pos.x as f32 / PIXELS_PER_LINE, pos.y as f32 / PIXELS_PER_LINE),
+	}
+}
 
-	let zfar = 1048576.0;
-	let znear = 0.1;
+/// Handle scrolling, zooming by narrowing or widening the field of view
+/// `fov` by `sensitivity` radians per line scrolled.
+///
+/// Scrolling up (positive `delta`) zooms in. The field of view is clamped to
+/// between `MIN_FOV` and `MAX_FOV`.
+pub fn handle_scroll(fov: &mut f32, delta: f32, sensitivity: f32) {
+	*fov = (*fov - delta * sensitivity).max(MIN_FOV).min(MAX_FOV);
+}
 
-	let f = 1.0 / (fov / 2.0).tan();
+/// Handle a mouse button press or release, picking the object under the
+/// crosshair in the middle of the screen.
+///
+/// For now, this just logs which object, by index into `objects`, was
+/// clicked, and returns it.
+pub fn handle_mouse_button(button: u32,
+		state: ElementState,
+		camera: &Camera,
+		objects: &[ModelInstance],
+		perspective: Mat4<f32>) -> Option<usize> {
+	if state != ElementState::Pressed {
+		return None;
+	}
+	// The mouse is captured, so we pick from the center of the screen
+	let view = view_matrix(camera.loc, camera.dir, Vec3::from([0.0, 1.0, 0.0]));
+	let ray = screen_to_world_ray(0.5, 0.5, 1, 1, view, perspective);
+	let picked = objects.iter()
+		.enumerate()
+		.filter_map(|(i, object)| ray.intersect_aabb(&object.bounds()).map(|t| (i, t)))
+		.fold(None, |nearest: Option<(usize, f32)>, (i, t)| match nearest {
+			Some((_, nearest_t)) if nearest_t <= t => nearest,
+			_ => Some((i, t)),
+		})
+		.map(|(i, _)| i);
+	match picked {
+		Some(i) => info!("Mouse button {} clicked on object {}", button, i),
+		None => info!("Mouse button {} clicked on nothing", button),
+	}
+	picked
+}
 
-	Mat4::from([
-		[f * aspect_ratio, 0.0, 0.0,                            0.0],
-		[0.0             , f,   0.0,                            0.0],
-		[0.0             , 0.0, (zfar+znear)/(zfar-znear),      1.0],
-		[0.0             , 0.0, -(2.0*zfar*znear)/(zfar-znear), 0.0],
-	])
+/// Capture or release the mouse. While captured, the cursor is hidden and
+/// confined to the window.
+///
+/// The mouse should be captured only while the window has focus.
+pub fn set_mouse_capture(window: &Window, capture: bool) -> Result<()> {
+	window.hide_cursor(capture);
+	window.grab_cursor(capture)
+		.map_err(|e| Error::from(format!("Could not grab cursor: {}", e)))
 }
 
 /// Handle mouse movement.
 ///
 /// This translates mouse x/y movement into a change of the direction of the
-/// given `Camera`, and keeps the mouse captured within the window.
+/// given `Camera`, scaled by its `sensitivity`. See `set_mouse_capture` to
+/// keep the mouse in the window.
 ///
 /// Very large mouse movements (typically due to gaining focus with the cursor
 /// in a different location than last seen) will be ignored.
-///
-/// TODO: The mouse capture and focus management should be handled elsewhere.
-pub fn handle_mouse_move(window: &Window, camera: &mut Camera, x: f64, y: f64) -> Result<()> {
-
-	// Capture the mouse
-	let (w, h): (u32, u32) = try!{
-		window.get_inner_size()
-			.map(|s| s.into())
-			.ok_or(Error::from("Could not get window size"))
-	};
-	try!{ window.set_cursor_position((w as i32/2, h as i32/2).into())
-			.map_err(|_| { Error::from("Could not set cursor position") } ) };
+pub fn handle_mouse_move(camera: &mut Camera, x: f64, y: f64) {
+	if let Some((yaw, pitch)) = mouse_look_delta(x, y, camera.sensitivity) {
+		camera.rotate(yaw, pitch);
+	}
+}
 
+/// Translate mouse x/y movement into a yaw and pitch, in radians, turning
+/// `sensitivity` radians per unit of movement.
+///
+/// Very large mouse movements are ignored, and return `None`; see
+/// `handle_mouse_move`.
+pub fn mouse_look_delta(x: f64, y: f64, sensitivity: f32) -> Option<(f32, f32)> {
 	if x.abs() > 200.0 || y.abs() > 200.0 {
 		info!("Skipping camera move due to large delta: {}, {}", x, y);
-		return Ok(());
+		return None;
 	}
 
-	// Turn dx into a rotation on the xz plane
-	let dh = x as f32 * -0.005;
-	camera.dir[0] = camera.dir[0] * dh.cos() - camera.dir[2] * dh.sin();
-	camera.dir[2] = camera.dir[0] * dh.sin() + camera.dir[2] * dh.cos();
-	// Accumulated error will lead to movement glitches if we don't renormalize this.
-	let xz_norm = f32::hypot(camera.dir[0], camera.dir[2]);
-	camera.dir[0] /= xz_norm;
-	camera.dir[2] /= xz_norm;
+	// Turn dx into a rotation on the xz plane, and dy into a rotation on the
+	// plane determined by dir and [0,1,0]
+	Some((x as f32 * -sensitivity, y as f32 * -sensitivity))
+}
 
-	// Turn dy into a rotation on the xy plane
-	// (not really the xy plane; it's the plane determined by x and [0,1,0])
-	// Clamp dir_y + dy
-	// (otherwise the camera will flip if you cross zenith or nadir, which is super confusing)
-	//FIXME: This more-or-less works, but is probably^Wdefinitely wrong.
-	camera.dir[1] += y as f32 * -0.005;
 
-	Ok(())
-}
+#[cfg(test)]
+mod tests {
+	use glium::glutin::MouseScrollDelta;
+	use glium::glutin::dpi::LogicalPosition;
+	use linear_algebra::{Aabb, Mat4, Vec3, Vec4};
+	use std::f32::consts::PI;
+	use super::{handle_scroll, MAX_FOV, MIN_FOV, scroll_lines};
+	use super::{Camera, Frustum, frustum_corners, MIN_ORBIT_RADIUS, OrbitCamera, view_matrix, MAX_PITCH, orthographic_matrix, perspective_matrix_full};
+	use super::{boom_position, orthographic_2d, perspective_matrix, screen_to_ray};
+	use super::{direction, mouse_look_delta, DEFAULT_MOUSE_SENSITIVITY};
+	use super::handle_mouse_button;
+	use glium::glutin::ElementState;
+	use super::screen_to_world_ray;
+
+	/// Project an eye-space point through `m` and return its NDC.
+	fn ndc(m: Mat4<f32>, p: [f32; 3]) -> [f32; 3] {
+		let p = [p[0], p[1], p[2], 1.0];
+		let clip = |j: usize| (0..4).map(|i| p[i] * m[i][j]).sum::<f32>();
+		[clip(0) / clip(3), clip(1) / clip(3), clip(2) / clip(3)]
+	}
+
+	/// Project an eye-space point through `m` and return its NDC Z.
+	fn ndc_z(m: Mat4<f32>, p: [f32; 3]) -> f32 {
+		ndc(m, p)[2]
+	}
+
+	#[test]
+	fn test_perspective_clip_planes() {
+		let m = perspective_matrix_full(800, 600, PI / 2.0, 0.5, 100.0);
+		let near = ndc_z(m, [0.0, 0.0, 0.5]);
+		let far = ndc_z(m, [0.0, 0.0, 100.0]);
+		assert!((near + 1.0).abs() < 1e-4, "near: {}", near);
+		assert!((far - 1.0).abs() < 1e-4, "far: {}", far);
+	}
+
+	#[test]
+	fn test_camera_full_rotation() {
+		let start = Vec3::from([0.6, 0.2, 0.8]);
+		let mut camera = Camera {
+			loc: Vec3::from([0.0, 0.0, 0.0]),
+			dir: start,
+			sensitivity: DEFAULT_MOUSE_SENSITIVITY,
+		};
+		for _ in 0..100 {
+			camera.rotate(2.0 * PI / 100.0, 0.0);
+		}
+		for i in 0..3 {
+			assert!((camera.dir[i] - start[i]).abs() < 1e-4,
+				"expected {:?}, got {:?}", start, camera.dir);
+		}
+	}
+
+	#[test]
+	fn test_camera_pitch_clamp() {
+		let mut camera = Camera {
+			loc: Vec3::from([0.0, 0.0, 0.0]),
+			dir: Vec3::from([1.0, 0.0, 0.0]),
+			sensitivity: DEFAULT_MOUSE_SENSITIVITY,
+		};
+		camera.rotate(0.0, PI);
+		assert!((camera.dir[1].atan() - MAX_PITCH).abs() < 1e-4);
+		camera.rotate(0.0, -2.0 * PI);
+		assert!((camera.dir[1].atan() + MAX_PITCH).abs() < 1e-4);
+		// Clamped, not wrapped over the nadir
+		assert!(camera.dir[0] > 0.0, "{:?}", camera.dir);
+		assert!((camera.pitch() + MAX_PITCH).abs() < 1e-4);
+		camera.rotate(0.0, 0.5);
+		assert!((camera.pitch() - (0.5 - MAX_PITCH)).abs() < 1e-4);
+	}
+
+	#[test]
+	fn test_direction() {
+		let dir = direction(0.0, 0.0);
+		assert!((dir - Vec3::from([1.0, 0.0, 0.0])).length() < 1e-6, "{:?}", dir);
+		let dir = direction(PI / 2.0, PI / 4.0);
+		assert!((dir - Vec3::from([0.0, 1.0, 1.0])).length() < 1e-6, "{:?}", dir);
+
+		for &(yaw, pitch) in [(0.3, -0.4), (-2.5, 1.2), (3.0, 0.0f32)].iter() {
+			let camera = Camera {
+				loc: Vec3::from([0.0, 0.0, 0.0]),
+				dir: direction(yaw, pitch),
+				sensitivity: DEFAULT_MOUSE_SENSITIVITY,
+			};
+			assert!((camera.yaw() - yaw).abs() < 1e-5, "{} became {}", yaw, camera.yaw());
+			assert!((camera.pitch() - pitch).abs() < 1e-5, "{} became {}", pitch, camera.pitch());
+			assert!((f32::hypot(camera.dir[0], camera.dir[2]) - 1.0).abs() < 1e-5);
+		}
+	}
+
+	#[test]
+	fn test_mouse_look_delta() {
+		assert_eq!(Some((-0.5, 1.0)), mouse_look_delta(50.0, -100.0, 0.01));
+		assert_eq!(None, mouse_look_delta(500.0, 0.0, 0.01));
+	}
+
+	#[test]
+	fn test_orthographic() {
+		let m = orthographic_matrix(-20.0, 620.0, 10.0, 490.0, -1.0, 1.0);
+		let actual = ndc(m, [-20.0, 490.0, 0.0]);
+		let expected = [-1.0, 1.0, 0.0];
+		for i in 0..3 {
+			assert!((actual[i] - expected[i]).abs() < 1e-5,
+				"expected {:?}, got {:?}", expected, actual);
+		}
+	}
 
+	#[test]
+	fn test_screen_to_world_ray() {
+		let identity = Mat4::from([
+			[1.0, 0.0, 0.0, 0.0],
+			[0.0, 1.0, 0.0, 0.0],
+			[0.0, 0.0, 1.0, 0.0],
+			[0.0, 0.0, 0.0, 1.0],
+		]);
+
+		// Right-handed cameras look down -Z
+		let perspective = Mat4::perspective_rh(PI / 2.0, 4.0 / 3.0, 0.1, 1000.0);
+		let ray = screen_to_world_ray(400.0, 300.0, 800, 600, identity, perspective);
+		let expected = Vec3::from([0.0, 0.0, -1.0]);
+		assert!((ray.direction - expected).length() < 1e-4,
+			"expected {:?}, got {:?}", expected, ray.direction);
+		assert!((ray.origin - Vec3::from([0.0, 0.0, -0.1])).length() < 1e-4,
+			"origin: {:?}", ray.origin);
+
+		// This crate's default left-handed camera looks down +Z
+		let perspective = perspective_matrix(800, 600, PI / 2.0);
+		let ray = screen_to_world_ray(400.0, 300.0, 800, 600, identity, perspective);
+		let expected = Vec3::from([0.0, 0.0, 1.0]);
+		assert!((ray.direction - expected).length() < 1e-4,
+			"expected {:?}, got {:?}", expected, ray.direction);
+
+		// The top right corner is up and to the right at the field of view
+		let ray = screen_to_world_ray(800.0, 0.0, 800, 600, identity, perspective);
+		let expected = Vec3::from([4.0 / 3.0, 1.0, 1.0]).normalize();
+		assert!((ray.direction - expected).length() < 1e-4,
+			"expected {:?}, got {:?}", expected, ray.direction);
+	}
+
+	#[test]
+	fn test_orthographic_2d() {
+		let m = orthographic_2d(640, 480);
+		// Orthographic projections leave clip space W at 1
+		let clip = |p: [f32; 3]| {
+			let p = [p[0], p[1], p[2], 1.0];
+			let mut r = [0.0f32; 4];
+			for j in 0..4 {
+				r[j] = (0..4).map(|i| p[i] * m[i][j]).sum::<f32>();
+			}
+			r
+		};
+		let cases = [
+			([0.0, 0.0, 0.0], [-1.0, 1.0, 0.0, 1.0]),
+			([640.0, 480.0, 0.0], [1.0, -1.0, 0.0, 1.0]),
+			([320.0, 240.0, 0.5], [0.0, 0.0, 0.5, 1.0]),
+			([160.0, 360.0, -1.0], [-0.5, -0.5, -1.0, 1.0]),
+		];
+		for &(point, expected) in cases.iter() {
+			let actual = clip(point);
+			for i in 0..4 {
+				assert!((actual[i] - expected[i]).abs() < 1e-5,
+					"{:?}: expected {:?}, got {:?}", point, expected, actual);
+			}
+		}
+	}
+
+	#[test]
+	fn test_boom_position() {
+		let target = Vec3::from([1.0, 2.0, 3.0]);
+		// Looking down +X from above and behind
+		let loc = boom_position(target, Vec3::from([2.0, 0.0, 0.0]), 4.0, 1.0);
+		let expected = [-3.0, 3.0, 3.0];
+		for i in 0..3 {
+			assert!((loc[i] - expected[i]).abs() < 1e-5, "{:?}", loc);
+		}
+		// Orbiting keeps the pivot at the same distance
+		let dir = Vec3::from([0.0, -1.0, 1.0]);
+		let pivot = target + Vec3::from([0.0, 1.0, 0.0]);
+		let loc = boom_position(target, dir, 4.0, 1.0);
+		assert!(((loc - pivot).length() - 4.0).abs() < 1e-5);
+		assert!(loc[1] > pivot[1]);
+	}
+
+	#[test]
+	fn test_frustum_planes() {
+		let frustum = Frustum::from_view_projection(
+				orthographic_matrix(-1.0, 1.0, -1.0, 1.0, 0.0, 10.0));
+		let expected = [
+			[1.0, 0.0, 0.0, 1.0],
+			[-1.0, 0.0, 0.0, 1.0],
+			[0.0, 1.0, 0.0, 1.0],
+			[0.0, -1.0, 0.0, 1.0],
+			[0.0, 0.0, 1.0, 0.0],
+			[0.0, 0.0, -1.0, 10.0],
+		];
+		for (plane, expected) in frustum.planes.iter().zip(expected.iter()) {
+			for i in 0..4 {
+				assert!((plane[i] - expected[i]).abs() < 1e-5,
+					"expected {:?}, got {:?}", expected, plane);
+			}
+		}
+
+		// Perspective planes are normalized, so they give distances
+		let frustum = Frustum::from_view_projection(
+				perspective_matrix_full(600, 600, PI / 2.0, 0.5, 100.0));
+		let point = Vec4::from([0.0, 0.0, 3.0, 1.0]);
+		let near = point.dot(frustum.planes[4]);
+		let far = point.dot(frustum.planes[5]);
+		let left = point.dot(frustum.planes[0]);
+		assert!((near - 2.5).abs() < 1e-3, "near: {}", near);
+		assert!((far - 97.0).abs() < 1e-2, "far: {}", far);
+		assert!((left - 3.0 / 2f32.sqrt()).abs() < 1e-4, "left: {}", left);
+	}
+
+	#[test]
+	fn test_frustum_contains_aabb() {
+		let aabb = |min: [f32; 3], max: [f32; 3]| Aabb {
+			min: Vec3::from(min),
+			max: Vec3::from(max),
+		};
+		let frustum = Frustum::from_view_projection(
+				orthographic_matrix(-1.0, 1.0, -1.0, 1.0, 0.0, 10.0));
+		// Touching the right plane
+		assert!(frustum.contains_aabb(aabb([1.0, 0.0, 1.0], [2.0, 0.5, 2.0])));
+		// Straddling the near plane
+		assert!(frustum.contains_aabb(aabb([-0.5, -0.5, -1.0], [0.5, 0.5, 0.0])));
+		// Just past the right plane
+		assert!(!frustum.contains_aabb(aabb([1.1, 0.0, 1.0], [2.0, 0.5, 2.0])));
+
+		let frustum = Frustum::from_view_projection(
+				perspective_matrix_full(800, 600, PI / 2.0, 0.5, 100.0));
+		assert!(frustum.contains_aabb(aabb([-1.0, -1.0, 5.0], [1.0, 1.0, 6.0])));
+		// Behind the near plane
+		assert!(!frustum.contains_aabb(aabb([-1.0, -1.0, -6.0], [1.0, 1.0, -5.0])));
+		// Beyond the far plane
+		assert!(!frustum.contains_aabb(aabb([-1.0, -1.0, 101.0], [1.0, 1.0, 102.0])));
+		// Off to the side
+		assert!(!frustum.contains_aabb(aabb([20.0, -1.0, 5.0], [21.0, 1.0, 6.0])));
+	}
+
+	#[test]
+	fn test_frustum_corners() {
+		// A square 90 degree frustum from 1 to 10 has corners at X, Y = +/-Z
+		let corners = frustum_corners(perspective_matrix_full(600, 600, PI / 2.0, 1.0, 10.0));
+		let expected = [
+			[-1.0, -1.0, 1.0], [1.0, -1.0, 1.0], [-1.0, 1.0, 1.0], [1.0, 1.0, 1.0],
+			[-10.0, -10.0, 10.0], [10.0, -10.0, 10.0], [-10.0, 10.0, 10.0], [10.0, 10.0, 10.0]];
+		for (actual, expected) in corners.iter().zip(expected.iter()) {
+			for i in 0..3 {
+				assert!((actual[i] - expected[i]).abs() < 1e-3,
+					"expected {:?}, got {:?}", expected, actual);
+			}
+		}
+
+		// Corners map back to the NDC cube
+		let m = orthographic_matrix(-2.0, 4.0, -1.0, 3.0, 0.5, 8.0);
+		let corners = frustum_corners(m);
+		assert!((corners[0] - Vec3::from([-2.0, -1.0, 0.5])).length() < 1e-4);
+		assert!((corners[7] - Vec3::from([4.0, 3.0, 8.0])).length() < 1e-4);
+		for (idx, corner) in corners.iter().enumerate() {
+			let p = ndc(m, [corner[0], corner[1], corner[2]]);
+			for i in 0..3 {
+				let expected = if idx & (1 << i) == 0 { -1.0 } else { 1.0 };
+				assert!((p[i] - expected).abs() < 1e-5, "{}: {:?}", idx, p);
+			}
+		}
+	}
+
+	#[test]
+	fn test_screen_to_ray() {
+		let identity = Mat4::from([
+				[1.0, 0.0, 0.0, 0.0],
+				[0.0, 1.0, 0.0, 0.0],
+				[0.0, 0.0, 1.0, 0.0],
+				[0.0, 0.0, 0.0, 1.0]]);
+		let (origin, direction) = screen_to_ray((400.0, 300.0), (800, 600),
+				identity, perspective_matrix(800, 600, PI / 2.0));
+		// The camera looks down +Z
+		assert!((direction - Vec3::from([0.0, 0.0, 1.0])).length() < 1e-5, "{:?}", direction);
+		assert!(origin[0].abs() < 1e-5 && origin[1].abs() < 1e-5, "{:?}", origin);
+	}
+
+	#[test]
+	fn test_camera_look_at() {
+		let eye = Vec3::from([1.0, 2.0, 3.0f32]);
+		let target = Vec3::from([-4.0, 0.0, 7.0]);
+		let up = Vec3::from([0.0, 1.0, 0.0]);
+		let camera = Camera::look_at(eye, target, up);
+		let actual = view_matrix(camera.loc, camera.dir, up);
+		let expected = view_matrix(eye, target - eye, up);
+		for i in 0..4 {
+			for j in 0..4 {
+				assert!((actual[i][j] - expected[i][j]).abs() < 1e-5,
+					"expected {:?}, got {:?}", expected, actual);
+			}
+		}
+		// Rotating a look_at camera keeps its pitch
+		let mut rotated = Camera::look_at(eye, target, up);
+		rotated.rotate(1.0, 0.0);
+		let pitch = |dir: Vec3<f32>| dir[1].atan2(f32::hypot(dir[0], dir[2]));
+		assert!((pitch(rotated.dir) - pitch(camera.dir)).abs() < 1e-5);
+	}
+
+	#[test]
+	fn test_camera_f64() {
+		// Near the origin, f64 cameras give the same matrices as f32 ones
+		let mut camera = Camera::look_at(Vec3::from([1.0, 2.0, 3.0f64]),
+			Vec3::from([-4.0, 0.0, 7.0]), Vec3::from([0.0, 1.0, 0.0]));
+		camera.rotate(0.5, -0.2);
+		let up = Vec3::from([0.0, 1.0, 0.0]);
+		let loc = Vec3::from([1.0, 2.0, 3.0f32]);
+		let dir = Vec3::from([camera.dir[0] as f32, camera.dir[1] as f32, camera.dir[2] as f32]);
+		let view = view_matrix(camera.loc, camera.dir, up).to_f32();
+		let expected = view_matrix(loc, dir, Vec3::from([0.0, 1.0, 0.0]));
+		let perspective = perspective_matrix(800, 600, PI as f64 / 3.0).to_f32();
+		let expected_perspective = perspective_matrix(800, 600, PI / 3.0);
+		for i in 0..4 {
+			for j in 0..4 {
+				assert!((view[i][j] - expected[i][j]).abs() < 1e-5,
+					"expected {:?}, got {:?}", expected, view);
+				assert!((perspective[i][j] - expected_perspective[i][j]).abs() < 1e-5,
+					"expected {:?}, got {:?}", expected_perspective, perspective);
+			}
+		}
+
+		// Far from the origin, they can still see small offsets
+		let far = Camera::look_at(Vec3::from([1.0e7, 0.0, 1.0e7f64]),
+			Vec3::from([1.0e7 + 1.0, 0.0, 1.0e7]), up);
+		let p = Vec4::from([1.0e7 + 0.25, 0.0, 1.0e7 + 0.125, 1.0f64])
+			* view_matrix(far.loc, far.dir, up);
+		assert!((p[0] + 0.125).abs() < 1e-9 && (p[2] - 0.25).abs() < 1e-9, "{:?}", p);
+	}
+
+	#[test]
+	fn test_camera_smooth_follow() {
+		let target = Camera {
+			loc: Vec3::from([10.0, 5.0, -3.0f32]),
+			dir: Vec3::from([0.0, 0.5, -1.0]),
+			sensitivity: DEFAULT_MOUSE_SENSITIVITY,
+		};
+		let mut camera = Camera {
+			loc: Vec3::from([0.0, 0.0, 0.0]),
+			dir: Vec3::from([1.0, 0.0, 0.0]),
+			sensitivity: DEFAULT_MOUSE_SENSITIVITY,
+		};
+		camera.smooth_follow(&target, 0.5);
+		assert!((camera.loc - Vec3::from([5.0, 2.5, -1.5])).length() < 1e-5);
+		assert!((camera.dir.length() - 1.0).abs() < 1e-5);
+		for _ in 0..50 {
+			camera.smooth_follow(&target, 0.5);
+		}
+		assert!((camera.loc - target.loc).length() < 1e-4, "{:?}", camera.loc);
+		assert!((camera.dir - target.dir.normalize()).length() < 1e-4, "{:?}", camera.dir);
+	}
+
+	#[test]
+	fn test_orbit_camera() {
+		let orbit = OrbitCamera {
+			center: Vec3::from([1.0, 2.0, 3.0]),
+			radius: 5.0,
+			yaw: 0.3,
+			pitch: -0.4,
+		};
+		for orbit in [orbit, orbit.rotate(2.0, 0.5), orbit.rotate(-1.0, 10.0)].iter() {
+			let camera = orbit.to_camera();
+			// The camera looks at the center from the given distance
+			let to_center = orbit.center - camera.loc;
+			assert!((to_center.length() - orbit.radius).abs() < 1e-4);
+			assert!((to_center.normalize() - camera.dir.normalize()).length() < 1e-5);
+			assert!(orbit.pitch.abs() <= MAX_PITCH);
+		}
+		assert_eq!(orbit.rotate(0.0, 10.0).pitch, MAX_PITCH);
+		assert_eq!(orbit.zoom(2.0).radius, 3.0);
+		assert_eq!(orbit.zoom(-2.0).radius, 7.0);
+		assert_eq!(orbit.zoom(10.0).radius, MIN_ORBIT_RADIUS);
+	}
+
+	#[test]
+	fn test_handle_scroll() {
+		let mut fov = PI / 2.0;
+		handle_scroll(&mut fov, 1.0, 0.1);
+		assert!((fov - (PI / 2.0 - 0.1)).abs() < 1e-6);
+		handle_scroll(&mut fov, -2.0, 0.1);
+		assert!((fov - (PI / 2.0 + 0.1)).abs() < 1e-6);
+		handle_scroll(&mut fov, 100.0, 0.1);
+		assert_eq!(fov, MIN_FOV);
+		handle_scroll(&mut fov, -100.0, 0.1);
+		assert_eq!(fov, MAX_FOV);
+
+		assert_eq!(scroll_lines(MouseScrollDelta::LineDelta(0.0, -2.0)), (0.0, -2.0));
+		assert_eq!(scroll_lines(MouseScrollDelta::PixelDelta(LogicalPosition::new(60.0, 240.0))),
+			(0.5, 2.0));
+	}
+
+	#[test]
+	fn test_handle_mouse_button() {
+		let camera = Camera {
+			loc: Vec3::from([0.0, 0.0, 0.0]),
+			dir: Vec3::from([1.0, 0.0, 0.0]),
+			sensitivity: DEFAULT_MOUSE_SENSITIVITY,
+		};
+		let perspective = perspective_matrix_full(800, 600, PI / 2.0, 0.1, 100.0);
+		for button in 0..32 {
+			for state in [ElementState::Pressed, ElementState::Released].iter() {
+				assert_eq!(None, handle_mouse_button(button, *state, &camera, &[], perspective));
+			}
+		}
+	}
+}
@@ -0,0 +1,186 @@
+//! Module to handle keyboard input.
+//!
+//! Right now, this is just configurable bindings for character movement.
+
+use MovementState;
+use errors::*;
+use glium::glutin::{ElementState, VirtualKeyCode};
+use serde::de::{self, Deserialize, Deserializer};
+use std::io::BufRead;
+use toml;
+
+/// Keys which can be bound, for parsing key names in configuration.
+const BINDABLE_KEYS: [VirtualKeyCode; 56] = [
+	VirtualKeyCode::A, VirtualKeyCode::B, VirtualKeyCode::C, VirtualKeyCode::D,
+	VirtualKeyCode::E, VirtualKeyCode::F, VirtualKeyCode::G, VirtualKeyCode::H,
+	VirtualKeyCode::I, VirtualKeyCode::J, VirtualKeyCode::K, VirtualKeyCode::L,
+	VirtualKeyCode::M, VirtualKeyCode::N, VirtualKeyCode::O, VirtualKeyCode::P,
+	VirtualKeyCode::Q, VirtualKeyCode::R, VirtualKeyCode::S, VirtualKeyCode::T,
+	VirtualKeyCode::U, VirtualKeyCode::V, VirtualKeyCode::W, VirtualKeyCode::X,
+	VirtualKeyCode::Y, VirtualKeyCode::Z,
+	VirtualKeyCode::Key0, VirtualKeyCode::Key1, VirtualKeyCode::Key2,
+	VirtualKeyCode::Key3, VirtualKeyCode::Key4, VirtualKeyCode::Key5,
+	VirtualKeyCode::Key6, VirtualKeyCode::Key7, VirtualKeyCode::Key8,
+	VirtualKeyCode::Key9,
+	VirtualKeyCode::Up, VirtualKeyCode::Down, VirtualKeyCode::Left,
+	VirtualKeyCode::Right,
+	VirtualKeyCode::Space, VirtualKeyCode::Return, VirtualKeyCode::Back,
+	VirtualKeyCode::Escape, VirtualKeyCode::Tab,
+	VirtualKeyCode::LShift, VirtualKeyCode::RShift, VirtualKeyCode::LControl,
+	VirtualKeyCode::RControl, VirtualKeyCode::LAlt, VirtualKeyCode::RAlt,
+	VirtualKeyCode::Comma, VirtualKeyCode::Period, VirtualKeyCode::Semicolon,
+	VirtualKeyCode::Slash, VirtualKeyCode::Apostrophe,
+];
+
+/// Keys bound to character movement and quitting.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyBindings {
+	/// Move forwards.
+	#[serde(deserialize_with = "deserialize_key")]
+	pub forward: VirtualKeyCode,
+	/// Move backwards.
+	#[serde(deserialize_with = "deserialize_key")]
+	pub backward: VirtualKeyCode,
+	/// Move left.
+	#[serde(deserialize_with = "deserialize_key")]
+	pub left: VirtualKeyCode,
+	/// Move right.
+	#[serde(deserialize_with = "deserialize_key")]
+	pub right: VirtualKeyCode,
+	/// Jump.
+	#[serde(deserialize_with = "deserialize_key")]
+	pub jump: VirtualKeyCode,
+	/// Exit. Esc always exits, too.
+	#[serde(deserialize_with = "deserialize_key")]
+	pub quit: VirtualKeyCode,
+}
+
+/// A config file, for reading just its `[keys]` section.
+#[derive(Deserialize)]
+struct KeysSection {
+	#[serde(default)]
+	keys: KeyBindings,
+}
+
+impl Default for KeyBindings {
+	fn default() -> KeyBindings {
+		KeyBindings {
+			forward: VirtualKeyCode::W,
+			backward: VirtualKeyCode::S,
+			left: VirtualKeyCode::A,
+			right: VirtualKeyCode::D,
+			jump: VirtualKeyCode::Space,
+			quit: VirtualKeyCode::Q,
+		}
+	}
+}
+
+impl KeyBindings {
+
+	/// Load key bindings from the `[keys]` section of a TOML file, like:
+	///
+	/// ```toml
+	/// [keys]
+	/// forward = "Up"
+	/// jump = "LControl"
+	/// ```
+	///
+	/// Key names are `VirtualKeyCode` variant names. Bindings which aren't
+	/// given keep their defaults, and other sections are ignored.
+	pub fn from_toml(read: &mut BufRead) -> Result<KeyBindings> {
+		let mut text = String::new();
+		try!{ read.read_to_string(&mut text).chain_err(|| "Could not read key bindings") };
+		let section: KeysSection = try!{ toml::from_str(&text)
+				.chain_err(|| "Invalid key bindings") };
+		Ok(section.keys)
+	}
+
+}
+
+/// Deserialize a key from its `VirtualKeyCode` variant name.
+fn deserialize_key<'de, D>(deserializer: D) -> ::std::result::Result<VirtualKeyCode, D::Error>
+		where D: Deserializer<'de> {
+	let name = try!{ String::deserialize(deserializer) };
+	parse_key(&name).ok_or_else(|| de::Error::custom(format!("unknown key \"{}\"", name)))
+}
+
+/// Get the key with the given `VirtualKeyCode` variant name.
+fn parse_key(name: &str) -> Option<VirtualKeyCode> {
+	BINDABLE_KEYS.iter()
+		.find(|keycode| format!("{:?}", keycode) == name)
+		.cloned()
+}
+
+/// Update the character's movement state from a key press or release. Keys
+/// which aren't bound to movement are ignored.
+pub fn update_movement_from_key(bindings: &KeyBindings,
+		movement: &mut MovementState,
+		keycode: VirtualKeyCode,
+		state: ElementState) {
+	let pressed = state == ElementState::Pressed;
+	if keycode == bindings.forward {
+		movement.forward = pressed;
+	}
+	if keycode == bindings.backward {
+		movement.backward = pressed;
+	}
+	if keycode == bindings.left {
+		movement.left = pressed;
+	}
+	if keycode == bindings.right {
+		movement.right = pressed;
+	}
+	if keycode == bindings.jump {
+		movement.jumping = pressed;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use MovementState;
+	use glium::glutin::{ElementState, VirtualKeyCode};
+	use super::{KeyBindings, update_movement_from_key};
+
+	#[test]
+	fn test_from_toml() {
+		let config = b"# Arrow keys\n\
+			[window]\n\
+			forward = \"Nonsense\"\n\
+			\n\
+			[keys]\n\
+			forward = \"Up\"\n\
+			backward = 'Down'\n\
+			jump = \"LControl\"\n";
+		let bindings = KeyBindings::from_toml(&mut &config[..]).unwrap();
+		assert_eq!(KeyBindings {
+			forward: VirtualKeyCode::Up,
+			backward: VirtualKeyCode::Down,
+			jump: VirtualKeyCode::LControl,
+			.. KeyBindings::default()
+		}, bindings);
+
+		assert!(KeyBindings::from_toml(&mut &b"[keys]\nforward = \"Nonsense\"\n"[..]).is_err());
+		assert!(KeyBindings::from_toml(&mut &b"[keys]\nfly = \"F\"\n"[..]).is_err());
+	}
+
+	#[test]
+	fn test_update_movement_from_key() {
+		let bindings = KeyBindings::default();
+		let mut movement = MovementState {
+			forward: false,
+			backward: false,
+			left: false,
+			right: false,
+			jumping: false,
+		};
+		update_movement_from_key(&bindings, &mut movement, VirtualKeyCode::W, ElementState::Pressed);
+		update_movement_from_key(&bindings, &mut movement, VirtualKeyCode::Space, ElementState::Pressed);
+		assert!(movement.forward && movement.jumping);
+		assert!(!movement.backward && !movement.left && !movement.right);
+
+		update_movement_from_key(&bindings, &mut movement, VirtualKeyCode::Space, ElementState::Released);
+		update_movement_from_key(&bindings, &mut movement, VirtualKeyCode::X, ElementState::Pressed);
+		assert!(movement.forward && !movement.jumping);
+	}
+}
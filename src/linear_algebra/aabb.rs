@@ -0,0 +1,59 @@
+use super::{Mat4, Vec3};
+
+/// An axis-aligned bounding box.
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub struct Aabb<T: Copy> {
+	/// The corner of this box with the smallest coordinates.
+	pub min: Vec3<T>,
+	/// The corner of this box with the largest coordinates.
+	pub max: Vec3<T>,
+}
+impl Aabb<f32> {
+	/// The smallest box containing all the given points.
+	///
+	/// If there are no points, this is an empty box at the origin.
+	pub fn from_points<I: IntoIterator<Item = Vec3<f32>>>(points: I) -> Aabb<f32> {
+		let mut points = points.into_iter();
+		let first = match points.next() {
+			Some(p) => p,
+			None => Vec3::from([0.0, 0.0, 0.0]),
+		};
+		points.fold(Aabb { min: first, max: first }, |mut aabb, p| {
+			for i in 0..3 {
+				aabb.min[i] = aabb.min[i].min(p[i]);
+				aabb.max[i] = aabb.max[i].max(p[i]);
+			}
+			aabb
+		})
+	}
+
+	/// The eight corners of this box.
+	pub fn corners(&self) -> [Vec3<f32>; 8] {
+		let (l, h) = (self.min, self.max);
+		[Vec3::from([l[0], l[1], l[2]]),
+			Vec3::from([h[0], l[1], l[2]]),
+			Vec3::from([l[0], h[1], l[2]]),
+			Vec3::from([h[0], h[1], l[2]]),
+			Vec3::from([l[0], l[1], h[2]]),
+			Vec3::from([h[0], l[1], h[2]]),
+			Vec3::from([l[0], h[1], h[2]]),
+			Vec3::from([h[0], h[1], h[2]])]
+	}
+
+	/// The distance from `point` to the nearest point in this box, or 0 if
+	/// `point` is inside it.
+	pub fn distance_to(&self, point: Vec3<f32>) -> f32 {
+		let mut sum = 0.0;
+		for i in 0..3 {
+			let d = (self.min[i] - point[i]).max(point[i] - self.max[i]).max(0.0);
+			sum += d * d;
+		}
+		sum.sqrt()
+	}
+
+	/// The smallest box containing this box transformed by the affine
+	/// transformation `m`, e.g. a model matrix.
+	pub fn transform(&self, m: Mat4<f32>) -> Aabb<f32> {
+		Aabb::from_points(self.corners().iter().map(|c| m.transform_point(*c)))
+	}
+}
@@ -1,9 +1,264 @@
 use std::ops::{Add, Index, IndexMut, Mul};
-use super::{Mat3, Vec4};
+use super::{Float, Mat3, Vec3, Vec4};
 
 /// A 4x4 matrix.
+///
+/// Transformation matrices in this crate are stored so that they apply to row
+/// vectors (that is, translation is in the last row), which is the same
+/// layout OpenGL expects for column-major uniforms.
 #[derive(Copy,Clone,Debug,PartialEq)]
 pub struct Mat4<T: Copy>([[T; 4]; 4]);
+
+impl Mat4<f32> {
+	/// The identity matrix.
+	pub fn identity() -> Mat4<f32> {
+		Mat4([
+			[1.0, 0.0, 0.0, 0.0],
+			[0.0, 1.0, 0.0, 0.0],
+			[0.0, 0.0, 1.0, 0.0],
+			[0.0, 0.0, 0.0, 1.0],
+		])
+	}
+
+	/// Translation by `t`.
+	pub fn translation(t: Vec3<f32>) -> Mat4<f32> {
+		Mat4([
+			[1.0,  0.0,  0.0,  0.0],
+			[0.0,  1.0,  0.0,  0.0],
+			[0.0,  0.0,  1.0,  0.0],
+			[t[0], t[1], t[2], 1.0],
+		])
+	}
+
+	/// Scaling by `s` along each axis.
+	pub fn scale(s: Vec3<f32>) -> Mat4<f32> {
+		Mat4([
+			[s[0], 0.0,  0.0,  0.0],
+			[0.0,  s[1], 0.0,  0.0],
+			[0.0,  0.0,  s[2], 0.0],
+			[0.0,  0.0,  0.0,  1.0],
+		])
+	}
+
+	/// Scaling by `s` along all axes.
+	pub fn scale_uniform(s: f32) -> Mat4<f32> {
+		Mat4::scale(Vec3::from([s, s, s]))
+	}
+
+	/// Rotation by `angle` radians about the X axis, from +Y towards +Z.
+	pub fn rotation_x(angle: f32) -> Mat4<f32> {
+		let (s, c) = angle.sin_cos();
+		Mat4([
+			[1.0, 0.0, 0.0, 0.0],
+			[0.0, c,   s,   0.0],
+			[0.0, -s,  c,   0.0],
+			[0.0, 0.0, 0.0, 1.0],
+		])
+	}
+
+	/// Rotation by `angle` radians about the Y axis, from +Z towards +X.
+	pub fn rotation_y(angle: f32) -> Mat4<f32> {
+		let (s, c) = angle.sin_cos();
+		Mat4([
+			[c,   0.0, -s,  0.0],
+			[0.0, 1.0, 0.0, 0.0],
+			[s,   0.0, c,   0.0],
+			[0.0, 0.0, 0.0, 1.0],
+		])
+	}
+
+	/// Rotation by `angle` radians about the Z axis, from +X towards +Y.
+	pub fn rotation_z(angle: f32) -> Mat4<f32> {
+		let (s, c) = angle.sin_cos();
+		Mat4([
+			[c,   s,   0.0, 0.0],
+			[-s,  c,   0.0, 0.0],
+			[0.0, 0.0, 1.0, 0.0],
+			[0.0, 0.0, 0.0, 1.0],
+		])
+	}
+
+	/// Rotation by `angle` radians about `axis`, counterclockwise looking down
+	/// the axis towards the origin.
+	pub fn rotation_axis_angle(axis: Vec3<f32>, angle: f32) -> Mat4<f32> {
+		let k = axis.normalize();
+		let (x, y, z) = (k[0], k[1], k[2]);
+		let (s, c) = angle.sin_cos();
+		let t = 1.0 - c;
+		Mat4([
+			[c + t * x * x,     t * x * y + s * z, t * x * z - s * y, 0.0],
+			[t * x * y - s * z, c + t * y * y,     t * y * z + s * x, 0.0],
+			[t * x * z + s * y, t * y * z - s * x, c + t * z * z,     0.0],
+			[0.0,               0.0,               0.0,               1.0],
+		])
+	}
+
+	/// Scale by `scale`, then rotate by `rotation`, then translate by
+	/// `translation`: the usual order for a model matrix.
+	pub fn trs(translation: Vec3<f32>, rotation: Mat4<f32>, scale: Vec3<f32>) -> Mat4<f32> {
+		Mat4::scale(scale) * rotation * Mat4::translation(translation)
+	}
+
+	/// Transform a point (that is, a vector with W = 1) by this affine
+	/// transformation.
+	pub fn transform_point(&self, p: Vec3<f32>) -> Vec3<f32> {
+		Vec3::from(Vec4::from([p[0], p[1], p[2], 1.0]) * *self)
+	}
+
+	/// Compute the inverse of this matrix, or `None` if it is singular.
+	pub fn inverse(&self) -> Option<Mat4<f32>> {
+		// Gauss-Jordan elimination with partial pivoting
+		let mut m = self.0;
+		let mut inv = [
+			[1.0, 0.0, 0.0, 0.0],
+			[0.0, 1.0, 0.0, 0.0],
+			[0.0, 0.0, 1.0, 0.0],
+			[0.0, 0.0, 0.0, 1.0f32],
+		];
+		for col in 0..4 {
+			let pivot = (col..4)
+				.max_by(|&a, &b| m[a][col].abs().partial_cmp(&m[b][col].abs()).unwrap())
+				.unwrap();
+			if m[pivot][col] == 0.0 {
+				return None;
+			}
+			m.swap(col, pivot);
+			inv.swap(col, pivot);
+			let p = m[col][col];
+			for j in 0..4 {
+				m[col][j] /= p;
+				inv[col][j] /= p;
+			}
+			for row in 0..4 {
+				if row != col {
+					let factor = m[row][col];
+					for j in 0..4 {
+						m[row][j] -= factor * m[col][j];
+						inv[row][j] -= factor * inv[col][j];
+					}
+				}
+			}
+		}
+		Some(Mat4(inv))
+	}
+
+	/// Left-handed orthographic projection.
+	///
+	/// The box bounded by `left`, `right`, `bottom`, `top` on X and Y and
+	/// `znear`, `zfar` on Z maps to the OpenGL NDC cube, with `znear` at
+	/// Z = -1 and `zfar` at Z = 1.
+	pub fn orthographic_lh(left: f32, right: f32,
+			bottom: f32, top: f32,
+			znear: f32, zfar: f32) -> Mat4<f32> {
+		let (w, h, d) = (right - left, top - bottom, zfar - znear);
+		Mat4([
+			[2.0 / w,              0.0,                  0.0,                 0.0],
+			[0.0,                  2.0 / h,              0.0,                 0.0],
+			[0.0,                  0.0,                  2.0 / d,             0.0],
+			[-(right + left) / w, -(top + bottom) / h, -(zfar + znear) / d, 1.0],
+		])
+	}
+
+	/// Right-handed orthographic projection.
+	///
+	/// This is the same transformation as OpenGL `glOrtho`: the box bounded by
+	/// `left`, `right`, `bottom`, `top` on X and Y and `-znear`, `-zfar` on Z
+	/// maps to the OpenGL NDC cube, with `-znear` at Z = -1 and `-zfar` at
+	/// Z = 1.
+	pub fn orthographic_rh(left: f32, right: f32,
+			bottom: f32, top: f32,
+			znear: f32, zfar: f32) -> Mat4<f32> {
+		let (w, h, d) = (right - left, top - bottom, zfar - znear);
+		Mat4([
+			[2.0 / w,              0.0,                  0.0,                 0.0],
+			[0.0,                  2.0 / h,              0.0,                 0.0],
+			[0.0,                  0.0,                  -2.0 / d,            0.0],
+			[-(right + left) / w, -(top + bottom) / h, -(zfar + znear) / d, 1.0],
+		])
+	}
+}
+impl<T: Float> Mat4<T> {
+	/// Left-handed perspective projection.
+	///
+	/// Eye space looks down +Z. Visible points map to OpenGL NDC with X, Y
+	/// and Z in [-1, 1]; `znear` maps to Z = -1 and `zfar` to Z = 1. `fov` is
+	/// the vertical field of view in radians and `aspect_ratio` is width over
+	/// height.
+	pub fn perspective_lh(fov: T, aspect_ratio: T, znear: T, zfar: T) -> Mat4<T> {
+		let (zero, one, two) = (T::from_f32(0.0), T::from_f32(1.0), T::from_f32(2.0));
+		let f = one / (fov / two).tan();
+		Mat4([
+			[f / aspect_ratio, zero, zero,                           zero],
+			[zero,             f,    zero,                           zero],
+			[zero,             zero, (zfar+znear)/(zfar-znear),      one],
+			[zero,             zero, -(two*zfar*znear)/(zfar-znear), zero],
+		])
+	}
+
+	/// Right-handed perspective projection.
+	///
+	/// Eye space looks down -Z. Visible points map to OpenGL NDC with X, Y
+	/// and Z in [-1, 1]; `-znear` maps to Z = -1 and `-zfar` to Z = 1. `fov` is
+	/// the vertical field of view in radians and `aspect_ratio` is width over
+	/// height.
+	pub fn perspective_rh(fov: T, aspect_ratio: T, znear: T, zfar: T) -> Mat4<T> {
+		let (zero, one, two) = (T::from_f32(0.0), T::from_f32(1.0), T::from_f32(2.0));
+		let f = one / (fov / two).tan();
+		Mat4([
+			[f / aspect_ratio, zero, zero,                           zero],
+			[zero,             f,    zero,                           zero],
+			[zero,             zero, -(zfar+znear)/(zfar-znear),     -one],
+			[zero,             zero, -(two*zfar*znear)/(zfar-znear), zero],
+		])
+	}
+
+	/// Left-handed view matrix.
+	///
+	/// This transforms world space to an eye space with the camera at the
+	/// origin looking down +Z, with +Y up and +X to the right. Unlike
+	/// `gluLookAt`, this takes a view direction rather than a target point.
+	pub fn look_at_lh(position: Vec3<T>, direction: Vec3<T>, up: Vec3<T>) -> Mat4<T> {
+		let (zero, one) = (T::from_f32(0.0), T::from_f32(1.0));
+		let f = direction.normalize();
+		let s = up.cross(f).normalize();
+		let u = f.cross(s);
+		Mat4([
+			[s[0], u[0], f[0], zero],
+			[s[1], u[1], f[1], zero],
+			[s[2], u[2], f[2], zero],
+			[-position.dot(s), -position.dot(u), -position.dot(f), one],
+		])
+	}
+
+	/// Right-handed view matrix.
+	///
+	/// This transforms world space to an eye space with the camera at the
+	/// origin looking down -Z, with +Y up and +X to the right. Unlike
+	/// `gluLookAt`, this takes a view direction rather than a target point.
+	pub fn look_at_rh(position: Vec3<T>, direction: Vec3<T>, up: Vec3<T>) -> Mat4<T> {
+		let (zero, one) = (T::from_f32(0.0), T::from_f32(1.0));
+		let f = direction.normalize();
+		let s = f.cross(up).normalize();
+		let u = s.cross(f);
+		Mat4([
+			[s[0], u[0], -f[0], zero],
+			[s[1], u[1], -f[1], zero],
+			[s[2], u[2], -f[2], zero],
+			[-position.dot(s), -position.dot(u), position.dot(f), one],
+		])
+	}
+
+	/// Convert this matrix to `f32`, e.g. to pass it as a uniform.
+	pub fn to_f32(&self) -> Mat4<f32> {
+		let mut m = [[0.0f32; 4]; 4];
+		for i in 0..4 {
+			for j in 0..4 {
+				m[i][j] = self[i][j].to_f32();
+			}
+		}
+		Mat4(m)
+	}
+}
 impl<T> Mul for Mat4<T> where T: Copy + Mul<Output = T> + Add<Output = T> {
 	type Output = Self;
 	/// Matrix product
@@ -34,6 +289,23 @@ impl<T> Mul<Vec4<T>> for Mat4<T> where T: Copy + Mul<Output = T> + Add<Output=T>
 		result
 	}
 }
+impl<T> Mul<Mat4<T>> for Vec4<T> where T: Copy + Mul<Output = T> + Add<Output=T> {
+	type Output = Vec4<T>;
+	/// Row vector application
+	///
+	/// This is how the crate's transformation matrices are applied to points
+	/// and directions.
+	fn mul(self, r: Mat4<T>) -> Vec4<T> {
+		let mut result = Vec4::from([self[0]; 4]);
+		for j in 0..4 {
+			result[j] = self[0] * r[0][j] +
+			            self[1] * r[1][j] +
+			            self[2] * r[2][j] +
+			            self[3] * r[3][j];
+		}
+		result
+	}
+}
 impl<T: Copy> Index<usize> for Mat4<T> {
 	type Output = [T; 4];
 	fn index(&self, index: usize) -> &[T; 4] {
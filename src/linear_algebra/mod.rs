@@ -1,14 +1,21 @@
 //! Linear algebra
+mod aabb;
 mod mat3;
 mod mat4;
+mod ray;
 mod vec3;
 mod vec4;
 
+pub use self::aabb::Aabb;
 pub use self::mat3::Mat3;
 pub use self::mat4::Mat4;
+pub use self::ray::Ray;
 pub use self::vec3::Vec3;
 pub use self::vec4::Vec4;
 
+use std::fmt::Debug;
+use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Rem, Sub, SubAssign};
+
 /// Trait for objects which can have their square root taken
 pub trait Sqrt {
 	/// The type of the square root
@@ -29,9 +36,81 @@ impl Sqrt for f64 {
 	}
 }
 
+/// Trait for the floating point types cameras and physics can work in.
+///
+/// This is implemented for `f32` and `f64`; the renderer still wants `f32`,
+/// so convert with `to_f32` at the uniform boundary.
+pub trait Float: Copy + Debug + Default + PartialOrd +
+		Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self> + Div<Output = Self> +
+		Rem<Output = Self> + Neg<Output = Self> +
+		AddAssign + SubAssign + MulAssign + DivAssign +
+		Sqrt<Output = Self> {
+	/// Convert from an `f32`, e.g. for constants.
+	fn from_f32(value: f32) -> Self;
+	/// Convert to an `f32`, possibly losing precision.
+	fn to_f32(self) -> f32;
+	/// Sine, in radians.
+	fn sin(self) -> Self;
+	/// Cosine, in radians.
+	fn cos(self) -> Self;
+	/// Tangent, in radians.
+	fn tan(self) -> Self;
+	/// Arccosine, in radians.
+	fn acos(self) -> Self;
+	/// Four quadrant arctangent of `self` (y) and `x`, in radians.
+	fn atan2(self, x: Self) -> Self;
+	/// Length of the hypotenuse of a right triangle with legs `self` and `y`.
+	fn hypot(self, y: Self) -> Self;
+	/// Absolute value.
+	fn abs(self) -> Self;
+	/// The smaller of `self` and `other`, ignoring NaN.
+	fn min(self, other: Self) -> Self;
+	/// The larger of `self` and `other`, ignoring NaN.
+	fn max(self, other: Self) -> Self;
+	/// Whether this is neither infinite nor NaN.
+	fn is_finite(self) -> bool;
+}
+macro_rules! impl_float {
+	($t:ident) => {
+		impl Float for $t {
+			fn from_f32(value: f32) -> $t { value as $t }
+			fn to_f32(self) -> f32 { self as f32 }
+			fn sin(self) -> $t { self.sin() }
+			fn cos(self) -> $t { self.cos() }
+			fn tan(self) -> $t { self.tan() }
+			fn acos(self) -> $t { self.acos() }
+			fn atan2(self, x: $t) -> $t { self.atan2(x) }
+			fn hypot(self, y: $t) -> $t { self.hypot(y) }
+			fn abs(self) -> $t { self.abs() }
+			fn min(self, other: $t) -> $t { self.min(other) }
+			fn max(self, other: $t) -> $t { self.max(other) }
+			fn is_finite(self) -> bool { self.is_finite() }
+		}
+	}
+}
+impl_float!(f32);
+impl_float!(f64);
+
 #[cfg(test)]
 mod tests {
-	use super::{Mat4, Vec3};
+	use super::{Aabb, Mat4, Ray, Vec3, Vec4};
+
+	/// Transform a point by `m` and perform the perspective divide.
+	fn transform(m: Mat4<f32>, p: [f32; 3]) -> [f32; 3] {
+		let p = [p[0], p[1], p[2], 1.0];
+		let mut r = [0.0f32; 4];
+		for j in 0..4 {
+			r[j] = p[0] * m[0][j] + p[1] * m[1][j] + p[2] * m[2][j] + p[3] * m[3][j];
+		}
+		[r[0] / r[3], r[1] / r[3], r[2] / r[3]]
+	}
+
+	fn assert_near(expected: [f32; 3], actual: [f32; 3]) {
+		for i in 0..3 {
+			assert!((expected[i] - actual[i]).abs() < 1e-5,
+				"expected {:?}, got {:?}", expected, actual);
+		}
+	}
 
 	#[test]
 	fn test_mat4_mul() {
@@ -65,5 +144,227 @@ mod tests {
 		let actual = lhs.cross(rhs);
 		assert_eq!(expected, actual);
 	}
-}
 
+	#[test]
+	fn test_perspective_lh() {
+		let m = Mat4::perspective_lh(::std::f32::consts::PI / 2.0, 1.0, 1.0, 10.0);
+		assert_near([0.5, 0.25, 1.0 / 9.0], transform(m, [1.0, 0.5, 2.0]));
+		assert_near([0.0, 0.0, -1.0], transform(m, [0.0, 0.0, 1.0]));
+		assert_near([0.0, 0.0, 1.0], transform(m, [0.0, 0.0, 10.0]));
+	}
+
+	#[test]
+	fn test_perspective_rh() {
+		let m = Mat4::perspective_rh(::std::f32::consts::PI / 2.0, 1.0, 1.0, 10.0);
+		assert_near([0.5, 0.25, 1.0 / 9.0], transform(m, [1.0, 0.5, -2.0]));
+		assert_near([0.0, 0.0, -1.0], transform(m, [0.0, 0.0, -1.0]));
+		assert_near([0.0, 0.0, 1.0], transform(m, [0.0, 0.0, -10.0]));
+	}
+
+	#[test]
+	fn test_look_at_lh() {
+		let m = Mat4::look_at_lh(
+			Vec3::from([1.0, 2.0, 3.0]),
+			Vec3::from([1.0, 0.0, 0.0]),
+			Vec3::from([0.0, 1.0, 0.0]));
+		assert_near([0.0, 0.0, 1.0], transform(m, [2.0, 2.0, 3.0]));
+		assert_near([0.0, 1.0, 0.0], transform(m, [1.0, 3.0, 3.0]));
+		assert_near([1.0, 0.0, 0.0], transform(m, [1.0, 2.0, 2.0]));
+	}
+
+	#[test]
+	fn test_look_at_rh() {
+		let m = Mat4::look_at_rh(
+			Vec3::from([1.0, 2.0, 3.0]),
+			Vec3::from([1.0, 0.0, 0.0]),
+			Vec3::from([0.0, 1.0, 0.0]));
+		assert_near([0.0, 0.0, -1.0], transform(m, [2.0, 2.0, 3.0]));
+		assert_near([0.0, 1.0, 0.0], transform(m, [1.0, 3.0, 3.0]));
+		assert_near([1.0, 0.0, 0.0], transform(m, [1.0, 2.0, 4.0]));
+	}
+
+	#[test]
+	fn test_vec_neg() {
+		assert_eq!(Vec3::from([-1, 2, -3]), -Vec3::from([1, -2, 3]));
+		assert_eq!(Vec4::from([-1, 2, -3, 4]), -Vec4::from([1, -2, 3, -4]));
+	}
+
+	#[test]
+	fn test_vec_assign_ops() {
+		let mut v = Vec3::from([1, 2, 3]);
+		v += Vec3::from([1, 1, 1]);
+		assert_eq!(Vec3::from([2, 3, 4]), v);
+		v -= Vec3::from([2, 2, 2]);
+		assert_eq!(Vec3::from([0, 1, 2]), v);
+		v *= 4;
+		assert_eq!(Vec3::from([0, 4, 8]), v);
+		v /= 2;
+		assert_eq!(Vec3::from([0, 2, 4]), v);
+
+		let mut v = Vec4::from([1, 2, 3, 4]);
+		v += Vec4::from([1, 1, 1, 1]);
+		assert_eq!(Vec4::from([2, 3, 4, 5]), v);
+		v -= Vec4::from([2, 2, 2, 2]);
+		assert_eq!(Vec4::from([0, 1, 2, 3]), v);
+		v *= 4;
+		assert_eq!(Vec4::from([0, 4, 8, 12]), v);
+		v /= 2;
+		assert_eq!(Vec4::from([0, 2, 4, 6]), v);
+	}
+
+	#[test]
+	fn test_vec_length() {
+		assert_eq!(25.0, Vec3::from([3.0, 4.0, 0.0f32]).length_squared());
+		assert_eq!(5.0, Vec3::from([3.0, 4.0, 0.0f32]).length());
+		assert_eq!(4.0, Vec4::from([1.0, 1.0, 1.0, 1.0f64]).length_squared());
+		assert_eq!(2.0, Vec4::from([1.0, 1.0, 1.0, 1.0f64]).length());
+		let n = Vec3::from([1.0, -2.0, 7.5f32]).normalize();
+		assert!((n.length() - 1.0).abs() < 1e-6);
+	}
+
+	#[test]
+	fn test_vec_lerp() {
+		let a = Vec3::from([0.0, 2.0, -4.0f32]);
+		let b = Vec3::from([4.0, 2.0, 4.0f32]);
+		assert_eq!(a, a.lerp(b, 0.0));
+		assert_eq!(b, a.lerp(b, 1.0));
+		assert_eq!(Vec3::from([1.0, 2.0, -2.0]), a.lerp(b, 0.25));
+		let a = Vec4::from([0.0, 2.0, -4.0, 1.0f64]);
+		let b = Vec4::from([4.0, 2.0, 4.0, 3.0f64]);
+		assert_eq!(Vec4::from([2.0, 2.0, 0.0, 2.0]), a.lerp(b, 0.5));
+	}
+
+	#[test]
+	fn test_mat4_inverse() {
+		let m = Mat4::from([
+			[2.0, 0.0, 0.0, 0.0],
+			[0.0, 0.0, 3.0, 0.0],
+			[0.0, 1.0, 0.0, 0.0],
+			[4.0, 5.0, 6.0, 1.0f32],
+		]);
+		let identity = Mat4::from([
+			[1.0, 0.0, 0.0, 0.0],
+			[0.0, 1.0, 0.0, 0.0],
+			[0.0, 0.0, 1.0, 0.0],
+			[0.0, 0.0, 0.0, 1.0f32],
+		]);
+		let product = m * m.inverse().unwrap();
+		for i in 0..4 {
+			for j in 0..4 {
+				assert!((product[i][j] - identity[i][j]).abs() < 1e-6,
+					"expected identity, got {:?}", product);
+			}
+		}
+
+		let singular = Mat4::from([
+			[1.0, 2.0, 3.0, 4.0],
+			[2.0, 4.0, 6.0, 8.0],
+			[0.0, 1.0, 0.0, 0.0],
+			[0.0, 0.0, 0.0, 1.0f32],
+		]);
+		assert_eq!(None, singular.inverse());
+	}
+
+	#[test]
+	fn test_mat4_rotations() {
+		use std::f32::consts::PI;
+		let x = Vec3::from([1.0, 0.0, 0.0]);
+		let y = Vec3::from([0.0, 1.0, 0.0]);
+		let z = Vec3::from([0.0, 0.0, 1.0]);
+		let near = |expected: Vec3<f32>, actual: Vec3<f32>|
+			assert_near(expected.into(), actual.into());
+
+		// Each rotation turns one axis into the next, right-handed
+		near(z, Mat4::rotation_x(PI / 2.0).transform_point(y));
+		near(x, Mat4::rotation_y(PI / 2.0).transform_point(z));
+		near(-z, Mat4::rotation_y(PI / 2.0).transform_point(x));
+		near(y, Mat4::rotation_z(PI / 2.0).transform_point(x));
+		// And the axis itself stays put
+		near(x, Mat4::rotation_x(1.0).transform_point(x));
+		near(y, Mat4::rotation_y(1.0).transform_point(y));
+		near(z, Mat4::rotation_z(1.0).transform_point(z));
+
+		// Axis/angle rotations about the axes agree with the above
+		for &angle in [0.3, PI / 2.0, 2.0].iter() {
+			for &(axis, rotation) in [
+					(x, Mat4::rotation_x(angle)),
+					(y, Mat4::rotation_y(angle)),
+					(z, Mat4::rotation_z(angle))].iter() {
+				let axis_angle = Mat4::rotation_axis_angle(axis * 2.0, angle);
+				for row in 0..4 {
+					assert_near([rotation[row][0], rotation[row][1], rotation[row][2]],
+						[axis_angle[row][0], axis_angle[row][1], axis_angle[row][2]]);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn test_mat4_trs() {
+		let t = Vec3::from([1.0, 2.0, 3.0]);
+		let r = Mat4::rotation_axis_angle(Vec3::from([1.0, 1.0, 0.0]), 0.7);
+		let s = Vec3::from([2.0, 0.5, 1.0]);
+		let trs = Mat4::trs(t, r, s);
+		assert_eq!(Mat4::scale(s) * r * Mat4::translation(t), trs);
+
+		// The point is scaled, then rotated, then translated
+		let p = Vec3::from([1.0, -1.0, 4.0]);
+		let expected = r.transform_point(Vec3::from([2.0, -0.5, 4.0])) + t;
+		assert_near(expected.into(), trs.transform_point(p).into());
+
+		assert_eq!(Mat4::scale(Vec3::from([3.0, 3.0, 3.0])), Mat4::scale_uniform(3.0));
+		assert_eq!(p, Mat4::identity().transform_point(p));
+	}
+
+	#[test]
+	fn test_aabb_transform() {
+		let aabb = Aabb::from_points(vec![
+				Vec3::from([1.0, -1.0, 0.0]),
+				Vec3::from([-1.0, 2.0, 0.5]),
+				Vec3::from([0.0, 0.0, 1.0])]);
+		assert_eq!(aabb.min, Vec3::from([-1.0, -1.0, 0.0]));
+		assert_eq!(aabb.max, Vec3::from([1.0, 2.0, 1.0]));
+		// Scale by 2 then translate by (10, 0, 0)
+		let m = Mat4::from([
+				[2.0, 0.0, 0.0, 0.0],
+				[0.0, 2.0, 0.0, 0.0],
+				[0.0, 0.0, 2.0, 0.0],
+				[10.0, 0.0, 0.0, 1.0]]);
+		let moved = aabb.transform(m);
+		assert_eq!(moved.min, Vec3::from([8.0, -2.0, 0.0]));
+		assert_eq!(moved.max, Vec3::from([12.0, 4.0, 2.0]));
+	}
+
+	#[test]
+	fn test_aabb_distance_to() {
+		let aabb = Aabb {
+			min: Vec3::from([-1.0, 0.0, -1.0]),
+			max: Vec3::from([1.0, 2.0, 1.0]),
+		};
+		assert_eq!(0.0, aabb.distance_to(Vec3::from([0.5, 1.0, -0.5])));
+		assert_eq!(0.0, aabb.distance_to(Vec3::from([1.0, 2.0, 1.0])));
+		assert_eq!(2.0, aabb.distance_to(Vec3::from([0.0, 4.0, 0.0])));
+		assert_eq!(5.0, aabb.distance_to(Vec3::from([4.0, 1.0, -5.0])));
+	}
+
+	#[test]
+	fn test_ray_intersect_aabb() {
+		let aabb = Aabb {
+			min: Vec3::from([1.0, -1.0, -1.0]),
+			max: Vec3::from([3.0, 1.0, 1.0]),
+		};
+		let ray = |origin: [f32; 3], direction: [f32; 3]| Ray {
+			origin: Vec3::from(origin),
+			direction: Vec3::from(direction),
+		};
+		// Straight on, and at an angle
+		assert_eq!(Some(1.0), ray([0.0, 0.0, 0.0], [1.0, 0.0, 0.0]).intersect_aabb(&aabb));
+		assert_eq!(Some(2.0), ray([-1.0, -1.5, 0.0], [1.0, 0.5, 0.0]).intersect_aabb(&aabb));
+		// From inside
+		assert_eq!(Some(0.0), ray([2.0, 0.0, 0.0], [0.0, 1.0, 0.0]).intersect_aabb(&aabb));
+		// Pointing away, missing to the side, and parallel outside
+		assert_eq!(None, ray([0.0, 0.0, 0.0], [-1.0, 0.0, 0.0]).intersect_aabb(&aabb));
+		assert_eq!(None, ray([0.0, 0.5, 0.0], [1.0, 1.0, 0.0]).intersect_aabb(&aabb));
+		assert_eq!(None, ray([0.0, 2.0, 0.0], [1.0, 0.0, 0.0]).intersect_aabb(&aabb));
+	}
+}
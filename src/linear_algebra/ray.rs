@@ -0,0 +1,47 @@
+use std::ops::{Add, Mul};
+use super::{Aabb, Vec3};
+
+/// A ray, starting at an origin and extending infinitely in one direction.
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub struct Ray<T: Copy> {
+	/// The point this ray starts from.
+	pub origin: Vec3<T>,
+	/// The direction this ray extends in. This is normally, but not
+	/// necessarily, normalized.
+	pub direction: Vec3<T>,
+}
+impl<T> Ray<T> where T: Copy + Add<Output = T> + Mul<Output = T> {
+	/// The point `t` lengths of `direction` along this ray.
+	pub fn at(&self, t: T) -> Vec3<T> {
+		self.origin + self.direction * t
+	}
+}
+impl Ray<f32> {
+	/// Find where this ray first enters an axis-aligned bounding box, as a
+	/// distance along the ray in lengths of `direction`.
+	///
+	/// Returns `None` if the ray misses the box, or the box is entirely behind
+	/// the ray's origin. If the origin is inside the box, this is zero.
+	pub fn intersect_aabb(&self, aabb: &Aabb<f32>) -> Option<f32> {
+		// Slab method: clip the ray to each pair of planes in turn
+		let mut t_min = 0.0f32;
+		let mut t_max = ::std::f32::INFINITY;
+		for i in 0..3 {
+			if self.direction[i] == 0.0 {
+				// Parallel to these planes, so we need to start between them
+				if self.origin[i] < aabb.min[i] || self.origin[i] > aabb.max[i] {
+					return None;
+				}
+				continue;
+			}
+			let t1 = (aabb.min[i] - self.origin[i]) / self.direction[i];
+			let t2 = (aabb.max[i] - self.origin[i]) / self.direction[i];
+			t_min = t_min.max(t1.min(t2));
+			t_max = t_max.min(t1.max(t2));
+			if t_min > t_max {
+				return None;
+			}
+		}
+		Some(t_min)
+	}
+}
@@ -1,4 +1,5 @@
-use std::ops::{Add, Div, Index, IndexMut, Mul, Sub};
+use std::ops::{Add, AddAssign, Div, DivAssign, Index, IndexMut, Mul, MulAssign, Neg, Sub,
+		SubAssign};
 use super::{Sqrt, Vec4};
 
 /// A 3D vector.
@@ -13,6 +14,23 @@ impl<T> Vec3<T> where T: Copy + Mul<Output=T> + Add<Output=T> {
 		l[0] * r[0] + l[1] * r[1] + l[2] * r[2]
 	}
 }
+impl<T> Vec3<T> where T: Copy + Mul<Output=T> + Add<Output=T> + Sqrt<Output=T> {
+	/// Squared length of this 3D vector.
+	pub fn length_squared(self) -> T {
+		self.dot(self)
+	}
+	/// Length of this 3D vector.
+	pub fn length(self) -> T {
+		self.length_squared().sqrt()
+	}
+}
+impl<T> Vec3<T> where T: Copy + Add<Output=T> + Sub<Output=T> + Mul<Output=T> {
+	/// Linear interpolation between this vector (at `t = 0`) and `other` (at
+	/// `t = 1`).
+	pub fn lerp(self, other: Self, t: T) -> Self {
+		self + (other - self) * t
+	}
+}
 impl<T> Vec3<T> where T: Copy + Mul<Output=T> + Sub<Output=T> {
 	/// Cross product of two 3D vectors.
 	pub fn cross(self, rhs: Self) -> Self {
@@ -63,6 +81,32 @@ impl<T> Sub for Vec3<T> where T: Copy + Sub<Output = T> {
 		Vec3([self[0] - r[0], self[1] - r[1], self[2] - r[2]])
 	}
 }
+impl<T> Neg for Vec3<T> where T: Copy + Neg<Output = T> {
+	type Output = Self;
+	fn neg(self) -> Self {
+		Vec3([-self[0], -self[1], -self[2]])
+	}
+}
+impl<T> AddAssign for Vec3<T> where T: Copy + AddAssign {
+	fn add_assign(&mut self, r: Self) {
+		for i in 0..3 { self[i] += r[i]; }
+	}
+}
+impl<T> DivAssign<T> for Vec3<T> where T: Copy + DivAssign {
+	fn div_assign(&mut self, r: T) {
+		for i in 0..3 { self[i] /= r; }
+	}
+}
+impl<T> MulAssign<T> for Vec3<T> where T: Copy + MulAssign {
+	fn mul_assign(&mut self, r: T) {
+		for i in 0..3 { self[i] *= r; }
+	}
+}
+impl<T> SubAssign for Vec3<T> where T: Copy + SubAssign {
+	fn sub_assign(&mut self, r: Self) {
+		for i in 0..3 { self[i] -= r[i]; }
+	}
+}
 
 // Indexing and conversion
 impl<T: Copy> Index<usize> for Vec3<T> {
@@ -1,4 +1,6 @@
-use std::ops::{Add, Index, IndexMut, Mul};
+use std::ops::{Add, AddAssign, Div, DivAssign, Index, IndexMut, Mul, MulAssign, Neg, Sub,
+		SubAssign};
+use super::Sqrt;
 
 /// A 4D vector.
 #[derive(Copy,Clone,Debug,PartialEq)]
@@ -11,6 +13,77 @@ impl<T> Vec4<T> where T: Copy + Mul<Output=T> + Add<Output=T> {
 		l[0] * r[0] + l[1] * r[1] + l[2] * r[2] + l[3] * r[3]
 	}
 }
+impl<T> Vec4<T> where T: Copy + Mul<Output=T> + Add<Output=T> + Sqrt<Output=T> {
+	/// Squared length of this 4D vector.
+	pub fn length_squared(self) -> T {
+		self.dot(self)
+	}
+	/// Length of this 4D vector.
+	pub fn length(self) -> T {
+		self.length_squared().sqrt()
+	}
+}
+impl<T> Vec4<T> where T: Copy + Add<Output=T> + Sub<Output=T> + Mul<Output=T> {
+	/// Linear interpolation between this vector (at `t = 0`) and `other` (at
+	/// `t = 1`).
+	pub fn lerp(self, other: Self, t: T) -> Self {
+		self + (other - self) * t
+	}
+}
+
+// Arithmetic operations
+impl<T> Add for Vec4<T> where T: Copy + Add<Output = T> {
+	type Output = Self;
+	fn add(self, r: Self) -> Self {
+		Vec4([self[0] + r[0], self[1] + r[1], self[2] + r[2], self[3] + r[3]])
+	}
+}
+impl<T> Div<T> for Vec4<T> where T: Copy + Div<Output = T> {
+	type Output = Self;
+	fn div(self, r: T) -> Self {
+		Vec4([self[0] / r, self[1] / r, self[2] / r, self[3] / r])
+	}
+}
+impl<T> Mul<T> for Vec4<T> where T: Copy + Mul<Output = T> {
+	type Output = Self;
+	fn mul(self, r: T) -> Self {
+		Vec4([self[0] * r, self[1] * r, self[2] * r, self[3] * r])
+	}
+}
+impl<T> Sub for Vec4<T> where T: Copy + Sub<Output = T> {
+	type Output = Self;
+	fn sub(self, r: Self) -> Self {
+		Vec4([self[0] - r[0], self[1] - r[1], self[2] - r[2], self[3] - r[3]])
+	}
+}
+impl<T> Neg for Vec4<T> where T: Copy + Neg<Output = T> {
+	type Output = Self;
+	fn neg(self) -> Self {
+		Vec4([-self[0], -self[1], -self[2], -self[3]])
+	}
+}
+impl<T> AddAssign for Vec4<T> where T: Copy + AddAssign {
+	fn add_assign(&mut self, r: Self) {
+		for i in 0..4 { self[i] += r[i]; }
+	}
+}
+impl<T> DivAssign<T> for Vec4<T> where T: Copy + DivAssign {
+	fn div_assign(&mut self, r: T) {
+		for i in 0..4 { self[i] /= r; }
+	}
+}
+impl<T> MulAssign<T> for Vec4<T> where T: Copy + MulAssign {
+	fn mul_assign(&mut self, r: T) {
+		for i in 0..4 { self[i] *= r; }
+	}
+}
+impl<T> SubAssign for Vec4<T> where T: Copy + SubAssign {
+	fn sub_assign(&mut self, r: Self) {
+		for i in 0..4 { self[i] -= r[i]; }
+	}
+}
+
+// Indexing and conversion
 impl<T: Copy> Index<usize> for Vec4<T> {
 	type Output = T;
 	fn index(&self, index: usize) -> &T {
@@ -12,75 +12,217 @@
 //!  * `data/materials.mtl`
 //!  * `data/wt_teapot.obj`
 //!  * `data/floor-texture.png`
-//!  * `data/heightmap.png`
+//!  * `data/heightmap.png` (optional; terrain is generated if it's missing)
 //!  * `data/teapot-texture.png`
 //!  * `data/vertex_shader.vert`
+//!  * `data/instanced-vertex-shader.vert`
+//!  * `data/debug-fragment-shader.frag`
+//!  * `data/debug-vertex-shader.vert`
+//!  * `data/skybox-fragment-shader.frag`
+//!  * `data/skybox-vertex-shader.vert`
+//!  * `data/text-fragment-shader.frag`
+//!  * `data/text-vertex-shader.vert`
+//!  * `data/skybox-{posx,negx,posy,negy,posz,negz}.png` (optional; the sky is
+//!    a flat color if they're missing)
+//!  * `data/config.toml` (optional; see `config::Config::from_toml` for
+//!    physics, camera and asset path settings and rebinding movement keys)
 //!
 //! These files are all in these locations relative to the repository root, so
 //! running the program from the repository root (e.g. with `cargo run`)
-//! will find them where it expects.
+//! will find them where it expects. The whole `data` directory can be moved
+//! with the `--data-dir` option, and all but the config file itself can be
+//! moved by setting their paths in the config file.
 //!
-//! Movement controls are as follows:
+//! Run with `--help` for the other command-line options, which set the window
+//! size, fullscreen, vsync and log level.
+//!
+//! Paths inside `.obj` and `.mtl` files are relative to the file they're in.
+//!
+//! Movement controls are as follows, by default:
 //!
 //!  * Mouse: rotate camera
+//!  * Scroll wheel: zoom (or move the orbit camera in and out)
 //!  * `W`: move forwards
 //!  * `A`: move left
 //!  * `S`: move backwards
 //!  * `D`: move right
 //!  * Space: jump
+//!  * `R`: respawn; this also happens after falling out of the world
+//!  * F5: respawn here from now on
+//!  * `V`: toggle between first- and third-person camera
+//!  * `` ` ``: toggle the debugging console; type `help` in it for commands
+//!  * `F`: toggle the top-down frustum overlay
+//!  * F3: cycle debug views: off, wireframe, and surfaces colored by normal
+//!  * F6: save the terrain tiles being drawn to `dump/terrain-tile-N.obj`
+//!  * `P`: pause or resume (switching to another window also pauses)
+//!  * F12: save a screenshot to the current directory
+//!  * Tab: toggle an orbit camera around the character's current location
+//!  * `[`/`]`: decrease/increase terrain LoD bias (finer/coarser terrain)
+//!  * `-`/`=`: shrink/grow the terrain LoD zone
 //!  * `Q`/Esc: exit
 
+extern crate base64;
 extern crate chrono;
 #[macro_use]
 extern crate error_chain;
 extern crate env_logger;
+extern crate gltf;
 #[macro_use]
 extern crate glium;
 extern crate image;
 #[macro_use]
 extern crate log;
+extern crate rayon;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate toml;
 extern crate wavefront_obj;
 
+pub mod args;
+pub mod config;
+pub mod console;
 pub mod display_math;
+pub mod input;
 pub mod linear_algebra;
 pub mod model;
 pub mod physics;
 pub mod renderable;
+pub mod timing;
+pub mod util;
 
 mod errors { error_chain! { } }
 
 use env_logger::Builder;
 use errors::*;
-use glium::{Depth, Display, DrawParameters, Program, Surface};
+use glium::{BlitTarget, Blend, Depth, Display, DrawParameters, Frame, Program};
+use glium::{Rect, Surface};
 use glium::draw_parameters::{BackfaceCullingMode, DepthTest};
 use glium::glutin::{Api, ContextBuilder, DeviceEvent, ElementState, Event};
 use glium::glutin::{EventsLoop, GlRequest, KeyboardInput, VirtualKeyCode};
-use glium::glutin::{WindowBuilder, WindowEvent};
-use glium::texture::Texture2d;
+use glium::glutin::{Window, WindowBuilder, WindowEvent};
+use glium::glutin::dpi::LogicalSize;
+use glium::texture::{PixelBuffer, RawImage2d, Texture2d};
+use glium::uniforms::MagnifySamplerFilter;
 use linear_algebra::{Mat4, Vec3};
 use log::LevelFilter;
+use args::Args;
+use config::Config;
+use console::{Command, Console};
+use display_math::{Camera, CameraMode, Frustum, OrbitCamera};
 use model::heightmap::Heightmap;
-use renderable::{Renderable, TextRenderable2d};
-use std::fs::File;
+use model::heightmap::noise::NoiseParams;
+use model::heightmap::simpleheightmap::{SimpleHeightmap, TerrainLayout};
+use renderable::{DebugMode, DirectionalLight, FrustumDebugRenderable, PointLight, Renderable};
+use renderable::{Overlay, OverlayElement, TextRenderable2d, TextRenderer};
+use timing::FrameTimer;
+use std::fs::{self, File};
 use std::io::{BufReader, Read, Write};
+use std::path::Path;
 use std::time::Instant;
 
-const TEAPOT_PATH: &'static str = "data/wt-teapot.obj";
-const FLOOR_HEIGHTMAP: &'static str = "data/heightmap.png";
-const FLOOR_MATERIALS: &'static str = "data/materials.mtl";
-const FONT_TEXTURE: &'static str = "data/font-texture.png";
-const VERTEX_SHADER_PATH: &'static str = "data/vertex-shader.vert";
-const FRAGMENT_SHADER_PATH: &'static str = "data/fragment-shader.frag";
+/// Width and height, in vertices, of each separately LoD'd tile of terrain.
+const TERRAIN_TILE_SIZE: usize = 256;
+/// Smoothing iterations for terrain loaded from a heightmap image, to hide the
+/// steps between its 768 possible heights.
+const HEIGHTMAP_SMOOTHING: usize = 1;
+
+/// Steepness of the bumps in the normal map generated from the floor texture.
+const FLOOR_BUMPINESS: f32 = 8.0;
+
+/// Width and height, in vertices, of the terrain generated if there's no
+/// heightmap file.
+const NOISE_TERRAIN_SIZE: usize = 1024;
+const NOISE_TERRAIN_SEED: u64 = 0x676c2d64656d6f;
+/// Number of teapots to scatter across the terrain.
+const SCATTER_COUNT: usize = 64;
+/// Seed for placing scattered teapots.
+const SCATTER_SEED: u64 = 0x7465617073;
+
+const CONTROLS_LEGEND: &'static str =
+		"WASD: move, Space: jump, R: respawn, F5: set spawn\n\
+		V: camera mode, Tab: orbit, F: overlay, F3: debug view, `: console\n\
+		F6: dump terrain tiles\n\
+		[/]: LoD bias, -/=: LoD zone\n\
+		P: pause, F12: screenshot, Q/Esc: exit";
+
+/// Physics ticks per second. Higher values are more accurate but cost more
+/// CPU; the render rate is independent of this.
+const PHYSICS_TICK_RATE: f32 = 60.0;
+
+/// Offset of the first-person camera from the character.
+const EYE_HEIGHT: f32 = 0.5;
+/// Distance of the third-person camera from its pivot.
+const THIRD_PERSON_DISTANCE: f32 = 4.0;
+/// Height of the third-person camera pivot above the character.
+const THIRD_PERSON_HEIGHT: f32 = 1.0;
+/// Minimum height of the third-person camera above the ground.
+const CAMERA_CLEARANCE: f32 = 0.2;
+/// Initial distance of the orbit camera from its center.
+const ORBIT_RADIUS: f32 = 10.0;
+/// Distance the orbit camera moves per line scrolled.
+const ORBIT_ZOOM_SENSITIVITY: f32 = 1.0;
+/// Field of view change, in radians, per line scrolled.
+const FOV_SENSITIVITY: f32 = 0.05;
+/// Scale of the character model.
+const CHARACTER_SCALE: f32 = 0.4;
+
+/// Size of the frustum overlay, as a fraction of the window height.
+const OVERLAY_SIZE: f32 = 0.4;
+/// Half the width of the area shown in the frustum overlay.
+const OVERLAY_EXTENT: f32 = 40.0;
+/// Far plane of the frustum drawn in the overlay. The real far plane is far
+/// too distant to be useful to draw.
+const OVERLAY_FRUSTUM_FAR: f32 = 30.0;
 
-const CHAR_MAX_SPEED: f32 = 0.2;
-const CHAR_DECEL: f32 = 0.05;
-const CHAR_MAX_JUMP: f32 = 0.2;
-const CHAR_GRAVITY: f32 = 0.02;
+/// Lines of text shown in the console, including the input line.
+const CONSOLE_LINES: usize = 10;
+/// Overlay layer for the HUD and controls legend.
+const HUD_LAYER: i32 = 0;
+/// Color of HUD text.
+const HUD_COLOR: (f32, f32, f32, f32) = (1.0, 1.0, 1.0, 1.0);
+/// Frame rate below which the FPS counter is highlighted.
+const LOW_FPS: f32 = 30.0;
+/// Color of the FPS counter below `LOW_FPS`.
+const LOW_FPS_COLOR: (f32, f32, f32, f32) = (1.0, 0.2, 0.2, 1.0);
+/// Scale of the "PAUSED" message.
+const PAUSED_SCALE: f32 = 4.0;
+/// Overlay layer for the console's background, over the HUD.
+const CONSOLE_PANEL_LAYER: i32 = 10;
+/// Overlay layer for the console's text, over its background.
+const CONSOLE_LAYER: i32 = 11;
+/// Number of recent frames to show frame time statistics over.
+const FRAME_TIMER_WINDOW: usize = 120;
+/// Length of a full day/night cycle, in seconds. Set to 0 for a static sun.
+const DAY_LENGTH: f32 = 240.0;
+/// Time of day to start at, from 0 (midnight) to 1; see
+/// `renderable::sun_and_sky`.
+const START_TIME_OF_DAY: f32 = 0.4;
+
+/// Amount each keypress changes the terrain LoD bias by.
+const LOD_BIAS_STEP: f32 = 0.5;
+/// Limits on the terrain LoD zone scale. Tiny zones regenerate terrain
+/// constantly, and huge ones almost never.
+const MIN_LOD_ZONE_SCALE: f32 = 0.125;
+const MAX_LOD_ZONE_SCALE: f32 = 8.0;
+/// Directory terrain tiles are saved to for debugging.
+const DUMP_DIR: &'static str = "dump";
 
 /// Main entry point and error handling.
 fn main() {
-	init_log();
-	if let Err(e) = run() {
+	let args = match Args::parse(::std::env::args().skip(1)) {
+		Ok(args) => args,
+		Err(e) => {
+			eprintln!("{}\n\n{}", e, args::USAGE);
+			::std::process::exit(2);
+		},
+	};
+	if args.help {
+		print!("{}", args::USAGE);
+		return;
+	}
+	init_log(args.log_level);
+	if let Err(e) = run(&args) {
 		error!("Fatal error: {}", e);
 		for e in e.iter().skip(1) {
 			error!("\tCaused by: {}", e);
@@ -96,63 +238,181 @@ fn main() {
 ///
 /// This loads all neccessary world state, then runs the main event loop,
 /// which reads input, updates world state, and renders to the window.
-fn run() -> Result<()> {
+fn run(args: &Args) -> Result<()> {
 	info!("Starting demo...");
 
 	info!("Initializing display...");
-	let window = WindowBuilder::new()
-			.with_title("gl-demo");
+	let mut event_loop = EventsLoop::new();
+	let mut window = WindowBuilder::new()
+			.with_title("gl-demo")
+			.with_dimensions(LogicalSize::new(args.width as f64, args.height as f64));
+	if args.fullscreen {
+		window = window
+				.with_fullscreen(Some(event_loop.get_primary_monitor()))
+				.with_decorations(false);
+	}
 	let context = ContextBuilder::new()
 			.with_depth_buffer(24)
-			.with_vsync(true)
+			.with_vsync(args.vsync)
 			.with_gl(GlRequest::Specific(Api::OpenGl, (2, 1)));
-	let mut event_loop = EventsLoop::new();
 	let display = try!{ Display::new(window, context, &event_loop)
 			.map_err(|e| { Error::from(format!("{:?}", e)) } ) };
+	let config_path = Path::new(&args.data_dir).join(config::CONFIG_FILE);
+	let config = match File::open(&config_path) {
+		Ok(file) => try!{ Config::from_toml_in(&mut BufReader::new(file), &args.data_dir)
+				.chain_err(|| format!("Could not load config {}", config_path.display())) },
+		Err(e) => {
+			info!("Could not open config {} ({}), using defaults", config_path.display(), e);
+			Config::in_dir(&args.data_dir)
+		},
+	};
+	let bindings = config.keys;
+	let paths = &config.paths;
+	let (znear, zfar) = (config.view.znear, config.view.zfar);
+	let max_slope = config.physics.max_slope.to_radians();
 
 	info!("Loading models and textures...");
 	let library = model::mem::ModelLibrary::new();
-	let mut file = try!{ File::open(TEAPOT_PATH).chain_err(|| "Could not load teapot model") };
-	let teapot = try!{ library.load_model(&mut file) };
-	let mut file = try!{ File::open(FLOOR_MATERIALS)
-			.chain_err(|| "Could not load floor materials") };
-	let floor_mat = try!{ try!{ model::disk::load_mats(&mut file) }.remove("Floor")
-			.ok_or(Error::from("Floor material library missing floor material (\"Floor\")")) };
-	let file = try!{ File::open(FLOOR_HEIGHTMAP).chain_err(|| "Could not load heightmap") };
-	let heightmap = try!{ model::disk::load_texture(&mut BufReader::new(file))
-			.chain_err(|| "Could not load heightmap") };
-	let mut floor = model::heightmap::simpleheightmap::SimpleHeightmap::from_map(
-			&heightmap,
-			0.0,
-			100.0,
-			-100.0,
-			-86.6,
-			1.0,
-			&display,
-			floor_mat);
-	let file = try!{ File::open(FONT_TEXTURE).chain_err(|| "Could not load font texture") };
-	let font = try!{ model::disk::load_texture(&mut BufReader::new(file))
+	let teapot = try!{ library.load_model_from_path(&paths.teapot) };
+	let mut floor_mat = (*try!{ library.load_material(&paths.floor_materials, "Floor")
+			.chain_err(|| "Could not load floor material") }).clone();
+	if floor_mat.normal_map.is_none() {
+		floor_mat.normal_map = Some(model::mem::normal_map_from_texture(
+				&floor_mat.texture, FLOOR_BUMPINESS));
+	}
+	let mut floor = match File::open(&paths.floor_heightmap) {
+		Ok(file) => {
+			let mut read = BufReader::new(file);
+			let heightmap = try!{ model::disk::load_texture_guessing_format(&mut read)
+					.chain_err(|| "Could not load heightmap") };
+			let mut floor = try!{ SimpleHeightmap::from_map(
+					&heightmap,
+					0.0,
+					100.0,
+					TerrainLayout {
+						tile_size: TERRAIN_TILE_SIZE,
+						x_offset: -100.0,
+						z_offset: -86.6,
+						resolution: 1.0,
+					},
+					&display,
+					floor_mat) };
+			floor.smooth(HEIGHTMAP_SMOOTHING);
+			floor
+		},
+		Err(e) => {
+			warn!("Could not open heightmap ({}), generating terrain instead", e);
+			try!{ SimpleHeightmap::from_noise(
+					NOISE_TERRAIN_SIZE,
+					NOISE_TERRAIN_SIZE,
+					NoiseParams {
+						seed: NOISE_TERRAIN_SEED,
+						octaves: 6,
+						amplitude: 100.0,
+						frequency: 1.0 / 256.0,
+					},
+					TerrainLayout {
+						tile_size: TERRAIN_TILE_SIZE,
+						x_offset: -(NOISE_TERRAIN_SIZE as f32) / 2.0,
+						z_offset: -(NOISE_TERRAIN_SIZE as f32) / 2.0 * 0.866,
+						resolution: 1.0,
+					},
+					&display,
+					floor_mat) }
+		},
+	};
+	floor.set_sampler(model::gpu::SamplerSpec {
+		mipmaps: model::gpu::MipmapMode::Linear,
+		anisotropy: config.view.anisotropy.max(1.0) as u16,
+		.. model::gpu::SamplerSpec::default()
+	});
+	floor.set_lod_cache_size(config.view.lod_cache_size);
+	let file = try!{ File::open(&paths.font).chain_err(|| "Could not load font texture") };
+	let font = try!{ model::disk::load_texture_guessing_format(&mut BufReader::new(file))
 			.chain_err(|| "Could not load font texture") };
 	let font = try!{ Texture2d::new(&display, font)
 			.chain_err(|| "Could not load font texture") };
 
 	info!("Loading shaders...");
 	let mut vertex_shader = String::new();
-	let mut file = try!{ File::open(VERTEX_SHADER_PATH)
+	let mut file = try!{ File::open(&paths.vertex_shader)
 			.chain_err(|| "Could not load vertex shader") };
 	try!{ file.read_to_string(&mut vertex_shader)
 			.chain_err(|| "Could not load vertex shader") };
 	let mut fragment_shader = String::new();
-	let mut file = try!{ File::open(FRAGMENT_SHADER_PATH)
+	let mut file = try!{ File::open(&paths.fragment_shader)
 			.chain_err(|| "Could not load fragment shader") };
 	try!{ file.read_to_string(&mut fragment_shader)
 			.chain_err(|| "Could not load fragment shader") };
 
+	let mut instanced_vertex_shader = String::new();
+	let mut file = try!{ File::open(&paths.instanced_vertex_shader)
+			.chain_err(|| "Could not load instanced vertex shader") };
+	try!{ file.read_to_string(&mut instanced_vertex_shader)
+			.chain_err(|| "Could not load instanced vertex shader") };
+
+	let mut debug_vertex_shader = String::new();
+	let mut file = try!{ File::open(&paths.debug_vertex_shader)
+			.chain_err(|| "Could not load debug vertex shader") };
+	try!{ file.read_to_string(&mut debug_vertex_shader)
+			.chain_err(|| "Could not load debug vertex shader") };
+	let mut debug_fragment_shader = String::new();
+	let mut file = try!{ File::open(&paths.debug_fragment_shader)
+			.chain_err(|| "Could not load debug fragment shader") };
+	try!{ file.read_to_string(&mut debug_fragment_shader)
+			.chain_err(|| "Could not load debug fragment shader") };
+
+	let mut skybox_vertex_shader = String::new();
+	let mut file = try!{ File::open(&paths.skybox_vertex_shader)
+			.chain_err(|| "Could not load skybox vertex shader") };
+	try!{ file.read_to_string(&mut skybox_vertex_shader)
+			.chain_err(|| "Could not load skybox vertex shader") };
+	let mut skybox_fragment_shader = String::new();
+	let mut file = try!{ File::open(&paths.skybox_fragment_shader)
+			.chain_err(|| "Could not load skybox fragment shader") };
+	try!{ file.read_to_string(&mut skybox_fragment_shader)
+			.chain_err(|| "Could not load skybox fragment shader") };
+
+	let mut text_vertex_shader = String::new();
+	let mut file = try!{ File::open(&paths.text_vertex_shader)
+			.chain_err(|| "Could not load text vertex shader") };
+	try!{ file.read_to_string(&mut text_vertex_shader)
+			.chain_err(|| "Could not load text vertex shader") };
+	let mut text_fragment_shader = String::new();
+	let mut file = try!{ File::open(&paths.text_fragment_shader)
+			.chain_err(|| "Could not load text fragment shader") };
+	try!{ file.read_to_string(&mut text_fragment_shader)
+			.chain_err(|| "Could not load text fragment shader") };
+
 	info!("Compiling shaders...");
 	let program = try!{
 		Program::from_source(&display, &vertex_shader, &fragment_shader, None)
 			.chain_err(|| "Error compiling shaders")
 	};
+	let instanced_program = try!{
+		Program::from_source(&display, &instanced_vertex_shader, &fragment_shader, None)
+			.chain_err(|| "Error compiling instanced shaders")
+	};
+	let debug_program = try!{
+		Program::from_source(&display, &debug_vertex_shader, &debug_fragment_shader, None)
+			.chain_err(|| "Error compiling debug shaders")
+	};
+	let skybox_program = try!{
+		Program::from_source(&display, &skybox_vertex_shader, &skybox_fragment_shader, None)
+			.chain_err(|| "Error compiling skybox shaders")
+	};
+	let text_program = try!{
+		Program::from_source(&display, &text_vertex_shader, &text_fragment_shader, None)
+			.chain_err(|| "Error compiling text shaders")
+	};
+	let text_renderer = TextRenderer::new(&display, &text_program);
+	let skybox = match load_skybox(&display, &skybox_program, &paths.skybox_faces()) {
+		Ok(skybox) => Some(skybox),
+		Err(e) => {
+			warn!("Could not load skybox ({}), using a flat sky instead", e);
+			None
+		},
+	};
 
 	info!("Preparing environment...");
 	let params = DrawParameters {
@@ -175,23 +435,53 @@ fn run() -> Result<()> {
 		let scale = 0.5 + (obx + oby + obz) / 30.0;
 		objects.push(model::gpu::ModelInstance {
 				model: &gpu_teapot,
-				model_matrix: Mat4::from( [
-					[scale,	0.0,	0.0,	0.0],
-					[0.0,	scale,	0.0,	0.0],
-					[0.0,	0.0,	scale,	0.0],
-					[obx,	oby,	obz,	1.0] ] ), } );
+				model_matrix: Mat4::scale_uniform(scale) *
+					Mat4::translation(Vec3::from([obx, oby, obz])), } );
 	} } };
+	objects.extend(model::scatter::scatter_on_terrain(
+			&gpu_teapot,
+			&floor,
+			SCATTER_COUNT,
+			SCATTER_SEED,
+			max_slope));
+	let mut teapot_batch = batch_objects(&display, &gpu_teapot, &objects);
 
-	let light_pos = Vec3::from([-1.0, 0.4, 0.9f32]);
-	let light_color = (1.0, 1.0, 1.0f32);
+	let mut directional_lights = vec![
+		// Sun, moved by the day/night cycle
+		DirectionalLight {
+			direction: Vec3::from([-1.0, 0.4, 0.9]),
+			color: (1.0, 1.0, 1.0),
+		},
+		// Fill
+		DirectionalLight {
+			direction: Vec3::from([0.8, 0.3, -0.6]),
+			color: (0.25, 0.25, 0.35),
+		},
+	];
+	let point_lights = vec![
+		PointLight {
+			position: Vec3::from([-1.0, 1.5, 1.5]),
+			color: (1.0, 0.2, 0.1),
+			constant: 1.0,
+			linear: 0.2,
+			quadratic: 0.1,
+		},
+		// Torch among the teapots
+		PointLight::with_radius(Vec3::from([4.0, 1.5, 4.0]), (1.0, 0.6, 0.2), 2.0),
+	];
+	let mut time_of_day = START_TIME_OF_DAY;
+	let mut sky_color = (0.5, 0.5, 1.0, 1.0);
 
 	let mut frame: u64 = 0;
-	let mut last_time = Instant::now();
+	let mut last_frame_time = Instant::now();
+	let mut timestep = physics::FixedTimestep::new(1.0 / PHYSICS_TICK_RATE);
 
 	let fps_message_interval = 500;
-	let fov: f32 = std::f32::consts::PI / 2.0;
+	let mut frame_timer = FrameTimer::new(FRAME_TIMER_WINDOW);
+	let mut fov: f32 = config.view.fov.to_radians();
+	let mut window_size: (u32, u32) = (1, 1);
 
-	let mut perspective = display_math::perspective_matrix(1, 1, fov);
+	let mut perspective = display_math::perspective_matrix_full(1, 1, fov, znear, zfar);
 
 	let mut movement = MovementState {
 		forward: false,
@@ -199,22 +489,46 @@ fn run() -> Result<()> {
 		left: false,
 		right: false,
 		jumping: false,
-		can_jump: 0
 	};
 
+	let mut console = Console::new();
+	let mut console_lines: Vec<String> = Vec::new();
+
+	let mut focus = FocusState {
+		focused: true,
+		skip_next_motion: false,
+		capture_failed: false,
+	};
+	// We start focused, so capture the mouse; see the Focused event handler.
+	focus.capture_mouse((**display.gl_window()).window(), true);
+
+	// Where the character starts, and goes back to on respawning
+	let mut spawn_point = Vec3::from([-5.0, 0.0, 0.0]);
 	let mut character = physics::CharacterState::new(
-		Vec3::from([-5.0, 0.0, 0.0]),
+		spawn_point,
 		Vec3::from([0.0, 0.0, 0.0]),
-		CHAR_MAX_SPEED,
-		CHAR_DECEL,
-		CHAR_MAX_JUMP,
-		CHAR_GRAVITY);
+		config.physics.max_speed,
+		config.physics.decel,
+		config.physics.max_jump,
+		config.physics.gravity,
+		max_slope,
+		physics::EdgePolicy::Clamp)
+		.coyote_time(config.physics.coyote_time)
+		.jump_buffer(config.physics.jump_buffer);
 
-	let mut camera = display_math::Camera {
-		loc: character.loc().clone(),
+	let mut camera_mode = CameraMode::FirstPerson;
+	let mut show_overlay = false;
+	let mut debug_mode = DebugMode::Off;
+	let mut take_screenshot = false;
+	let mut respawn = false;
+	let mut paused = false;
+	let mut camera = Camera {
+		loc: *character.loc(),
 		dir: Vec3::from([1.0, 0.0, 0.0]),
+		sensitivity: display_math::DEFAULT_MOUSE_SENSITIVITY,
 	};
-	camera.loc[1] += 0.5;
+	let mut character_loc = *character.loc();
+	follow_character(&mut camera, camera_mode, character_loc, &floor);
 	floor.update_lod(&camera.loc);
 	// Main program loop
 	info!("Starting program loop...");
@@ -222,89 +536,385 @@ fn run() -> Result<()> {
 	while !exit_flag {
 		frame += 1;
 
+		if DAY_LENGTH > 0.0 {
+			let (sun, sky) = renderable::sun_and_sky(time_of_day);
+			directional_lights[0] = sun;
+			sky_color = sky;
+		}
+
 		let mut target = display.draw();
-		target.clear_color_and_depth((0.5, 0.5, 1.0, 1.0), 1.0);
+		target.clear_color_and_depth(sky_color, 1.0);
 
 		let view = display_math::view_matrix(
 			camera.loc,
 			camera.dir,
 			Vec3::from([0.0, 1.0, 0.0]));
 
+		let frame_params = debug_mode.draw_parameters(&params);
 		let renderstate = renderable::DefaultRenderState {
 			view: view,
 			perspective: perspective,
-			light_pos: light_pos,
-			light_color: light_color,
-			params: &params,
+			directional_lights: directional_lights.clone(),
+			point_lights: point_lights.clone(),
+			params: &frame_params,
 			program: &program,
+			debug_mode: debug_mode,
+			alpha: 1.0,
+			// The skybox is tinted by this and hazes into it at the horizon,
+			// so fogged surfaces match it as well as the clear color
+			fog_color: (sky_color.0, sky_color.1, sky_color.2),
+			fog_start: config.view.fog_start,
+			fog_end: config.view.fog_end,
 		};
 
-		for object in objects.iter() {
-			object.render(&renderstate, &mut target);
+		// Draw calls for the main view, to show on the HUD
+		let mut draw_calls = 0;
+		if let Some(ref skybox) = skybox {
+			skybox.render(&renderstate, &mut target);
+			draw_calls += 1;
+		}
+
+		// Skip objects which are entirely off-screen or too far away, and
+		// fade out those near the maximum draw distance
+		let frustum = Frustum::from_view_projection(view * perspective);
+		let (max_draw_distance, fade_width) =
+				(config.view.max_draw_distance, config.view.fade_width);
+		let mut opaque = Vec::new();
+		let mut fading = Vec::new();
+		for object in objects.iter().filter(|o| frustum.contains_aabb(o.bounds())) {
+			match object.draw_alpha(camera.loc, max_draw_distance, fade_width) {
+				Some(alpha) if alpha < 1.0 => fading.push((alpha, object)),
+				Some(_) => opaque.push(object),
+				None => (),
+			}
+		}
+		// The fully opaque objects are drawn in one instanced batch, if
+		// instancing works at all
+		let opaque_batch = match teapot_batch {
+			Some(_) if !opaque.is_empty() =>
+				batch_objects(&display, &gpu_teapot, opaque.iter().cloned()),
+			_ => None,
+		};
+		match opaque_batch {
+			Some(ref batch) => {
+				let instanced_state = renderable::DefaultRenderState {
+					directional_lights: renderstate.directional_lights.clone(),
+					point_lights: renderstate.point_lights.clone(),
+					program: &instanced_program,
+					.. renderstate
+				};
+				batch.render(&instanced_state, &mut target);
+				draw_calls += 1;
+			},
+			None => for object in opaque.iter() {
+				object.render(&renderstate, &mut target);
+				draw_calls += 1;
+			},
 		}
 		floor.render(&renderstate, &mut target);
+		draw_calls += floor.tile_count();
+		if camera_mode != CameraMode::FirstPerson {
+			let avatar = model::gpu::ModelInstance {
+				model: &gpu_teapot,
+				model_matrix: character_matrix(character_loc, camera.dir),
+			};
+			avatar.render(&renderstate, &mut target);
+			draw_calls += 1;
+		}
 
-		//TODO
-		let duration = last_time.elapsed().as_millis() as f32 / 1000.0;
-		let frames = frame % fps_message_interval;
-		let fps = frames as f32 / duration;
-		let hud_text = format!("fps: {:.1}, loc: {:.1},{:.1},{:.1}, dir: {:.1},{:.1},{:.1}",
-				fps,
+		// Fading objects blend over everything opaque, furthest (most
+		// transparent) first
+		fading.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(::std::cmp::Ordering::Equal));
+		let fade_params = DrawParameters {
+			blend: Blend::alpha_blending(),
+			.. frame_params.clone()
+		};
+		for &(alpha, object) in fading.iter() {
+			let fade_state = renderable::DefaultRenderState {
+				directional_lights: renderstate.directional_lights.clone(),
+				point_lights: renderstate.point_lights.clone(),
+				params: &fade_params,
+				alpha: alpha,
+				.. renderstate
+			};
+			object.render(&fade_state, &mut target);
+		}
+		draw_calls += fading.len();
+
+		if show_overlay {
+			// Top-down view of the world and the main camera's frustum in
+			// the bottom right corner
+			let (w, h) = target.get_dimensions();
+			let size = (h as f32 * OVERLAY_SIZE) as u32;
+			let viewport = Rect {
+				left: w.saturating_sub(size),
+				bottom: 0,
+				width: size,
+				height: size,
+			};
+			target.clear(Some(&viewport), Some((0.2, 0.2, 0.2, 1.0)), false, Some(1.0), None);
+			let overlay_params = DrawParameters {
+				viewport: Some(viewport),
+				.. frame_params.clone()
+			};
+			let loc = *character.loc();
+			let overlay_state = renderable::DefaultRenderState {
+				view: display_math::view_matrix(
+					loc + Vec3::from([0.0, OVERLAY_EXTENT, 0.0]),
+					Vec3::from([0.0, -1.0, 0.0]),
+					Vec3::from([0.0, 0.0, 1.0])),
+				perspective: display_math::orthographic_matrix(
+					-OVERLAY_EXTENT, OVERLAY_EXTENT, -OVERLAY_EXTENT, OVERLAY_EXTENT,
+					0.0, OVERLAY_EXTENT * 2.0),
+				params: &overlay_params,
+				directional_lights: renderstate.directional_lights.clone(),
+				point_lights: renderstate.point_lights.clone(),
+				fog_end: 0.0,
+				.. renderstate
+			};
+			let corners = display_math::frustum_corners(view *
+					display_math::perspective_matrix_full(w, h, fov, znear, OVERLAY_FRUSTUM_FAR));
+			let frustum_lines = try!{ FrustumDebugRenderable::new(
+					&display, &corners, [1.0, 1.0, 0.0], &debug_program) };
+			match teapot_batch {
+				Some(ref batch) => {
+					let instanced_state = renderable::DefaultRenderState {
+						directional_lights: overlay_state.directional_lights.clone(),
+						point_lights: overlay_state.point_lights.clone(),
+						program: &instanced_program,
+						.. overlay_state
+					};
+					batch.render(&instanced_state, &mut target);
+				},
+				None => for object in objects.iter() {
+					object.render(&overlay_state, &mut target);
+				},
+			}
+			floor.render(&overlay_state, &mut target);
+			frustum_lines.render(&overlay_state, &mut target);
+		}
+
+		let fps_text = format!("fps: {:.1}, frame ms: {:.1}/{:.1}/{:.1} min/avg/max",
+				frame_timer.fps(),
+				frame_timer.min_frame_ms(), frame_timer.avg_frame_ms(), frame_timer.max_frame_ms());
+		let hud_text = format!("loc: {:.1},{:.1},{:.1}, dir: {:.1},{:.1},{:.1}\n\
+				lod bias: {:.1}, lod zone: {:.2}, debug view: {}, draw calls: {}",
 				character.loc()[0], character.loc()[1], character.loc()[2],
-				camera.dir[0], camera.dir[1], camera.dir[2])
-				.to_string().into_bytes();
-		let hud = TextRenderable2d::new(hud_text, &font, 16);
-		hud.render(&renderstate, &mut target);
+				camera.dir[0], camera.dir[1], camera.dir[2],
+				floor.lod_bias(), floor.lod_zone_scale(), debug_mode.name(), draw_calls)
+				.to_string();
+		let mut overlay = Overlay::new();
+		// Draw text bigger on high DPI displays
+		let text_scale = (**display.gl_window()).window().get_hidpi_factor() as f32;
+		let fps = TextRenderable2d::new(&text_renderer, &fps_text, &font, 16)
+			.scale(text_scale)
+			.color(if frame_timer.fps() < LOW_FPS { LOW_FPS_COLOR } else { HUD_COLOR });
+		let hud = TextRenderable2d::new(&text_renderer, &hud_text, &font, 16)
+			.scale(text_scale)
+			.at(0, fps.char_size().1);
+		overlay.add(HUD_LAYER, OverlayElement::Text(fps));
+		overlay.add(HUD_LAYER, OverlayElement::Text(hud));
+
+		let legend = TextRenderable2d::new(&text_renderer, CONTROLS_LEGEND, &font, 16)
+			.scale(text_scale);
+		let legend_lines = CONTROLS_LEGEND.lines().count() as u32;
+		let legend_height = legend.char_size().1 * legend_lines;
+		let legend = legend.at(0, target.get_dimensions().1.saturating_sub(legend_height));
+		overlay.add(HUD_LAYER, OverlayElement::Text(legend));
 
+		if paused {
+			let text = TextRenderable2d::new(&text_renderer, "PAUSED", &font, 16)
+				.scale(PAUSED_SCALE * text_scale);
+			let (w, h) = target.get_dimensions();
+			let (char_w, char_h) = text.char_size();
+			let text = text.at(
+				(w / 2).saturating_sub(char_w * 3),
+				(h / 2).saturating_sub(char_h / 2));
+			overlay.add(HUD_LAYER, OverlayElement::Text(text));
+		}
+
+		if console.is_open() {
+			// Drop down from the top of the window, over the HUD
+			let console_text = console.text(CONSOLE_LINES);
+			let text = TextRenderable2d::new(&text_renderer, &console_text, &font, 16)
+				.scale(text_scale);
+			let (w, h) = target.get_dimensions();
+			let height = text.char_size().1 * CONSOLE_LINES as u32;
+			let background = Rect {
+				left: 0,
+				bottom: h.saturating_sub(height),
+				width: w,
+				height: height,
+			};
+			overlay.add(CONSOLE_LAYER, OverlayElement::Text(text));
+			overlay.add(CONSOLE_PANEL_LAYER, OverlayElement::Panel(background, (0.0, 0.0, 0.0, 1.0)));
+		}
+		overlay.render(&renderstate, &mut target);
+
+		// Copy the frame for a screenshot before it's swapped away
+		let screenshot = if take_screenshot {
+			take_screenshot = false;
+			Some(read_frame(&display, &target))
+		} else {
+			None
+		};
 		target.finish().unwrap();
 
+		if let Some(frame) = screenshot {
+			match frame.and_then(|frame| save_screenshot(&frame)) {
+				Ok(path) => info!("Saved screenshot to {}", path),
+				Err(e) => error!("Could not save screenshot: {}", e),
+			}
+		}
+
 		// Handle events
 		event_loop.poll_events(|ev| {
 			match ev {
+				// Typing in the console
+				Event::WindowEvent{event: WindowEvent::ReceivedCharacter('`'), ..} => {
+					console.toggle();
+					// Stop moving, or we'd keep going while typing
+					movement.stop();
+				},
+				Event::WindowEvent{event: WindowEvent::ReceivedCharacter(c), ..} =>
+					if console.is_open() {
+						if let Some(line) = console.handle_char(c) {
+							console_lines.push(line);
+						}
+					},
+				// Keys go to the console while it's open
+				Event::DeviceEvent{event: DeviceEvent::Key(_), ..} if console.is_open() => (),
 				// Key presses:
 				Event::DeviceEvent{event: DeviceEvent::Key(KeyboardInput{
-						virtual_keycode: Some(keycode), state, ..}), ..} =>
+						virtual_keycode: Some(keycode), state, ..}), ..} => {
+					if !paused {
+						input::update_movement_from_key(&bindings, &mut movement, keycode, state);
+					}
 					match (keycode, state) {
-						(VirtualKeyCode::Q, ElementState::Released) |
 						(VirtualKeyCode::Escape, ElementState::Released) =>
 							exit_flag = true,
-						(VirtualKeyCode::W, ElementState::Pressed) =>
-							movement.forward = true,
-						(VirtualKeyCode::W, ElementState::Released) =>
-							movement.forward = false,
-						(VirtualKeyCode::A, ElementState::Pressed) =>
-							movement.left = true,
-						(VirtualKeyCode::A, ElementState::Released) =>
-							movement.left = false,
-						(VirtualKeyCode::S, ElementState::Pressed) =>
-							movement.backward = true,
-						(VirtualKeyCode::S, ElementState::Released) =>
-							movement.backward = false,
-						(VirtualKeyCode::D, ElementState::Pressed) =>
-							movement.right = true,
-						(VirtualKeyCode::D, ElementState::Released) =>
-							movement.right = false,
-						(VirtualKeyCode::Space, ElementState::Pressed) =>
-							movement.jumping = true,
-						(VirtualKeyCode::Space, ElementState::Released) => {
-							movement.jumping = false;
-							movement.can_jump = 0;
+						(keycode, ElementState::Released) if keycode == bindings.quit =>
+							exit_flag = true,
+						(VirtualKeyCode::LBracket, ElementState::Released) => {
+							let bias = floor.lod_bias() - LOD_BIAS_STEP;
+							floor.set_lod_bias(bias);
+						},
+						(VirtualKeyCode::RBracket, ElementState::Released) => {
+							let bias = floor.lod_bias() + LOD_BIAS_STEP;
+							floor.set_lod_bias(bias);
+						},
+						(VirtualKeyCode::Minus, ElementState::Released) => {
+							let scale = (floor.lod_zone_scale() / 2.0).max(MIN_LOD_ZONE_SCALE);
+							floor.set_lod_zone_scale(scale);
 						},
+						(VirtualKeyCode::Equals, ElementState::Released) => {
+							let scale = (floor.lod_zone_scale() * 2.0).min(MAX_LOD_ZONE_SCALE);
+							floor.set_lod_zone_scale(scale);
+						},
+						(VirtualKeyCode::P, ElementState::Released) => {
+							paused = !paused;
+							// Don't keep moving with keys held when we paused
+							movement.stop();
+							// Let the mouse go while paused
+							let capture = focus.focused && !paused;
+							focus.capture_mouse((**display.gl_window()).window(), capture);
+							focus.skip_next_motion = !paused;
+						},
+						(VirtualKeyCode::F12, ElementState::Pressed) =>
+							take_screenshot = true,
+						(VirtualKeyCode::R, ElementState::Released) =>
+							respawn = true,
+						(VirtualKeyCode::F5, ElementState::Released) => {
+							spawn_point = *character.loc();
+							info!("Spawn point set to {}, {}, {}",
+								spawn_point[0], spawn_point[1], spawn_point[2]);
+						},
+						(VirtualKeyCode::F, ElementState::Released) =>
+							show_overlay = !show_overlay,
+						(VirtualKeyCode::F3, ElementState::Released) =>
+							debug_mode = debug_mode.next(),
+						(VirtualKeyCode::F6, ElementState::Released) =>
+							match dump_terrain(&floor) {
+								Ok(tiles) => info!("Saved {} terrain tiles to {}", tiles, DUMP_DIR),
+								Err(e) => error!("Could not save terrain tiles: {}", e),
+							},
+						(VirtualKeyCode::V, ElementState::Released) =>
+							camera_mode = toggle_third_person(camera_mode),
+						(VirtualKeyCode::Tab, ElementState::Released) =>
+							camera_mode = match camera_mode {
+								CameraMode::Orbit(_) => CameraMode::FirstPerson,
+								_ => CameraMode::Orbit(OrbitCamera {
+									center: *character.loc(),
+									radius: ORBIT_RADIUS,
+									yaw: camera.yaw(),
+									pitch: camera.pitch(),
+								}),
+							},
 						_ => (),
-					},
-				//FIXME: This captures mouse events even when unfocused, which
-				//	is disconcerting.
+					}
+				},
+				// Device events arrive even when unfocused, so only mouse-look
+				// while we have focus and aren't paused.
 				Event::DeviceEvent{event:DeviceEvent::MouseMotion{delta: (x, y)}, ..} =>
-					display_math::handle_mouse_move(
-							// gl_window returns a Ref (Deref) of a Takeable
-							// (also a Deref) of a context object that contains
-							// the actual window. Somebody needs to tell these
-							// people that "three star C programmer" really,
-							// really isn't a compliment.
-							(**display.gl_window()).window(), &mut camera, x, y).unwrap(),
+					if focus.skip_next_motion {
+						// The first delta after regaining focus includes
+						// however far the cursor moved while we were away.
+						focus.skip_next_motion = false;
+					} else if focus.focused && !paused {
+						match camera_mode {
+							CameraMode::Orbit(ref mut orbit) => {
+								if let Some((yaw, pitch)) =
+										display_math::mouse_look_delta(x, y, camera.sensitivity) {
+									*orbit = orbit.rotate(yaw, pitch);
+								}
+							},
+							_ => display_math::handle_mouse_move(&mut camera, x, y),
+						}
+					},
+				// Like mouse motion, only while we have focus
+				Event::DeviceEvent{event: DeviceEvent::Button{button, state}, ..} =>
+					if focus.focused && !paused && !console.is_open() {
+						display_math::handle_mouse_button(
+							button, state, &camera, &objects, perspective);
+					},
+				// Only capture the mouse while we have focus and aren't
+				// paused, so it's usable in other windows. Losing focus also
+				// pauses, so the character doesn't keep falling while we're
+				// away.
+				Event::WindowEvent{event: WindowEvent::Focused(focused), ..} => {
+					focus.focused = focused;
+					focus.skip_next_motion = focused;
+					if !focused && !paused {
+						paused = true;
+						movement.stop();
+					}
+					// gl_window returns a Ref (Deref) of a Takeable (also a
+					// Deref) of a context object that contains the actual
+					// window. Somebody needs to tell these people that "three
+					// star C programmer" really, really isn't a compliment.
+					let gl_window = display.gl_window();
+					focus.capture_mouse((**gl_window).window(), focused && !paused);
+				},
+				// Scrolling is reported both as a device event and, while we
+				// have focus, a window event. Only handle the latter, or we'd
+				// zoom twice as fast and while unfocused.
+				Event::DeviceEvent{event: DeviceEvent::MouseWheel{..}, ..} => (),
+				Event::WindowEvent{event: WindowEvent::MouseWheel{delta, ..}, ..} => {
+					let (_, delta_y) = display_math::scroll_lines(delta);
+					match camera_mode {
+						CameraMode::Orbit(ref mut orbit) =>
+							*orbit = orbit.zoom(delta_y * ORBIT_ZOOM_SENSITIVITY),
+						_ => {
+							display_math::handle_scroll(&mut fov, delta_y, FOV_SENSITIVITY);
+							perspective = display_math::perspective_matrix_full(
+								window_size.0, window_size.1, fov, znear, zfar);
+						},
+					}
+				},
 				Event::WindowEvent{event: WindowEvent::Resized(size), ..} => {
-					let (w, h) = size.into();
-					perspective = display_math::perspective_matrix(w, h, fov);
+					window_size = size.into();
+					perspective = display_math::perspective_matrix_full(
+						window_size.0, window_size.1, fov, znear, zfar);
 				},
 				Event::WindowEvent{event: WindowEvent::CloseRequested, ..} =>
 					exit_flag = true,
@@ -312,24 +922,91 @@ fn run() -> Result<()> {
 			}
 		});
 
-		character.do_char_movement(&camera.dir, &mut movement, &floor);
+		// Run console commands
+		for line in console_lines.drain(..) {
+			match console::parse_command(&line) {
+				Ok(Command::Teleport(x, y, z)) => {
+					character.teleport(Vec3::from([x, y, z]));
+					console.print(&format!("Teleported to {}, {}, {}", x, y, z));
+				},
+				Ok(Command::Fov(degrees)) => {
+					fov = degrees.to_radians()
+						.max(display_math::MIN_FOV).min(display_math::MAX_FOV);
+					perspective = display_math::perspective_matrix_full(
+						window_size.0, window_size.1, fov, znear, zfar);
+					console.print(&format!("Field of view is {:.1} degrees", fov.to_degrees()));
+				},
+				Ok(Command::Spawn) => {
+					objects.push(model::gpu::ModelInstance {
+						model: &gpu_teapot,
+						model_matrix: character_matrix(*character.loc(), camera.dir),
+					});
+					teapot_batch = batch_objects(&display, &gpu_teapot, &objects);
+					console.print(&format!("Spawned teapot {}", objects.len() - 1));
+				},
+				Ok(Command::Toggle(ref view)) if view == "overlay" =>
+					show_overlay = !show_overlay,
+				Ok(Command::Toggle(ref view)) if view == "thirdperson" =>
+					camera_mode = toggle_third_person(camera_mode),
+				Ok(Command::Get(ref name)) if name == "loc" => {
+					let loc = character.loc();
+					console.print(&format!("loc: {}, {}, {}", loc[0], loc[1], loc[2]));
+				},
+				Ok(Command::Get(ref name)) if name == "fov" =>
+					console.print(&format!("fov: {:.1} degrees", fov.to_degrees())),
+				Ok(Command::Get(ref name)) if name == "lod" =>
+					console.print(&format!("lod bias: {}, lod zone: {}",
+						floor.lod_bias(), floor.lod_zone_scale())),
+				Ok(Command::Toggle(name)) | Ok(Command::Get(name)) =>
+					console.print(&format!("Unknown name: {}", name)),
+				Ok(Command::Help) => console.print(console::HELP),
+				Err(e) => console.print(&e),
+			}
+		}
+
+		// Run as many physics ticks as have elapsed since the last frame
+		let frame_duration = last_frame_time.elapsed();
+		last_frame_time = Instant::now();
+		frame_timer.tick(last_frame_time);
+		let frame_secs = frame_duration.as_secs() as f32 +
+				frame_duration.subsec_nanos() as f32 / 1_000_000_000.0;
+		// While paused, the world stands still
+		if !paused {
+			if DAY_LENGTH > 0.0 {
+				time_of_day = (time_of_day + frame_secs / DAY_LENGTH) % 1.0;
+			}
+			for _ in 0..timestep.advance(frame_secs) {
+				character.do_char_movement(&camera.dir, &movement, &floor, timestep.tick());
+			}
+			// Render between the last two physics states, so movement is smooth
+			// even when frames and ticks don't line up
+			character_loc = character.interpolated_loc(timestep.alpha());
 
-		// Update camera
-		camera.loc = character.loc().clone();
-		camera.loc[1] += 0.5;
-		floor.update_lod(&camera.loc);
+			// Update camera
+			follow_character(&mut camera, camera_mode, character_loc, &floor);
+			floor.update_lod(&camera.loc);
+		}
+
+		// Respawn on request, or after falling out of the world
+		if respawn || character.loc()[1] < config.physics.kill_plane {
+			respawn = false;
+			character.teleport(spawn_point);
+			// Move the camera and terrain detail now, even while paused, so
+			// the next frame isn't drawn from where we were
+			character_loc = spawn_point;
+			follow_character(&mut camera, camera_mode, character_loc, &floor);
+			floor.update_lod(&camera.loc);
+		}
 
 		// Wait for end of frame
 		// We enabled vsync when creating the window, so this happens automatically.
 
 		if frame % fps_message_interval == 0 {
-			let duration = last_time.elapsed().as_millis() as f32 / 1000.0;
-			let fps = fps_message_interval as f32 / duration;
-			last_time = Instant::now();
-			info!("Rendered {} frames in {} seconds ({} FPS)",
-				fps_message_interval,
-				duration,
-				fps);
+			info!("{:.1} FPS, frame times {:.1}/{:.1}/{:.1} ms min/avg/max",
+				frame_timer.fps(),
+				frame_timer.min_frame_ms(),
+				frame_timer.avg_frame_ms(),
+				frame_timer.max_frame_ms());
 		}
 	}
 
@@ -351,18 +1028,181 @@ pub struct MovementState {
 	pub right: bool,
 	/// True if this character is attempting to jump.
 	pub jumping: bool,
-	/// Number of frames this character can continue to accelerate while
-	/// jumping.
-	pub can_jump: u8
 }
 
-/// Configure logging.
-fn init_log() {
+impl MovementState {
+	/// Stop all movement, as if every movement key were released.
+	pub fn stop(&mut self) {
+		self.forward = false;
+		self.backward = false;
+		self.left = false;
+		self.right = false;
+		self.jumping = false;
+	}
+}
+
+/// Struct to hold window focus state.
+#[derive(Debug)]
+pub struct FocusState {
+	/// True if the window currently has input focus.
+	pub focused: bool,
+	/// True if the next mouse motion event should be ignored, e.g. because
+	/// focus was just regained.
+	pub skip_next_motion: bool,
+	/// True once capturing the mouse has failed, so the failure is only
+	/// logged once.
+	pub capture_failed: bool,
+}
+
+impl FocusState {
+	/// Capture or release the mouse; see `display_math::set_mouse_capture`.
+	///
+	/// Some platforms (notably some Wayland compositors) can't grab the
+	/// cursor. Mouse look still works from relative motion events there, just
+	/// without confining the cursor, so failures are only logged, once.
+	pub fn capture_mouse(&mut self, window: &Window, capture: bool) {
+		if let Err(e) = display_math::set_mouse_capture(window, capture) {
+			if !self.capture_failed {
+				warn!("{}; mouse look will still work, but the cursor can leave the window", e);
+				self.capture_failed = true;
+			}
+		}
+	}
+}
+
+/// Load the skybox faces from `paths` and upload them to the GPU.
+fn load_skybox<'a>(display: &Display,
+		program: &'a Program,
+		paths: &[&str]) -> Result<renderable::Skybox<'a>> {
+	let mut faces = Vec::with_capacity(paths.len());
+	for &path in paths.iter() {
+		let file = try!{ File::open(path)
+				.chain_err(|| format!("Could not load skybox face {}", path)) };
+		let face = try!{ model::disk::load_texture_guessing_format(&mut BufReader::new(file))
+				.chain_err(|| format!("Could not load skybox face {}", path)) };
+		faces.push(try!{ Texture2d::new(display, face)
+				.chain_err(|| "Could not upload skybox to GPU") });
+	}
+	renderable::Skybox::new(display, faces, program)
+}
+
+/// Move the camera to follow the character, at `character`, according to the
+/// camera mode.
+///
+/// In third-person mode, the camera is kept above the ground so backing up a
+/// slope doesn't put it underground.
+fn follow_character(camera: &mut Camera,
+		mode: CameraMode,
+		character: Vec3<f32>,
+		heightmap: &Heightmap<f32>) {
+	match mode {
+		CameraMode::FirstPerson =>
+			camera.loc = character + Vec3::from([0.0, EYE_HEIGHT, 0.0]),
+		CameraMode::ThirdPerson { distance, height } => {
+			let mut loc = display_math::boom_position(
+					character, camera.dir, distance, height);
+			let ground = physics::ground_height(heightmap, &loc) + CAMERA_CLEARANCE;
+			if loc[1] < ground {
+				loc[1] = ground;
+			}
+			camera.loc = loc;
+		},
+		CameraMode::Orbit(orbit) => {
+			let orbiting = orbit.to_camera();
+			camera.loc = orbiting.loc;
+			camera.dir = orbiting.dir;
+		},
+	}
+}
+
+/// Get the camera mode the third-person toggle switches to from `mode`.
+fn toggle_third_person(mode: CameraMode) -> CameraMode {
+	match mode {
+		CameraMode::FirstPerson => CameraMode::ThirdPerson {
+			distance: THIRD_PERSON_DISTANCE,
+			height: THIRD_PERSON_HEIGHT,
+		},
+		CameraMode::ThirdPerson { .. } => CameraMode::FirstPerson,
+		CameraMode::Orbit(orbit) => CameraMode::Orbit(orbit),
+	}
+}
+
+/// Batch instances of a model to draw with one draw call, if we can.
+fn batch_objects<'a, 'b, 'c: 'b, I>(display: &Display,
+		model: &'a model::gpu::Model,
+		objects: I)
+		-> Option<model::gpu::ModelInstanceBatch<'a>>
+		where I: IntoIterator<Item = &'b model::gpu::ModelInstance<'c>> {
+	let matrices: Vec<Mat4<f32>> = objects.into_iter().map(|o| o.model_matrix).collect();
+	match model::gpu::ModelInstanceBatch::new(display, model, &matrices) {
+		Ok(batch) => Some(batch),
+		Err(e) => {
+			warn!("Could not batch instances ({}), drawing them one at a time", e);
+			None
+		},
+	}
+}
+
+/// Start copying `frame`, which must not be finished yet, back from the GPU.
+fn read_frame(display: &Display, frame: &Frame) -> Result<PixelBuffer<(u8, u8, u8, u8)>> {
+	let (width, height) = frame.get_dimensions();
+	let copy = try!{ Texture2d::empty(display, width, height)
+			.chain_err(|| "Could not copy frame") };
+	frame.blit_whole_color_to(
+		&copy.as_surface(),
+		&BlitTarget { left: 0, bottom: 0, width: width as i32, height: height as i32 },
+		MagnifySamplerFilter::Nearest);
+	Ok(copy.read_to_pixel_buffer())
+}
+
+/// Save a frame copied by `read_frame` as `screenshot_<timestamp>.png` in the
+/// current directory, and return the file's name.
+fn save_screenshot(pixels: &PixelBuffer<(u8, u8, u8, u8)>) -> Result<String> {
+	use chrono::offset::Local;
+	let frame: RawImage2d<u8> = try!{
+		pixels.read_as_texture_2d()
+			.map_err(|e| Error::from(format!("Could not read frame: {:?}", e)))
+	};
+	let pixels = try!{
+		image::RgbaImage::from_raw(frame.width, frame.height, frame.data.into_owned())
+			.ok_or(Error::from("Frame has the wrong number of pixels"))
+	};
+	// OpenGL's rows go from the bottom up
+	let pixels = image::imageops::flip_vertical(&pixels);
+	let path = format!("screenshot_{}.png", Local::now().format("%Y%m%d-%H%M%S%.3f"));
+	try!{ pixels.save(&path).chain_err(|| "Could not write screenshot") };
+	Ok(path)
+}
+
+/// Save the floor's tiles, as drawn, to `terrain-tile-N.obj` files in
+/// `DUMP_DIR`, and return how many there were.
+fn dump_terrain(floor: &SimpleHeightmap) -> Result<usize> {
+	try!{ fs::create_dir_all(DUMP_DIR)
+			.chain_err(|| format!("Could not create {}", DUMP_DIR)) };
+	let tiles = try!{ floor.tile_models() };
+	for (i, tile) in tiles.iter().enumerate() {
+		let path = Path::new(DUMP_DIR).join(format!("terrain-tile-{}.obj", i));
+		try!{ model::disk::save_model_to_path(tile, &path) };
+	}
+	Ok(tiles.len())
+}
+
+/// Compute the model matrix for the character model at `loc`, facing along
+/// `dir` on the XZ plane.
+fn character_matrix(loc: Vec3<f32>, dir: Vec3<f32>) -> Mat4<f32> {
+	let yaw = dir[2].atan2(dir[0]);
+	Mat4::trs(loc,
+		Mat4::rotation_y(-yaw),
+		Vec3::from([CHARACTER_SCALE, CHARACTER_SCALE, CHARACTER_SCALE]))
+}
+
+/// Configure logging, showing messages up to `level`.
+fn init_log(level: LevelFilter) {
 	use chrono::DateTime;
 	use chrono::offset::Utc;
 	use std::time::SystemTime;
 	Builder::new()
-		.filter(None, LevelFilter::Info)
+		.filter(None, level)
 		.format(|buf, record| {
 			let time: DateTime<Utc> = SystemTime::now().into();
 			write!(buf, "[{}] [{} {}:{}] [{}] {}\n",
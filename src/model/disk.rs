@@ -1,158 +1,880 @@
-//! Functions to load models from disk.
+//! Functions to load models from disk, and save them back.
 //!
 //! This module supports geometry and materials in wavefront `.obj` and `.mtl`
-//! formats, respectively, and textures in `.png`.
+//! formats, respectively, and textures in `.png`, `.jpg` and other common
+//! image formats. Vertex colors in `.obj` files are read from the common
+//! `v x y z r g b` extension.
 
 use errors::*;
 use image;
-use model::{mem, Vertex};
+use linear_algebra::Vec3;
+use model::{mem, Vertex, WHITE};
+use std::cmp::min;
 use std::collections::HashMap;
 use std::fs::File;
-use std::io;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 use wavefront_obj::{obj, mtl};
 
-/// Load a model from a wavefront `.obj` file.
+/// Load a model from a wavefront `.obj` file containing a single object.
 ///
-/// This will follow paths to `.mtl` material libraries and `.png` textures,
-/// returning `Err` if it cannot find them.
-pub fn load_model(read: &mut io::Read) -> Result<(mem::Geometry, mem::Material)> {
+/// This will follow paths to `.mtl` material libraries and image textures,
+/// relative to `base_dir` (usually the directory the `.obj` is in) or the
+/// working directory if it's `None`, returning `Err` if it cannot find them.
+/// Files with more than one object are also an error; see `load_models`.
+///
+/// Objects without a material, including all those in files without a
+/// material library, get `mem::default_mat()`.
+pub fn load_model(read: &mut io::Read, base_dir: Option<&Path>)
+		-> Result<(mem::Geometry, mem::Material)> {
+	let mut models = try!{ load_models(read, base_dir) };
+	if models.len() != 1 {
+		bail!("Expected one object in model file, found {}", models.len());
+	}
+	Ok(models.pop().unwrap())
+}
+
+/// Load every object from a wavefront `.obj` file, such as a scene exported
+/// from a modelling tool, with its material.
+///
+/// Paths are followed as in `load_model`. The material library is only
+/// loaded once, however many objects use it.
+pub fn load_models(read: &mut io::Read, base_dir: Option<&Path>)
+		-> Result<Vec<(mem::Geometry, mem::Material)>> {
+	let (objects, mat_path) = try!{ load_geometries(read) };
+	let mats = match mat_path {
+		Some(mat_path) => try!{ load_mats_from_path(&resolve_path(base_dir, &mat_path)) },
+		None => HashMap::new(),
+	};
+
+	let default_mat = mem::default_mat();
+	Ok(objects.into_iter().map(|(geometry, mat_name)| {
+		let mat = match mat_name {
+			Some(ref m) => mats.get(m).cloned().unwrap_or_else(|| {
+				error!("Missing material: {:?}", m);
+				default_mat.clone() }),
+			None => default_mat.clone(),
+		};
+		(geometry, mat)
+	}).collect())
+}
+
+/// Load a model from the wavefront `.obj` file at `path`.
+///
+/// Material and texture paths are relative to the directory of the file
+/// which refers to them, not the working directory.
+pub fn load_model_from_path(path: &Path) -> Result<(mem::Geometry, mem::Material)> {
+	let mut file = try!{ File::open(path)
+			.chain_err(|| format!("I/O error loading model {}", path.display())) };
+	load_model(&mut file, path.parent())
+		.chain_err(|| format!("Could not load model {}", path.display()))
+}
+
+/// Resolve a path given in a model or material file against `base_dir`, or
+/// the working directory if it's `None`.
+pub fn resolve_path(base_dir: Option<&Path>, path: &str) -> PathBuf {
+	match base_dir {
+		Some(dir) => dir.join(path),
+		None => PathBuf::from(path),
+	}
+}
+
+/// Load the geometry of the last object in a wavefront `.obj` file, without
+/// its material.
+///
+/// Returns the geometry, the path to the `.mtl` material library as given in
+/// the file, if it has one, and the name of the object's material, if it has
+/// one.
+pub fn load_geometry(read: &mut io::Read)
+		-> Result<(mem::Geometry, Option<String>, Option<String>)> {
+	let (mut objects, mat_path) = try!{ load_geometries(read) };
+	let (geometry, mat_name) = try!{ objects.pop()
+			.ok_or(Error::from("No objects in object file")) };
+	Ok( (geometry, mat_path, mat_name) )
+}
+
+/// Load the geometry of each object with faces in a wavefront `.obj` file,
+/// without materials.
+///
+/// Returns each object's geometry and the name of its material, if it has
+/// one, in the order they're in the file, and the path to the `.mtl` material
+/// library as given in the file, if it has one.
+pub fn load_geometries(read: &mut io::Read)
+		-> Result<(Vec<(mem::Geometry, Option<String>)>, Option<String>)> {
 	let mut object_str = String::new();
 	try!{
 		read.read_to_string(&mut object_str)
 			.chain_err(|| "I/O error loading model")
 	};
-	let mut loaded_object = try!{
+	// wavefront_obj doesn't understand vertex colors
+	let (object_str, colors) = split_vertex_colors(&object_str);
+	let loaded_object = try!{
 		obj::parse(object_str)
 			.map_err(|e| { Error::from(format!("{:?}", e)) } )
 	};
 
-	let mat_path = try!{
-		loaded_object.material_library
-			.ok_or(Error::from("Object lacks material specification (usemtl)"))
-	};
-	let mut mat_file = try!{
-		File::open(mat_path)
-			.chain_err(|| "I/O error loading materials")
-	};
-	let mats = try!{
-		load_mats(&mut mat_file)
-			.chain_err(|| "Could not load materials")
-	};
+	let mat_path = loaded_object.material_library;
 
-	let object = try!{
-		loaded_object.objects.pop()
-			.ok_or(Error::from("No objects in object file"))
-	};
+	let mut objects = Vec::with_capacity(loaded_object.objects.len());
+	let mut first_position = 0;
+	for object in loaded_object.objects.iter() {
+		// Objects number their positions from zero, following on from the
+		// previous object's in the file
+		let start = min(first_position, colors.len());
+		let end = min(first_position + object.vertices.len(), colors.len());
+		first_position += object.vertices.len();
+		if object.geometry.iter().all(|geom| geom.shapes.is_empty()) {
+			continue;
+		}
 
-	let mut vertices = object.vertices.iter()
-		.map(|v| Vertex{position: [v.x as f32, v.y as f32, v.z as f32],
-			normal: [0.0, 1.0, 0.0],
-			tex_uv: [0.0, 0.0] })
-		.collect::<Vec<Vertex>>();
-	let normals = object.normals.iter()
-		.map(|n| [n.x as f32, n.y as f32, n.z as f32])
-		.collect::<Vec<_>>();
-	let tex_uv = object.tex_vertices.iter()
-		//TODO: Is a texture w a common or useful thing?
-		.map(|t| [t.u as f32, t.v as f32])
-		.collect::<Vec<_>>();
+		// The last material named wins
+		let mat_name = object.geometry.iter()
+			.filter_map(|geom| geom.material_name.clone())
+			.last();
+		objects.push( (build_geometry(object, &colors[start..end]), mat_name) );
+	}
+
+	if objects.is_empty() {
+		bail!("No objects in object file");
+	}
+	Ok( (objects, mat_path) )
+}
+
+/// Remove vertex colors from `v x y z r g b` lines of a wavefront object.
+///
+/// Returns the object without colors, and the color of each `v` line in the
+/// file, in order; lines without colors are white.
+fn split_vertex_colors(source: &str) -> (String, Vec<[f32; 3]>) {
+	let mut stripped = String::with_capacity(source.len());
+	let mut colors = Vec::new();
+	for line in source.lines() {
+		let words: Vec<&str> = line.split_whitespace().collect();
+		if words.first() == Some(&"v") {
+			let color: Vec<f32> = words.iter().skip(4).filter_map(|w| w.parse().ok()).collect();
+			if color.len() == 3 {
+				colors.push([color[0], color[1], color[2]]);
+				stripped.push_str(&words[..4].join(" "));
+				stripped.push('\n');
+				continue;
+			}
+			colors.push(WHITE);
+		}
+		stripped.push_str(line);
+		stripped.push('\n');
+	}
+	(stripped, colors)
+}
+
+/// Build in-memory geometry from a wavefront object.
+///
+/// Each distinct combination of position, texture UV and normal in the
+/// object's faces becomes a separate vertex, so vertices which share a position
+/// but not UVs (like along a texture seam) keep their own UVs. Vertices without
+/// normals get smooth normals from `mem::Geometry::recompute_normals`.
+///
+/// `colors` are the colors of the object's positions; positions past its end
+/// are white.
+///
+/// Quads and other convex polygons arrive already split into triangles by
+/// `wavefront_obj`; points and lines are skipped.
+fn build_geometry(object: &obj::Object, colors: &[[f32; 3]]) -> mem::Geometry {
+	let mut vertices: Vec<Vertex> = Vec::new();
 	let mut indices: Vec<u16> = Vec::new();
-	let mut mat: mem::Material = mem::default_mat();
-	for geom in object.geometry {
-		//TODO: Figure out the ownership to avoid the unneeded clone
-		mat = match geom.material_name {
-			Some(m) => mats.get(&m).unwrap_or_else(|| {
-				error!("Missing material: {:?}", &m);
-				&mat }).clone(),
-			None => mat,
-		};
-		for shape in geom.shapes {
+	let mut vertex_indices: HashMap<obj::VTNIndex, u16> = HashMap::new();
+	// The normal given for each vertex in the file, if any
+	let mut normals: Vec<Option<[f32; 3]>> = Vec::new();
+	let position = |i: usize| {
+		let v = object.vertices[i];
+		Vec3::from([v.x as f32, v.y as f32, v.z as f32])
+	};
+
+	for geom in object.geometry.iter() {
+		for shape in geom.shapes.iter() {
 			match shape.primitive {
 				obj::Primitive::Triangle(a, b, c) => {
-					//FIXME: wavefront obj is excessively flexible about
-					// indexing normals and texture UV. If anybody actually
-					// uses those capabilities, this will break silently.
-					indices.push(a.0 as u16);
-					if let Some(i) = a.1 { vertices[a.0].tex_uv = tex_uv[i]; }
-					if let Some(i) = a.2 { vertices[a.0].normal = normals[i]; }
-					indices.push(b.0 as u16);
-					if let Some(i) = b.1 { vertices[b.0].tex_uv = tex_uv[i]; }
-					if let Some(i) = b.2 { vertices[b.0].normal = normals[i]; }
-					indices.push(c.0 as u16);
-					if let Some(i) = c.1 { vertices[c.0].tex_uv = tex_uv[i]; }
-					if let Some(i) = c.2 { vertices[c.0].normal = normals[i]; }
+					for &corner in [a, b, c].iter() {
+						let index = *vertex_indices.entry(corner).or_insert_with(|| {
+							let normal = corner.2.map(|i| object.normals[i])
+								.map(|n| [n.x as f32, n.y as f32, n.z as f32]);
+							normals.push(normal);
+							vertices.push(Vertex {
+								position: position(corner.0).into(),
+								normal: normal.unwrap_or([0.0, 1.0, 0.0]),
+								tangent: [1.0, 0.0, 0.0],
+								//TODO: Is a texture w a common or useful thing?
+								tex_uv: corner.1.map(|i| object.tex_vertices[i])
+									.map(|t| [t.u as f32, t.v as f32])
+									.unwrap_or([0.0, 0.0]),
+								color: colors.get(corner.0).cloned().unwrap_or(WHITE),
+							});
+							(vertices.len() - 1) as u16
+						});
+						indices.push(index);
+					}
 				}
 				x => warn!("Unsupported primitive: {:?}", x)
 			}
 		}
 	}
 
-	Ok( (mem::Geometry { vertices: vertices, indices: indices, }, mat) )
+	let mut geometry = mem::Geometry::new(vertices, indices);
+	geometry.recompute_normals();
+	for (vertex, normal) in geometry.vertices.iter_mut().zip(normals) {
+		if let Some(normal) = normal {
+			vertex.normal = normal;
+		}
+	}
+	geometry.recompute_tangents();
+	geometry
 }
 
 /// Load materials from a wavefront `.mtl` file.
 ///
-/// This will follow paths to `.png` textures, returning `Err` if it cannot find
-/// them.
-pub fn load_mats(read: &mut io::Read) -> Result<HashMap<String, mem::Material>> {
+/// This will follow paths to image textures, relative to `base_dir` (usually
+/// the directory the `.mtl` is in) or the working directory if it's `None`,
+/// returning `Err` if it cannot find them.
+/// Materials without a texture (`map_Kd`) get a 1x1 texture of their diffuse
+/// color instead, and a white diffuse color so it isn't applied twice.
+/// Materials without a specular exponent (`Ns`) or emissive color (`Ke`) get
+/// `mem::DEFAULT_SHININESS` and black.
+pub fn load_mats(read: &mut io::Read, base_dir: Option<&Path>)
+		-> Result<HashMap<String, mem::Material>> {
 	let mut mat_str = String::new();
 	try!{
 		read.read_to_string(&mut mat_str)
 			.chain_err(|| "I/O error loading materials")
 	};
 	let loaded_mats = try!{
-		mtl::parse(mat_str)
+		mtl::parse(default_missing_shininess(&mat_str))
 			.map_err(|e| { Error::from(format!("{:?}", e)) } )
 	};
 	let mut mats = HashMap::with_capacity(loaded_mats.materials.len());
 	for mat in loaded_mats.materials {
-		let tex_path = try!{
-			mat.uv_map
-				.ok_or(Error::from("Material lacks texture specification (map_Kd)"))
-		};
-		let tex_file = try!{
-			File::open(tex_path)
-				.chain_err(|| "I/O error loading texture")
-		};
-		let texture = try!{
-			load_texture(&mut io::BufReader::new(tex_file))
-				.chain_err(|| "Could not load texture")
+		let diffuse = color_conv(mat.color_diffuse);
+		let (texture, diffuse) = match mat.uv_map {
+			Some(tex_path) => {
+				let texture = try!{ load_texture_from_path(&resolve_path(base_dir, &tex_path)) };
+				(texture, diffuse)
+			},
+			None => (mem::color_texture((diffuse.0, diffuse.1, diffuse.2, mat.alpha as f32)),
+				(1.0, 1.0, 1.0)),
 		};
 		mats.insert(mat.name, mem::Material {
 				ambient: color_conv(mat.color_ambient),
+				diffuse: diffuse,
 				specular: color_conv(mat.color_specular),
-				texture: texture } );
+				shininess: mat.specular_coefficient as f32,
+				emissive: mat.color_emissive.map(color_conv).unwrap_or((0.0, 0.0, 0.0)),
+				texture: texture,
+				normal_map: None } );
 	}
 	Ok(mats)
 }
+
+/// Add `Ns` lines with the default specular exponent to materials which lack
+/// them, since the `.mtl` parser requires one after every `newmtl`.
+fn default_missing_shininess(mat_str: &str) -> String {
+	let mut filled = String::with_capacity(mat_str.len());
+	let mut awaiting_ns = false;
+	for line in mat_str.lines() {
+		match line.split_whitespace().next() {
+			None => (),
+			Some(word) if word.starts_with('#') => (),
+			Some(word) => {
+				if awaiting_ns && word != "Ns" {
+					filled.push_str(&format!("Ns {}\n", mem::DEFAULT_SHININESS));
+				}
+				awaiting_ns = word == "newmtl";
+			},
+		}
+		filled.push_str(line);
+		filled.push('\n');
+	}
+	if awaiting_ns {
+		filled.push_str(&format!("Ns {}\n", mem::DEFAULT_SHININESS));
+	}
+	filled
+}
+
+/// Load materials from the wavefront `.mtl` file at `path`, with texture
+/// paths relative to its directory.
+pub fn load_mats_from_path(path: &Path) -> Result<HashMap<String, mem::Material>> {
+	let mut file = try!{ File::open(path)
+			.chain_err(|| format!("I/O error loading materials {}", path.display())) };
+	load_mats(&mut file, path.parent())
+		.chain_err(|| format!("Could not load materials {}", path.display()))
+}
 /// Convert a color from wavefront_obj `Color` to internal RGB tuple
 /// representation.
 fn color_conv(color: mtl::Color) -> (f32, f32, f32) {
 	(color.r as f32, color.g as f32, color.b as f32)
 }
 
-/// Load a texture from a `.png` file.
-pub fn load_texture<T>(read: &mut T) -> Result<Vec<Vec<(u8, u8, u8, u8)>>>
+/// Write geometry as a wavefront `.obj` object, e.g. to inspect it in another
+/// tool.
+///
+/// Each vertex gets its own `v` (with its color), `vt` and `vn` line, so
+/// faces use the same index for all three.
+pub fn save_obj<W: io::Write>(geometry: &mem::Geometry, write: &mut W) -> Result<()> {
+	write_obj(geometry, None, write).chain_err(|| "I/O error saving model")
+}
+
+/// Save a model as a wavefront `.obj` file at `path`, with its material in a
+/// `.mtl` file and its texture in a `.png` file of the same name alongside it.
+/// `load_model_from_path` loads it back.
+///
+/// The material's normal map isn't saved.
+pub fn save_model_to_path(model: &mem::Model, path: &Path) -> Result<()> {
+	let name = try!{ path.file_stem().and_then(|stem| stem.to_str())
+			.ok_or(Error::from(format!("No model name in {}", path.display()))) };
+	let texture_path = path.with_extension("png");
+	let mtl_path = path.with_extension("mtl");
+
+	let texture = &model.material.texture;
+	let width = texture.first().map_or(0, Vec::len);
+	let image = image::RgbaImage::from_fn(width as u32, texture.len() as u32, |x, y| {
+		let texel = texture[y as usize][x as usize];
+		image::Rgba([texel.0, texel.1, texel.2, texel.3])
+	});
+	try!{ image.save(&texture_path)
+			.chain_err(|| format!("Could not write texture {}", texture_path.display())) };
+	try!{ File::create(&mtl_path)
+			.and_then(|mut file| write_mtl(&model.material, name, &mut file))
+			.chain_err(|| format!("I/O error saving materials {}", mtl_path.display())) };
+	try!{ File::create(path)
+			.and_then(|mut file| write_obj(&model.geometry, Some(name), &mut file))
+			.chain_err(|| format!("I/O error saving model {}", path.display())) };
+	Ok(())
+}
+
+/// Write a wavefront object, using the material `material` from the library
+/// of the same name, if given.
+fn write_obj<W: io::Write>(geometry: &mem::Geometry, material: Option<&str>, write: &mut W)
+		-> io::Result<()> {
+	let mut write = io::BufWriter::new(write);
+	if let Some(name) = material {
+		try!{ writeln!(write, "mtllib {}.mtl", name) };
+	}
+	try!{ writeln!(write, "o {}", material.unwrap_or("geometry")) };
+	for vertex in geometry.vertices.iter() {
+		let (p, c) = (vertex.position, vertex.color);
+		try!{ writeln!(write, "v {} {} {} {} {} {}", p[0], p[1], p[2], c[0], c[1], c[2]) };
+	}
+	for vertex in geometry.vertices.iter() {
+		try!{ writeln!(write, "vt {} {}", vertex.tex_uv[0], vertex.tex_uv[1]) };
+	}
+	for vertex in geometry.vertices.iter() {
+		let n = vertex.normal;
+		try!{ writeln!(write, "vn {} {} {}", n[0], n[1], n[2]) };
+	}
+	if let Some(name) = material {
+		try!{ writeln!(write, "usemtl {}", name) };
+	}
+	for triangle in geometry.indices.chunks(3) {
+		try!{ write!(write, "f") };
+		for &index in triangle.iter() {
+			let i = index as usize + 1;
+			try!{ write!(write, " {}/{}/{}", i, i, i) };
+		}
+		try!{ writeln!(write) };
+	}
+	write.flush()
+}
+
+/// Write a wavefront material library with the single material `name`, whose
+/// texture is `name.png`.
+fn write_mtl<W: io::Write>(material: &mem::Material, name: &str, write: &mut W)
+		-> io::Result<()> {
+	let color = |c: (f32, f32, f32)| format!("{} {} {}", c.0, c.1, c.2);
+	write!(write, "newmtl {}\n\
+			Ns {}\n\
+			Ka {}\n\
+			Kd {}\n\
+			Ks {}\n\
+			Ke {}\n\
+			d 1\n\
+			illum 2\n\
+			map_Kd {}.png\n",
+		name, material.shininess, color(material.ambient), color(material.diffuse),
+		color(material.specular), color(material.emissive), name)
+}
+
+/// Load a texture from an image file, guessing its format.
+///
+/// The format is guessed from the file's contents, so any format the `image`
+/// crate can recognize (PNG, JPEG, BMP, ...) works. Formats without a magic
+/// number, like TGA, can't be recognized; use `load_texture_from_path` or
+/// `load_texture` for them.
+///
+/// The texture is laid out as in `load_texture`.
+pub fn load_texture_guessing_format<T>(read: &mut T) -> Result<Vec<Vec<(u8, u8, u8, u8)>>>
 		where T: io::BufRead + io::Seek {
+	let reader = try!{
+		image::io::Reader::new(read).with_guessed_format()
+			.chain_err(|| "I/O error loading texture")
+	};
 	let image = try!{
-		image::load(read, image::ImageFormat::Png)
+		reader.decode()
 			.chain_err(|| "Could not load texture")
-	}.to_rgba();
+	};
+	Ok(texture_from_image(image))
+}
+
+/// Load a texture from an image in the given format.
+///
+/// The texture is a list of the image's rows of RGBA pixels, from the top:
+/// `texture[y][x]`, with `texture.len()` the image's height.
+pub fn load_texture(read: &mut io::BufRead, format: image::ImageFormat)
+		-> Result<Vec<Vec<(u8, u8, u8, u8)>>> {
+	let mut bytes = Vec::new();
+	try!{ read.read_to_end(&mut bytes).chain_err(|| "I/O error loading texture") };
+	let image = try!{
+		image::load_from_memory_with_format(&bytes, format)
+			.chain_err(|| format!("Could not load {:?} texture", format))
+	};
+	Ok(texture_from_image(image))
+}
+
+/// Load a texture from the image file at `path`.
+///
+/// The format comes from the file's extension, like `.png` or `.jpg`. Files
+/// without a recognized extension have their format guessed from their
+/// contents, as in `load_texture_guessing_format`.
+pub fn load_texture_from_path(path: &Path) -> Result<Vec<Vec<(u8, u8, u8, u8)>>> {
+	let file = try!{ File::open(path)
+			.chain_err(|| format!("I/O error loading texture {}", path.display())) };
+	let mut read = io::BufReader::new(file);
+	match path.extension().and_then(image::ImageFormat::from_extension) {
+		Some(format) => load_texture(&mut read, format),
+		None => load_texture_guessing_format(&mut read),
+	}.chain_err(|| format!("Could not load texture {}", path.display()))
+}
+
+/// Convert an image to a texture, laid out as in `load_texture`.
+fn texture_from_image(image: image::DynamicImage) -> Vec<Vec<(u8, u8, u8, u8)>> {
+	let image = image.to_rgba();
 	let (width, height) = image.dimensions();
 	//Derp.
-	let mut y = 0;
-	let mut rows = Vec::with_capacity(width as usize);
-	let mut row = Vec::with_capacity(height as usize);
+	let mut x = 0;
+	let mut rows = Vec::with_capacity(height as usize);
+	let mut row = Vec::with_capacity(width as usize);
 	for pixel in image.pixels() {
 		let pixel_value = (pixel[0], pixel[1], pixel[2], pixel[3]);
 		row.push(pixel_value);
-		y += 1;
-		if y == width {
-			y = 0;
+		x += 1;
+		if x == width {
+			x = 0;
 			rows.push(row);
-			row = Vec::with_capacity(height as usize);
+			row = Vec::with_capacity(width as usize);
 		}
 	}
-	Ok(rows)
+	rows
 }
 
+
+#[cfg(test)]
+mod tests {
+	use image::{DynamicImage, ImageFormat, Rgba, RgbaImage};
+	use std::io::Cursor;
+	use model::{mem, TestDir, Vertex, WHITE};
+	use std::rc::Rc;
+	use std::fs::{self, File};
+	use std::io::Write;
+	use std::path::Path;
+	use super::{build_geometry, load_geometry, load_mats, load_model_from_path, load_texture};
+	use super::{load_model, load_models, save_model_to_path, save_obj};
+	use super::{load_texture_from_path, load_texture_guessing_format};
+	use wavefront_obj::obj;
+
+	#[test]
+	fn test_uv_only_geometry() {
+		// A quad facing +Y, with positions and UVs but no normals. Position 1
+		// has a different UV in each triangle, so it must be split.
+		let source = String::from("o quad\n\
+			v 0 0 0\n\
+			v 0 0 1\n\
+			v 1 0 1\n\
+			v 1 0 0\n\
+			vt 0 0\n\
+			vt 0 1\n\
+			vt 1 1\n\
+			vt 1 0\n\
+			vt 0.5 0.5\n\
+			f 1/1 2/2 3/3\n\
+			f 1/5 3/3 4/4\n");
+		let object = obj::parse(source).unwrap().objects.pop().unwrap();
+		let geometry = build_geometry(&object, &[]);
+
+		assert_eq!(5, geometry.vertices.len());
+		assert_eq!(6, geometry.indices.len());
+		let mut corners = geometry.vertices.iter()
+			.map(|v| (v.position, v.tex_uv))
+			.collect::<Vec<_>>();
+		corners.sort_by(|a, b| a.partial_cmp(b).unwrap());
+		assert_eq!(vec![
+				([0.0, 0.0, 0.0], [0.0, 0.0]),
+				([0.0, 0.0, 0.0], [0.5, 0.5]),
+				([0.0, 0.0, 1.0], [0.0, 1.0]),
+				([1.0, 0.0, 0.0], [1.0, 0.0]),
+				([1.0, 0.0, 1.0], [1.0, 1.0])],
+			corners);
+		for vertex in geometry.vertices.iter() {
+			assert_eq!([0.0, 1.0, 0.0], vertex.normal);
+		}
+	}
+
+	#[test]
+	fn test_quad_geometry() {
+		let source = String::from("o quad\n\
+			v 0 0 0\n\
+			v 0 0 1\n\
+			v 1 0 1\n\
+			v 1 0 0\n\
+			vn 0 1 0\n\
+			f 1//1 2//1 3//1 4//1\n");
+		let object = obj::parse(source).unwrap().objects.pop().unwrap();
+		let geometry = build_geometry(&object, &[]);
+		assert_eq!(4, geometry.vertices.len());
+		assert_eq!(6, geometry.indices.len());
+	}
+
+	#[test]
+	fn test_hard_edge_geometry() {
+		// Two faces meeting at a right angle along the edge from position 1 to
+		// 2, each with its own normal.
+		let source = String::from("o edge\n\
+			v 0 0 0\n\
+			v 0 0 1\n\
+			v 1 0 0\n\
+			v 0 1 0\n\
+			vn 0 1 0\n\
+			vn -1 0 0\n\
+			f 1//1 2//1 3//1\n\
+			f 1//2 4//2 2//2\n");
+		let object = obj::parse(source).unwrap().objects.pop().unwrap();
+		let geometry = build_geometry(&object, &[]);
+
+		// Positions 1 and 2 are split, one copy for each normal
+		assert_eq!(6, geometry.vertices.len());
+		for triangle in geometry.indices.chunks(3) {
+			let normals = triangle.iter()
+				.map(|&i| geometry.vertices[i as usize].normal)
+				.collect::<Vec<_>>();
+			assert!(normals == vec![[0.0, 1.0, 0.0]; 3] || normals == vec![[-1.0, 0.0, 0.0]; 3],
+				"mixed normals: {:?}", normals);
+		}
+	}
+
+	#[test]
+	fn test_mixed_normals() {
+		// A face with a given normal (not its geometric one), and a face
+		// without normals at right angles to it
+		let source = String::from("o mixed\n\
+			v 0 0 0\n\
+			v 0 0 1\n\
+			v 1 0 0\n\
+			v 0 1 0\n\
+			vn 0 0 1\n\
+			f 1//1 2//1 3//1\n\
+			f 1 4 2\n");
+		let object = obj::parse(source).unwrap().objects.pop().unwrap();
+		let geometry = build_geometry(&object, &[]);
+
+		assert_eq!(6, geometry.vertices.len());
+		let (given, smoothed): (Vec<_>, Vec<_>) = geometry.indices.iter()
+			.map(|&i| geometry.vertices[i as usize])
+			.enumerate()
+			.partition(|&(i, _)| i < 3);
+		// Given normals are kept
+		for &(_, vertex) in given.iter() {
+			assert_eq!([0.0, 0.0, 1.0], vertex.normal);
+		}
+		// Others are smoothed across both faces where they meet
+		let half = 0.5f32.sqrt();
+		for &(_, vertex) in smoothed.iter() {
+			let expected = if vertex.position == [0.0, 1.0, 0.0] {
+				[1.0, 0.0, 0.0]
+			} else {
+				[half, half, 0.0]
+			};
+			for (n, e) in vertex.normal.iter().zip(expected.iter()) {
+				assert!((n - e).abs() < 1e-6, "{:?} at {:?}", vertex.normal, vertex.position);
+			}
+		}
+	}
+
+	#[test]
+	fn test_vertex_colors() {
+		let source = b"mtllib colors.mtl\n\
+			o first\n\
+			v 0 0 0\n\
+			o triangle\n\
+			v 0 0 0 1 0 0\n\
+			v 0 0 1 0 1 0.5\n\
+			v 1 0 0\n\
+			usemtl Colors\n\
+			f 2 3 4\n";
+		let (geometry, _, _) = load_geometry(&mut &source[..]).unwrap();
+		let mut colors: Vec<_> = geometry.vertices.iter()
+			.map(|v| (v.position, v.color))
+			.collect();
+		colors.sort_by(|a, b| a.partial_cmp(b).unwrap());
+		assert_eq!(vec![
+				([0.0, 0.0, 0.0], [1.0, 0.0, 0.0]),
+				([0.0, 0.0, 1.0], [0.0, 1.0, 0.5]),
+				([1.0, 0.0, 0.0], WHITE)],
+			colors);
+	}
+
+	#[test]
+	fn test_load_model_from_path() {
+		// The model, its materials and their texture are in sibling
+		// directories, each referred to relative to the file referring to it
+		let dir = TestDir::new("disk");
+		for sub in ["models", "materials", "textures"].iter() {
+			fs::create_dir_all(dir.join(sub)).unwrap();
+		}
+		File::create(dir.join("models/tri.obj")).unwrap().write_all(b"mtllib ../materials/tri.mtl\n\
+			o tri\n\
+			v 0 0 0\n\
+			v 0 0 1\n\
+			v 1 0 0\n\
+			usemtl Tri\n\
+			f 1 2 3\n").unwrap();
+		File::create(dir.join("materials/tri.mtl")).unwrap().write_all(b"newmtl Tri\n\
+			Ns 1.0\n\
+			Ka 0.0 0.0 0.0\n\
+			Kd 0.25 0.5 1.0\n\
+			Ks 0.5 0.5 0.5\n\
+			d 1.0\n\
+			illum 2\n\
+			map_Kd ../textures/tri.png\n").unwrap();
+		RgbaImage::new(3, 2).save(dir.join("textures/tri.png")).unwrap();
+
+		let loaded = load_model_from_path(&dir.join("models/tri.obj"));
+		fs::remove_file(dir.join("textures/tri.png")).unwrap();
+		let missing = load_model_from_path(&dir.join("models/tri.obj"));
+
+		let (geometry, material) = loaded.unwrap();
+		assert_eq!(3, geometry.vertices.len());
+		assert_eq!(2, material.texture.len());
+		assert_eq!((0.25, 0.5, 1.0), material.diffuse);
+		// The error says exactly which file was missing
+		let errors: Vec<String> = missing.unwrap_err().iter().map(|e| e.to_string()).collect();
+		let texture_path = dir.join("models/../materials/../textures/tri.png");
+		assert!(errors.iter().any(|e| e.contains(&texture_path.display().to_string())),
+			"{:?}", errors);
+	}
+
+	#[test]
+	fn test_no_material_library() {
+		let source = b"o tri\n\
+			v 0 0 0\n\
+			v 0 0 1\n\
+			v 1 0 0\n\
+			f 1 2 3\n";
+		let (geometry, material) = load_model(&mut &source[..], Some(Path::new("nonexistent"))).unwrap();
+		assert_eq!(3, geometry.vertices.len());
+		// The default material's magenta and black checkerboard
+		assert_eq!((255, 0, 255, 255), material.texture[0][0]);
+		assert_eq!(None, load_geometry(&mut &source[..]).unwrap().1);
+	}
+
+	#[test]
+	fn test_load_models() {
+		// Two objects with their own materials, and a third with no faces
+		let dir = TestDir::new("scene");
+		File::create(dir.join("scene.mtl")).unwrap().write_all(b"newmtl Red\n\
+			Ns 10\n\
+			Ka 0 0 0\n\
+			Kd 1 0 0\n\
+			Ks 0 0 0\n\
+			d 1\n\
+			illum 2\n\
+			newmtl Blue\n\
+			Ns 10\n\
+			Ka 0 0 0\n\
+			Kd 0 0 1\n\
+			Ks 0 0 0\n\
+			d 1\n\
+			illum 2\n").unwrap();
+		let scene = b"mtllib scene.mtl\n\
+			o triangle\n\
+			v 0 0 0\n\
+			v 0 0 1\n\
+			v 1 0 0\n\
+			usemtl Red\n\
+			f 1 2 3\n\
+			o point\n\
+			v 5 5 5\n\
+			o quad\n\
+			v 2 0 0 0 1 0\n\
+			v 2 0 1\n\
+			v 3 0 1\n\
+			v 3 0 0\n\
+			usemtl Blue\n\
+			f 5 6 7 8\n";
+		let models = load_models(&mut &scene[..], Some(dir.path()));
+		let single = load_model(&mut &scene[..], Some(dir.path()));
+		// Without a base directory, scene.mtl is looked for in the working
+		// directory
+		let working_dir = load_models(&mut &scene[..], None);
+
+		let models = models.unwrap();
+		assert_eq!(2, models.len());
+		let (ref triangle, ref red) = models[0];
+		let (ref quad, ref blue) = models[1];
+		assert_eq!(3, triangle.indices.len());
+		assert_eq!(6, quad.indices.len());
+		assert_eq!(vec![vec![(255, 0, 0, 255)]], red.texture);
+		assert_eq!(vec![vec![(0, 0, 255, 255)]], blue.texture);
+		// Vertex colors go with the right object's positions
+		assert!(triangle.vertices.iter().all(|v| v.color == WHITE));
+		for vertex in quad.vertices.iter() {
+			let expected = if vertex.position == [2.0, 0.0, 0.0] { [0.0, 1.0, 0.0] } else { WHITE };
+			assert_eq!(expected, vertex.color);
+		}
+		assert!(single.is_err());
+		let error = working_dir.unwrap_err();
+		assert!(error.iter().any(|e| e.to_string().contains("materials scene.mtl")), "{}", error);
+	}
+
+	#[test]
+	fn test_save_model() {
+		let vertex = |position: [f32; 3], tex_uv: [f32; 2], color: [f32; 3]| Vertex {
+			position: position,
+			normal: [0.0, 1.0, 0.0],
+			tangent: [1.0, 0.0, 0.0],
+			tex_uv: tex_uv,
+			color: color,
+		};
+		let geometry = mem::Geometry::new(vec![
+				vertex([0.0, 0.0, 0.0], [0.0, 0.0], WHITE),
+				vertex([0.0, 0.5, 1.0], [0.0, 1.0], [1.0, 0.0, 0.0]),
+				vertex([1.0, 0.0, 1.0], [1.0, 1.0], WHITE),
+				vertex([1.0, -0.25, 0.0], [1.0, 0.0], [0.0, 0.5, 1.0])],
+			vec![0, 1, 2, 0, 2, 3]);
+		let mut obj = Vec::new();
+		save_obj(&geometry, &mut obj).unwrap();
+		let obj = String::from_utf8(obj).unwrap();
+		assert!(obj.contains("\nf 1/1/1 3/3/3 4/4/4\n"), "{}", obj);
+
+		let material = mem::Material {
+			ambient: (0.0, 0.0, 0.0),
+			diffuse: (0.25, 0.5, 1.0),
+			specular: (0.5, 0.5, 0.5),
+			shininess: 16.0,
+			emissive: (0.0, 0.25, 0.0),
+			texture: vec![vec![(255, 0, 0, 255), (0, 255, 0, 128)]],
+			normal_map: None,
+		};
+		let model = mem::Model { geometry: Rc::new(geometry), material: Rc::new(material) };
+		let dir = TestDir::new("save");
+		save_model_to_path(&model, &dir.join("quad.obj")).unwrap();
+		let loaded = load_model_from_path(&dir.join("quad.obj"));
+
+		let (geometry, material) = loaded.unwrap();
+		assert_eq!(model.geometry.indices.len(), geometry.indices.len());
+		let corners = |geometry: &mem::Geometry| {
+			let mut corners = geometry.vertices.iter()
+				.map(|v| (v.position, v.tex_uv, v.color))
+				.collect::<Vec<_>>();
+			corners.sort_by(|a, b| a.partial_cmp(b).unwrap());
+			corners
+		};
+		assert_eq!(corners(&model.geometry), corners(&geometry));
+		assert_eq!(model.material.texture, material.texture);
+		assert_eq!(model.material.diffuse, material.diffuse);
+		assert_eq!(model.material.shininess, material.shininess);
+		assert_eq!(model.material.emissive, material.emissive);
+	}
+
+	#[test]
+	fn test_color_only_material() {
+		let source = b"newmtl Red\n\
+			Ns 10\n\
+			Ka 0.1 0.1 0.1\n\
+			Kd 1.0 0.0 0.2\n\
+			Ks 0.5 0.5 0.5\n\
+			d 1.0\n\
+			illum 2\n";
+		let mats = load_mats(&mut &source[..], Some(Path::new("nonexistent"))).unwrap();
+		let red = &mats["Red"];
+		assert_eq!(vec![vec![(255, 0, 51, 255)]], red.texture);
+		assert_eq!((1.0, 1.0, 1.0), red.diffuse);
+		assert_eq!((0.1, 0.1, 0.1), red.ambient);
+		assert_eq!((0.5, 0.5, 0.5), red.specular);
+		assert_eq!(10.0, red.shininess);
+		assert_eq!((0.0, 0.0, 0.0), red.emissive);
+	}
+
+	#[test]
+	fn test_shininess_and_emissive() {
+		let source = b"newmtl Lamp\n\
+			Ns 200\n\
+			Ka 0.0 0.0 0.0\n\
+			Kd 1.0 1.0 0.8\n\
+			Ks 0.5 0.5 0.5\n\
+			Ke 1.0 0.5 0.0\n\
+			d 1.0\n\
+			illum 2\n\
+			\n\
+			# No Ns or Ke\n\
+			newmtl Plain\n\
+			Ka 0.0 0.0 0.0\n\
+			Kd 0.5 0.5 0.5\n\
+			Ks 0.5 0.5 0.5\n\
+			d 1.0\n\
+			illum 2\n";
+		let mats = load_mats(&mut &source[..], Some(Path::new("nonexistent"))).unwrap();
+		assert_eq!(200.0, mats["Lamp"].shininess);
+		assert_eq!((1.0, 0.5, 0.0), mats["Lamp"].emissive);
+		assert_eq!(mem::DEFAULT_SHININESS, mats["Plain"].shininess);
+		assert_eq!((0.0, 0.0, 0.0), mats["Plain"].emissive);
+	}
+
+	#[test]
+	fn test_load_texture_formats() {
+		let image = DynamicImage::ImageRgba8(RgbaImage::new(3, 2));
+		for &format in [ImageFormat::Png, ImageFormat::Jpeg, ImageFormat::Bmp].iter() {
+			let mut bytes = Vec::new();
+			image.write_to(&mut bytes, format).unwrap();
+			let texture = load_texture_guessing_format(&mut Cursor::new(bytes)).unwrap();
+			assert_eq!(2, texture.len(), "{:?}", format);
+			assert!(texture.iter().all(|row| row.len() == 3), "{:?}", format);
+		}
+		assert!(load_texture_guessing_format(&mut Cursor::new(b"not an image".to_vec())).is_err());
+	}
+
+	#[test]
+	fn test_load_jpeg_texture() {
+		let color = [200, 100, 50, 255];
+		let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(8, 4, Rgba(color)));
+		let mut jpeg = Vec::new();
+		image.write_to(&mut jpeg, ImageFormat::Jpeg).unwrap();
+		let mut bmp = Vec::new();
+		image.write_to(&mut bmp, ImageFormat::Bmp).unwrap();
+
+		let dir = TestDir::new("jpeg");
+		File::create(dir.join("solid.jpg")).unwrap().write_all(&jpeg).unwrap();
+		File::create(dir.join("solid.texture")).unwrap().write_all(&bmp).unwrap();
+		let from_jpg = load_texture_from_path(&dir.join("solid.jpg"));
+		let guessed = load_texture_from_path(&dir.join("solid.texture"));
+
+		// JPEG is lossy, but a solid color should come back very close
+		let close = |texel: (u8, u8, u8, u8)| {
+			let texel = [texel.0, texel.1, texel.2, texel.3];
+			texel.iter().zip(color.iter()).all(|(&a, &b)| (a as i32 - b as i32).abs() <= 2)
+		};
+		for texture in [from_jpg.unwrap(), load_texture(
+				&mut &jpeg[..], ImageFormat::Jpeg).unwrap()].iter() {
+			assert_eq!(4, texture.len());
+			assert!(texture.iter().all(|row| row.len() == 8));
+			assert!(texture.iter().flat_map(|row| row.iter()).all(|&texel| close(texel)),
+				"{:?}", texture[0][0]);
+		}
+		// Unrecognized extensions fall back to guessing from the contents
+		assert_eq!(vec![vec![(200, 100, 50, 255); 8]; 4], guessed.unwrap());
+		assert!(load_texture(&mut &jpeg[..], ImageFormat::Png).is_err());
+	}
+}
@@ -0,0 +1,357 @@
+//! Functions to load models from glTF 2.0 files.
+//!
+//! This supports both `.gltf` (JSON) and `.glb` (binary) files. Buffers and
+//! images can be embedded, in the `.glb` binary chunk or as base64 `data:`
+//! URIs, or in separate files. Only the first primitive of each mesh is
+//! loaded, and node transforms are ignored.
+
+use base64;
+use errors::*;
+use gltf::{self, Accessor, Gltf};
+use gltf::accessor::{DataType, Dimensions};
+use gltf::buffer;
+use gltf::image;
+use gltf::mesh::Mode;
+use model::{disk, mem, Vertex, WHITE};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// The ambient color of loaded materials, as a fraction of the base color.
+/// glTF has no equivalent.
+const AMBIENT: (f32, f32, f32) = (0.2, 0.2, 0.2);
+/// The specular color of fully smooth loaded materials. Rougher materials get
+/// less.
+const MAX_SPECULAR: f32 = 0.5;
+
+/// Load every mesh in a glTF or GLB file, each with its material.
+///
+/// Buffers and images in separate files are relative to `base_dir`, or the
+/// working directory if it's `None`.
+///
+/// Vertices without normals get smooth normals, averaged from the triangles
+/// around them. Materials use the base color texture if there is one, and
+/// the base color factor otherwise.
+pub fn load_gltf(read: &mut io::Read, base_dir: Option<&Path>)
+		-> Result<Vec<(mem::Geometry, mem::Material)>> {
+	let mut bytes = Vec::new();
+	try!{ read.read_to_end(&mut bytes).chain_err(|| "I/O error loading glTF") };
+	let Gltf { document, mut blob } = try!{ Gltf::from_slice(&bytes)
+			.chain_err(|| "Could not parse glTF") };
+
+	let mut buffers = Vec::new();
+	for buffer in document.buffers() {
+		let data = match buffer.source() {
+			buffer::Source::Bin => try!{ blob.take()
+				.ok_or(Error::from("Buffer has neither a URI nor GLB data")) },
+			buffer::Source::Uri(uri) => try!{ read_uri(uri, base_dir) },
+		};
+		if data.len() < buffer.length() {
+			bail!("Buffer {} is {} bytes, expected {}", buffer.index(), data.len(), buffer.length());
+		}
+		buffers.push(data);
+	}
+	let file = GltfFile { buffers: buffers, base_dir: base_dir };
+
+	let mut meshes = Vec::new();
+	for mesh in document.meshes() {
+		let primitive = try!{ mesh.primitives().next()
+			.ok_or(Error::from(format!("Mesh {} has no primitives", mesh.index()))) };
+		meshes.push( (
+			try!{ file.load_geometry(&primitive)
+				.chain_err(|| format!("Could not load mesh {}", mesh.index())) },
+			try!{ file.load_material(&primitive.material())
+				.chain_err(|| format!("Could not load mesh {}", mesh.index())) },
+		) );
+	}
+	Ok(meshes)
+}
+
+/// Read the data at a URI: a base64 `data:` URI, or a path relative to
+/// `base_dir`.
+fn read_uri(uri: &str, base_dir: Option<&Path>) -> Result<Vec<u8>> {
+	if uri.starts_with("data:") {
+		match uri.find(";base64,") {
+			Some(start) => base64::decode(&uri[start + ";base64,".len()..])
+				.chain_err(|| "Invalid base64 in data URI"),
+			None => bail!("Only base64 data URIs are supported"),
+		}
+	} else {
+		let path = disk::resolve_path(base_dir, uri);
+		let mut bytes = Vec::new();
+		try!{ File::open(&path)
+			.and_then(|mut file| file.read_to_end(&mut bytes))
+			.chain_err(|| format!("Could not load {}", path.display())) };
+		Ok(bytes)
+	}
+}
+
+/// A parsed glTF file, with its buffers loaded.
+struct GltfFile<'a> {
+	buffers: Vec<Vec<u8>>,
+	base_dir: Option<&'a Path>,
+}
+
+impl<'a> GltfFile<'a> {
+
+	/// Get the bytes of a buffer view.
+	fn buffer_view(&self, view: &buffer::View) -> Result<&[u8]> {
+		let buffer = &self.buffers[view.buffer().index()];
+		if view.offset() + view.length() > buffer.len() {
+			bail!("Buffer view {} runs past the end of its buffer", view.index());
+		}
+		Ok(&buffer[view.offset()..view.offset() + view.length()])
+	}
+
+	/// Check that an accessor has the given dimensions and one of the given
+	/// component types, and fits in its buffer view, so reading it with
+	/// `gltf::mesh::Reader` can't panic.
+	fn check_accessor(&self, accessor: &Accessor, dimensions: Dimensions, types: &[DataType])
+			-> Result<()> {
+		if accessor.dimensions() != dimensions || !types.contains(&accessor.data_type()) {
+			bail!("Accessor {} is {:?} {:?}, expected {:?} {:?}", accessor.index(),
+				accessor.dimensions(), accessor.data_type(), dimensions, types);
+		}
+		if accessor.sparse().is_some() {
+			bail!("Accessor {} is sparse, which isn't supported", accessor.index());
+		}
+		let view = try!{ accessor.view()
+			.ok_or(Error::from(format!("Accessor {} has no buffer view", accessor.index()))) };
+		let bytes = try!{ self.buffer_view(&view) };
+		let stride = view.stride().unwrap_or(accessor.size());
+		if accessor.count() == 0 || stride < accessor.size() ||
+				accessor.offset() + (accessor.count() - 1) * stride + accessor.size() > bytes.len() {
+			bail!("Accessor {} doesn't fit in its buffer view", accessor.index());
+		}
+		Ok(())
+	}
+
+	/// Load a primitive's geometry.
+	fn load_geometry(&self, primitive: &gltf::Primitive) -> Result<mem::Geometry> {
+		if primitive.mode() != Mode::Triangles {
+			bail!("Only triangle primitives are supported");
+		}
+		let float = [DataType::F32];
+		let position_accessor = try!{ primitive.get(&gltf::Semantic::Positions)
+			.ok_or(Error::from("Primitive has no positions")) };
+		try!{ self.check_accessor(&position_accessor, Dimensions::Vec3, &float) };
+		if let Some(accessor) = primitive.get(&gltf::Semantic::Normals) {
+			try!{ self.check_accessor(&accessor, Dimensions::Vec3, &float) };
+		}
+		if let Some(accessor) = primitive.get(&gltf::Semantic::TexCoords(0)) {
+			try!{ self.check_accessor(&accessor, Dimensions::Vec2,
+				&[DataType::U8, DataType::U16, DataType::F32]) };
+		}
+		if let Some(accessor) = primitive.indices() {
+			try!{ self.check_accessor(&accessor, Dimensions::Scalar,
+				&[DataType::U8, DataType::U16, DataType::U32]) };
+		}
+
+		let reader = primitive.reader(|buffer| self.buffers.get(buffer.index()).map(|data| &data[..]));
+		let positions: Vec<[f32; 3]> = try!{ reader.read_positions()
+			.ok_or(Error::from("Could not read positions")) }.collect();
+		let normals: Option<Vec<[f32; 3]>> = reader.read_normals().map(|normals| normals.collect());
+		let uvs: Option<Vec<[f32; 2]>> = reader.read_tex_coords(0)
+			.map(|uvs| uvs.into_f32().collect());
+		if let Some(ref normals) = normals {
+			if normals.len() != positions.len() {
+				bail!("Primitive has {} normals for {} positions", normals.len(), positions.len());
+			}
+		}
+		if let Some(ref uvs) = uvs {
+			if uvs.len() != positions.len() {
+				bail!("Primitive has {} texture coordinates for {} positions", uvs.len(), positions.len());
+			}
+		}
+		let indices: Vec<usize> = match reader.read_indices() {
+			Some(indices) => indices.into_u32().map(|index| index as usize).collect(),
+			None => (0..positions.len()).collect(),
+		};
+		if positions.len() > ::std::u16::MAX as usize {
+			bail!("Too many vertices ({}) for 16-bit indices", positions.len());
+		}
+		if let Some(&bad) = indices.iter().find(|&&i| i >= positions.len()) {
+			bail!("Index {} is past the last vertex", bad);
+		}
+
+		let vertices: Vec<Vertex> = positions.iter().enumerate().map(|(i, &position)| Vertex {
+			position: position,
+			normal: normals.as_ref().map(|n| n[i]).unwrap_or([0.0, 1.0, 0.0]),
+			tangent: [1.0, 0.0, 0.0],
+			tex_uv: uvs.as_ref().map(|uv| uv[i]).unwrap_or([0.0, 0.0]),
+			color: WHITE,
+		}).collect();
+		let mut geometry = mem::Geometry::new(vertices, indices.iter().map(|&i| i as u16).collect());
+		if normals.is_none() {
+			geometry.recompute_normals();
+		}
+		geometry.recompute_tangents();
+		Ok(geometry)
+	}
+
+	/// Load a material.
+	fn load_material(&self, material: &gltf::Material) -> Result<mem::Material> {
+		if material.index().is_none() {
+			return Ok(mem::default_mat());
+		}
+		let pbr = material.pbr_metallic_roughness();
+		let factor = pbr.base_color_factor();
+		// The base color factor multiplies the texture, or replaces it
+		let (texture, diffuse) = match pbr.base_color_texture() {
+			Some(info) => (try!{ self.load_texture(&info.texture()) }, (factor[0], factor[1], factor[2])),
+			None => (mem::color_texture((factor[0], factor[1], factor[2], factor[3])), (1.0, 1.0, 1.0)),
+		};
+		let normal_map = match material.normal_texture() {
+			Some(normal) => Some(try!{ self.load_texture(&normal.texture()) }),
+			None => None,
+		};
+		let emissive = material.emissive_factor();
+		let specular = MAX_SPECULAR * (1.0 - pbr.roughness_factor());
+		Ok(mem::Material {
+			ambient: AMBIENT,
+			diffuse: diffuse,
+			specular: (specular, specular, specular),
+			shininess: mem::DEFAULT_SHININESS,
+			emissive: (emissive[0], emissive[1], emissive[2]),
+			texture: texture,
+			normal_map: normal_map,
+		})
+	}
+
+	/// Load the image for a texture, from a buffer view or a URI.
+	fn load_texture(&self, texture: &gltf::Texture) -> Result<Vec<Vec<(u8, u8, u8, u8)>>> {
+		let bytes = match texture.source().source() {
+			image::Source::View { view, .. } => try!{ self.buffer_view(&view) }.to_vec(),
+			image::Source::Uri { uri, .. } => try!{ read_uri(uri, self.base_dir) },
+		};
+		disk::load_texture_guessing_format(&mut io::Cursor::new(bytes))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use base64;
+	use linear_algebra::Vec3;
+	use super::load_gltf;
+
+	/// A unit cube from the origin to (1, 1, 1): 8 float positions, then 36
+	/// unsigned short indices, wound counter-clockwise from outside.
+	const CUBE_BUFFER: &'static str =
+		"AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAAAACAPwAAgD8AAAAAAAAAAAAAAAAAAIA/AACAPwAAAAAAAIA/\
+		AAAAAAAAgD8AAIA/AACAPwAAgD8AAIA/AAACAAMAAAADAAEABAAFAAcABAAHAAYAAAABAAUAAAAFAAQAAgAGAAcAAgAHAAMA\
+		AAAEAAYAAAAGAAIAAQADAAcAAQAHAAUA";
+
+	/// glTF JSON for the cube, with its buffer either embedded as `uri` or
+	/// left for a GLB binary chunk.
+	fn cube_json(uri: Option<&str>) -> String {
+		let uri = uri.map(|uri| format!(", \"uri\": \"{}\"", uri)).unwrap_or(String::new());
+		format!(r#"{{
+			"asset": {{"version": "2.0"}},
+			"buffers": [{{"byteLength": 168{}}}],
+			"bufferViews": [
+				{{"buffer": 0, "byteOffset": 0, "byteLength": 96}},
+				{{"buffer": 0, "byteOffset": 96, "byteLength": 72}}
+			],
+			"accessors": [
+				{{"bufferView": 0, "componentType": 5126, "count": 8, "type": "VEC3",
+					"min": [0.0, 0.0, 0.0], "max": [1.0, 1.0, 1.0]}},
+				{{"bufferView": 1, "componentType": 5123, "count": 36, "type": "SCALAR"}}
+			],
+			"materials": [{{"pbrMetallicRoughness": {{"baseColorFactor": [1.0, 0.5, 0.0, 1.0]}}}}],
+			"meshes": [{{"primitives": [{{"attributes": {{"POSITION": 0}}, "indices": 1, "material": 0}}]}}]
+		}}"#, uri)
+	}
+
+	/// Check the cube loaded from `cube_json`.
+	fn check_cube(meshes: Vec<(::model::mem::Geometry, ::model::mem::Material)>) {
+		assert_eq!(1, meshes.len());
+		let (ref geometry, ref material) = meshes[0];
+		assert_eq!(8, geometry.vertices.len());
+		assert_eq!(36, geometry.indices.len());
+		assert_eq!((Vec3::from([0.0, 0.0, 0.0]), Vec3::from([1.0, 1.0, 1.0])), geometry.aabb());
+		// Smooth normals point roughly out from the center at the corners.
+		// Not exactly, since corners on a face's diagonal get both its
+		// triangles.
+		for vertex in geometry.vertices.iter() {
+			let outwards = (Vec3::from(vertex.position) - Vec3::from([0.5, 0.5, 0.5])).normalize();
+			assert!(Vec3::from(vertex.normal).dot(outwards) > 0.9,
+				"{:?} doesn't point outwards", vertex);
+		}
+		assert_eq!(vec![vec![(255, 128, 0, 255)]], material.texture);
+	}
+
+	#[test]
+	fn test_load_gltf() {
+		let json = cube_json(Some(&format!("data:application/octet-stream;base64,{}", CUBE_BUFFER)));
+		check_cube(load_gltf(&mut json.as_bytes(), None).unwrap());
+
+		assert!(load_gltf(&mut cube_json(Some("no-such-cube.bin")).as_bytes(), None).is_err());
+		assert!(load_gltf(&mut &b"{\"meshes\": [{\"primitives\": []}]}"[..], None).is_err());
+	}
+
+	#[test]
+	fn test_external_buffer() {
+		use model::TestDir;
+		use std::fs;
+
+		let dir = TestDir::new("gltf-external");
+		fs::write(dir.join("cube.bin"), base64::decode(CUBE_BUFFER).unwrap()).unwrap();
+		check_cube(load_gltf(&mut cube_json(Some("cube.bin")).as_bytes(), Some(&dir)).unwrap());
+	}
+
+	#[test]
+	fn test_short_accessors() {
+		let uri = format!("data:application/octet-stream;base64,{}", CUBE_BUFFER);
+		// Normals with fewer elements than there are positions
+		let json = cube_json(Some(&uri))
+			.replace(r#""attributes": {"POSITION": 0}"#, r#""attributes": {"POSITION": 0, "NORMAL": 2}"#)
+			.replace(r#""type": "SCALAR"}"#,
+				r#""type": "SCALAR"}, {"bufferView": 0, "componentType": 5126, "count": 4, "type": "VEC3"}"#);
+		assert!(load_gltf(&mut json.as_bytes(), None).is_err());
+		// Positions running past the end of their buffer view
+		let json = cube_json(Some(&uri)).replace(r#""count": 8"#, r#""count": 9"#);
+		assert!(load_gltf(&mut json.as_bytes(), None).is_err());
+		// Indices of the wrong type
+		let json = cube_json(Some(&uri)).replace(r#""type": "SCALAR""#, r#""type": "VEC2""#);
+		assert!(load_gltf(&mut json.as_bytes(), None).is_err());
+	}
+
+	#[test]
+	fn test_load_from_library() {
+		use model::TestDir;
+		use model::mem::ModelLibrary;
+		use std::fs;
+
+		let dir = TestDir::new("gltf");
+		let json = cube_json(Some(&format!("data:application/octet-stream;base64,{}", CUBE_BUFFER)));
+		fs::write(dir.join("cube.GLTF"), json).unwrap();
+		let library = ModelLibrary::new();
+		let model = library.load_model_from_path(dir.join("cube.GLTF"));
+
+		let model = model.unwrap();
+		assert_eq!(36, model.geometry.indices.len());
+		assert_eq!(vec![vec![(255, 128, 0, 255)]], model.material.texture);
+		assert_eq!(1, library.models.borrow().len());
+	}
+
+	#[test]
+	fn test_load_glb() {
+		let mut json = cube_json(None).into_bytes();
+		while json.len() % 4 != 0 {
+			json.push(b' ');
+		}
+		let bin = base64::decode(CUBE_BUFFER).unwrap();
+		assert_eq!(168, bin.len());
+		let u32_bytes = |n: usize| vec![n as u8, (n >> 8) as u8, (n >> 16) as u8, (n >> 24) as u8];
+		let mut glb = b"glTF".to_vec();
+		glb.extend(u32_bytes(2));
+		glb.extend(u32_bytes(12 + 8 + json.len() + 8 + bin.len()));
+		glb.extend(u32_bytes(json.len()));
+		glb.extend(b"JSON");
+		glb.extend(json);
+		glb.extend(u32_bytes(bin.len()));
+		glb.extend(b"BIN\0");
+		glb.extend(bin);
+		check_cube(load_gltf(&mut &glb[..], None).unwrap());
+	}
+}
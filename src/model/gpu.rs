@@ -4,8 +4,10 @@ use errors::*;
 use glium::backend::Facade;
 use glium::{IndexBuffer, VertexBuffer};
 use glium::index::PrimitiveType::TrianglesList;
-use glium::texture::Texture2d;
-use linear_algebra::Mat4;
+use glium::texture::{MipmapsOption, Texture2d};
+use glium::uniforms::{MagnifySamplerFilter, MinifySamplerFilter, Sampler, SamplerBehavior};
+use glium::uniforms::SamplerWrapFunction;
+use linear_algebra::{Aabb, Mat4, Vec3};
 use model::{mem, Vertex};
 
 /// GPU geometry, that is `Vertex`s.
@@ -15,40 +17,141 @@ pub struct Geometry {
 	pub vertices: VertexBuffer<Vertex>,
 	/// The uploaded index buffer.
 	pub indices: IndexBuffer<u16>,
+	/// The bounding box of the vertices, in model space.
+	pub bounds: Aabb<f32>,
 }
 impl Geometry {
 	/// Upload an in-memory `model::mem::Geometry` to GPU memory.
 	pub fn from_mem(display: &Facade, geometry: &mem::Geometry) -> Result<Geometry> {
+		let (min, max) = geometry.aabb();
 		Ok( Geometry {
 			vertices: try!{ VertexBuffer::new(display, geometry.vertices.as_ref())
 					.chain_err(|| "Could not upload vertices to GPU") },
 			indices: try!{ IndexBuffer::new(display, TrianglesList, geometry.indices.as_ref())
 					.chain_err(|| "Could not upload indices to GPU") },
+			bounds: Aabb { min: min, max: max },
 		} )
 	}
 }
 
+/// How to use mipmaps when a texture is drawn smaller than its full size.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MipmapMode {
+	/// Always sample the full-size texture.
+	None,
+	/// Sample the nearest mipmap.
+	Nearest,
+	/// Blend between the two nearest mipmaps.
+	Linear,
+}
+
+/// How a material's texture is sampled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SamplerSpec {
+	/// What to do with texture coordinates outside [0, 1], in both directions.
+	pub wrap: SamplerWrapFunction,
+	/// Filter within a texture level when it's drawn smaller than its size.
+	pub minify: MagnifySamplerFilter,
+	/// Filter when the texture is drawn larger than its size.
+	pub magnify: MagnifySamplerFilter,
+	/// How to use mipmaps when minifying.
+	pub mipmaps: MipmapMode,
+	/// Maximum anisotropic filtering level; 1 disables it.
+	pub anisotropy: u16,
+}
+
+impl Default for SamplerSpec {
+	fn default() -> SamplerSpec {
+		SamplerSpec {
+			wrap: SamplerWrapFunction::Repeat,
+			minify: MagnifySamplerFilter::Linear,
+			magnify: MagnifySamplerFilter::Linear,
+			mipmaps: MipmapMode::None,
+			anisotropy: 1,
+		}
+	}
+}
+
+impl SamplerSpec {
+	/// The equivalent glium sampler behavior.
+	pub fn behavior(&self) -> SamplerBehavior {
+		use glium::uniforms::MagnifySamplerFilter::{Linear, Nearest};
+		let minify_filter = match (self.minify, self.mipmaps) {
+			(Nearest, MipmapMode::None) => MinifySamplerFilter::Nearest,
+			(Linear, MipmapMode::None) => MinifySamplerFilter::Linear,
+			(Nearest, MipmapMode::Nearest) => MinifySamplerFilter::NearestMipmapNearest,
+			(Linear, MipmapMode::Nearest) => MinifySamplerFilter::LinearMipmapNearest,
+			(Nearest, MipmapMode::Linear) => MinifySamplerFilter::NearestMipmapLinear,
+			(Linear, MipmapMode::Linear) => MinifySamplerFilter::LinearMipmapLinear,
+		};
+		SamplerBehavior {
+			wrap_function: (self.wrap, self.wrap, self.wrap),
+			minify_filter: minify_filter,
+			magnify_filter: self.magnify,
+			depth_texture_comparison: None,
+			max_anisotropy: self.anisotropy,
+		}
+	}
+
+	/// Sample `texture` this way.
+	pub fn sample<'t>(&self, texture: &'t Texture2d) -> Sampler<'t, Texture2d> {
+		Sampler(texture, self.behavior())
+	}
+}
+
 /// GPU materials.
 #[derive(Debug)]
 pub struct Material {
 	/// The object's ambient color.
 	pub ambient: (f32, f32, f32),
+	/// The object's diffuse color.
+	pub diffuse: (f32, f32, f32),
 	/// The object's specular color.
 	pub specular: (f32, f32, f32),
+	/// The object's specular exponent.
+	pub shininess: f32,
+	/// The object's emissive color.
+	pub emissive: (f32, f32, f32),
 	/// The uploaded texture buffer.
 	pub texture: Texture2d,
+	/// The uploaded normal map, if the material has one.
+	pub normal_map: Option<Texture2d>,
+	/// How the texture is sampled.
+	pub sampler: SamplerSpec,
 }
 impl Material {
 	/// Upload the texture from an in-memory `model::mem::Material` to GPU
 	/// memory.
 	pub fn from_mem(display: &Facade, material: &mem::Material) -> Result<Material> {
+		Material::from_mem_with_sampler(display, material, SamplerSpec::default())
+	}
+
+	/// Upload the texture from an in-memory `model::mem::Material` to GPU
+	/// memory, to be sampled with `sampler`. Mipmaps are generated for the
+	/// texture if the sampler uses them.
+	pub fn from_mem_with_sampler(display: &Facade,
+			material: &mem::Material,
+			sampler: SamplerSpec) -> Result<Material> {
 		let src = material.clone();
+		let upload = |texture| if sampler.mipmaps == MipmapMode::None {
+			Texture2d::new(display, texture)
+		} else {
+			Texture2d::with_mipmaps(display, texture, MipmapsOption::AutoGeneratedMipmaps)
+		};
+		let normal_map = match src.normal_map {
+			Some(normal_map) => Some(try!{ upload(normal_map)
+					.chain_err(|| "Could not upload normal map to GPU") }),
+			None => None,
+		};
 		Ok( Material {
 			ambient: src.ambient,
+			diffuse: src.diffuse,
 			specular: src.specular,
-			texture: try!{
-				Texture2d::new(display, src.texture)
-					.chain_err(|| "Could not upload texture to GPU") },
+			shininess: src.shininess,
+			emissive: src.emissive,
+			texture: try!{ upload(src.texture).chain_err(|| "Could not upload texture to GPU") },
+			normal_map: normal_map,
+			sampler: sampler,
 		} )
 	}
 }
@@ -70,7 +173,27 @@ impl Model {
 			material: try!{ Material::from_mem(display, model.material.as_ref()) },
 		} )
 	}
+
+	/// Upload an in-memory `model::mem::Model` to GPU memory, sampling its
+	/// texture with `sampler`. See `Material::from_mem_with_sampler`.
+	pub fn from_mem_with_sampler(display: &Facade,
+			model: &mem::Model,
+			sampler: SamplerSpec) -> Result<Model> {
+		Ok( Model {
+			geometry: try!{ Geometry::from_mem(display, model.geometry.as_ref()) },
+			material: try!{
+				Material::from_mem_with_sampler(display, model.material.as_ref(), sampler) },
+		} )
+	}
+}
+
+/// Per-instance vertex attributes for drawing a `ModelInstanceBatch`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct InstanceAttributes {
+	/// The transformation matrix to place this instance in the world.
+	pub instance_model_matrix: [[f32; 4]; 4],
 }
+implement_vertex!(InstanceAttributes, instance_model_matrix);
 
 /// An in-world instance of an uploaded model.
 #[derive(Debug)]
@@ -80,4 +203,170 @@ pub struct ModelInstance<'a> {
 	/// The transformation matrix to place the model in the world.
 	pub model_matrix: Mat4<f32>,
 }
+impl<'a> ModelInstance<'a> {
+	/// The bounding box of this instance, in world space.
+	pub fn bounds(&self) -> Aabb<f32> {
+		self.model.geometry.bounds.transform(self.model_matrix)
+	}
+
+	/// Whether any part of this instance's bounding box is within `radius` of
+	/// `center`.
+	pub fn is_within(&self, center: Vec3<f32>, radius: f32) -> bool {
+		self.bounds().distance_to(center) <= radius
+	}
+
+	/// The opacity to draw this instance with, seen from `camera`, or `None`
+	/// if it's too far away to draw. See `fade_alpha`.
+	pub fn draw_alpha(&self, camera: Vec3<f32>, max_distance: f32, fade_width: f32)
+			-> Option<f32> {
+		fade_alpha(self.bounds().distance_to(camera), max_distance, fade_width)
+	}
+}
 
+/// The opacity of something `distance` from the camera, which fades out over
+/// the last `fade_width` before `max_distance`, or `None` if it's beyond
+/// `max_distance` and shouldn't be drawn at all.
+pub fn fade_alpha(distance: f32, max_distance: f32, fade_width: f32) -> Option<f32> {
+	if distance > max_distance {
+		None
+	} else if distance <= max_distance - fade_width {
+		Some(1.0)
+	} else {
+		Some((max_distance - distance) / fade_width)
+	}
+}
+
+/// Iterate over the instances with any part of their bounding box within
+/// `radius` of `center`.
+///
+/// This is a read-only query, e.g. for what's near the character; it doesn't
+/// resolve collisions.
+pub fn instances_within<'a, 'b>(instances: &'b [ModelInstance<'a>],
+		center: Vec3<f32>,
+		radius: f32) -> impl Iterator<Item = &'b ModelInstance<'a>> {
+	instances.iter().filter(move |instance| instance.is_within(center, radius))
+}
+
+/// Many in-world instances of one uploaded model, drawn with a single
+/// instanced draw call.
+#[derive(Debug)]
+pub struct ModelInstanceBatch<'a> {
+	/// The model in question.
+	pub model: &'a Model,
+	/// The uploaded per-instance attributes.
+	pub instances: VertexBuffer<InstanceAttributes>,
+	bounds: Aabb<f32>,
+}
+impl<'a> ModelInstanceBatch<'a> {
+	/// Upload the transformation matrices for instances of `model`.
+	///
+	/// This fails if the OpenGL implementation doesn't support instancing.
+	pub fn new(display: &Facade, model: &'a Model, model_matrices: &[Mat4<f32>])
+			-> Result<ModelInstanceBatch<'a>> {
+		let (attributes, bounds) = instance_attributes(model.geometry.bounds, model_matrices);
+		let instances = try!{ VertexBuffer::new(display, &attributes)
+				.chain_err(|| "Could not upload instances to GPU") };
+		try!{ instances.per_instance()
+				.map_err(|_| Error::from("Instanced rendering is not supported")) };
+		Ok( ModelInstanceBatch {
+			model: model,
+			instances: instances,
+			bounds: bounds,
+		} )
+	}
+
+	/// The bounding box of all instances, in world space.
+	pub fn bounds(&self) -> Aabb<f32> {
+		self.bounds
+	}
+}
+
+/// The per-instance attributes for placing a model with bounding box
+/// `bounds` at each of `model_matrices`, and the world space bounding box of
+/// all of them.
+pub fn instance_attributes(bounds: Aabb<f32>, model_matrices: &[Mat4<f32>])
+		-> (Vec<InstanceAttributes>, Aabb<f32>) {
+	let attributes = model_matrices.iter()
+		.map(|&m| InstanceAttributes { instance_model_matrix: m.into() })
+		.collect();
+	let bounds = Aabb::from_points(model_matrices.iter()
+			.flat_map(|&m| bounds.transform(m).corners().to_vec()));
+	(attributes, bounds)
+}
+
+#[cfg(test)]
+mod tests {
+	use glium::uniforms::{MagnifySamplerFilter, MinifySamplerFilter, SamplerWrapFunction};
+	use linear_algebra::{Aabb, Mat4, Vec3};
+	use super::{fade_alpha, instance_attributes, MipmapMode, SamplerSpec};
+
+	#[test]
+	fn test_fade_alpha() {
+		// Beyond the maximum distance
+		assert_eq!(None, fade_alpha(101.0, 100.0, 20.0));
+		// Within the fade band
+		assert_eq!(Some(0.25), fade_alpha(95.0, 100.0, 20.0));
+		assert_eq!(Some(0.0), fade_alpha(100.0, 100.0, 20.0));
+		// Close by
+		assert_eq!(Some(1.0), fade_alpha(80.0, 100.0, 20.0));
+		assert_eq!(Some(1.0), fade_alpha(0.0, 100.0, 20.0));
+		// No fade band
+		assert_eq!(Some(1.0), fade_alpha(100.0, 100.0, 0.0));
+		assert_eq!(None, fade_alpha(100.5, 100.0, 0.0));
+	}
+
+	#[test]
+	fn test_instance_attributes() {
+		let bounds = Aabb {
+			min: Vec3::from([-1.0, 0.0, -1.0]),
+			max: Vec3::from([1.0, 2.0, 1.0]) };
+		let matrices = [
+			Mat4::translation(Vec3::from([10.0, 0.0, 0.0])),
+			Mat4::trs(
+				Vec3::from([0.0, 5.0, -3.0]),
+				Mat4::rotation_y(0.5),
+				Vec3::from([2.0, 2.0, 2.0])),
+			Mat4::scale_uniform(0.5),
+		];
+		let (attributes, batch_bounds) = instance_attributes(bounds, &matrices);
+
+		assert_eq!(matrices.len(), attributes.len());
+		for (attribute, &m) in attributes.iter().zip(matrices.iter()) {
+			let expected: [[f32; 4]; 4] = m.into();
+			assert_eq!(expected, attribute.instance_model_matrix);
+		}
+
+		// The union of each instance's own bounds
+		let mut union = bounds.transform(matrices[0]);
+		for &m in matrices[1..].iter() {
+			let instance = bounds.transform(m);
+			for i in 0..3 {
+				union.min[i] = union.min[i].min(instance.min[i]);
+				union.max[i] = union.max[i].max(instance.max[i]);
+			}
+		}
+		assert_eq!(union, batch_bounds);
+	}
+
+	#[test]
+	fn test_sampler_spec_behavior() {
+		let spec = SamplerSpec {
+			wrap: SamplerWrapFunction::Clamp,
+			minify: MagnifySamplerFilter::Nearest,
+			magnify: MagnifySamplerFilter::Linear,
+			mipmaps: MipmapMode::Linear,
+			anisotropy: 8,
+		};
+		let behavior = spec.behavior();
+		assert_eq!((SamplerWrapFunction::Clamp, SamplerWrapFunction::Clamp, SamplerWrapFunction::Clamp),
+			behavior.wrap_function);
+		assert_eq!(MinifySamplerFilter::NearestMipmapLinear, behavior.minify_filter);
+		assert_eq!(MagnifySamplerFilter::Linear, behavior.magnify_filter);
+		assert_eq!(8, behavior.max_anisotropy);
+
+		let behavior = SamplerSpec::default().behavior();
+		assert_eq!(SamplerWrapFunction::Repeat, behavior.wrap_function.0);
+		assert_eq!(MinifySamplerFilter::Linear, behavior.minify_filter);
+		assert_eq!(1, behavior.max_anisotropy);
+	}
+}
@@ -1,9 +1,13 @@
 //! Module for dealing with heightmaps.
 
+/// Deterministic 2D noise, for generating heightmaps. The same seed and
+/// coordinates always give the same value.
+pub mod noise;
 /// Simple in-memory heightmap with multiple levels of detail.
 pub mod simpleheightmap;
 
-use linear_algebra::Vec3;
+use linear_algebra::{Aabb, Vec3};
+use std::ops::{Add, Div, Mul, Sub};
 
 /// Minimum functionality for a heightmap.
 pub trait Heightmap<'a, T: Copy> {
@@ -11,7 +15,87 @@ pub trait Heightmap<'a, T: Copy> {
 	/// Get the mesh triangle under a given 3D position, for collision purposes.
 	fn get_tri_from_position(&self, pos: &Vec3<T>) -> [Vec3<T>; 3];
 
+	/// Get the box around the heightmap: in X and Z, the area over which
+	/// `get_tri_from_position` finds ground, and in Y, from the lowest to the
+	/// highest point.
+	fn bounds(&self) -> Aabb<T>;
+
+	/// Whether `pos` is within the heightmap's X/Z bounds, so
+	/// `get_tri_from_position` finds ground under it (unless there's a hole).
+	fn contains_position(&self, pos: &Vec3<T>) -> bool where T: PartialOrd {
+		let bounds = self.bounds();
+		pos[0] >= bounds.min[0] && pos[0] <= bounds.max[0] &&
+			pos[2] >= bounds.min[2] && pos[2] <= bounds.max[2]
+	}
+
 	/// Update levels of detail based on the camera's position.
 	fn update_lod(&mut self, pos: &Vec3<T>);
 
+	/// Get the height of the surface under a given 3D position, interpolated
+	/// across the triangle from `get_tri_from_position`. The position's Y is
+	/// ignored.
+	///
+	/// Positions which aren't over the heightmap get a height which compares
+	/// false against everything.
+	fn get_height_at(&self, pos: &Vec3<T>) -> T
+			where T: Default + Add<Output=T> + Sub<Output=T> + Mul<Output=T> + Div<Output=T> {
+		self.get_height_at_xz(pos[0], pos[2])
+	}
+
+	/// Get the height of the surface at a given X and Z position; see
+	/// `get_height_at`.
+	fn get_height_at_xz(&self, x: T, z: T) -> T
+			where T: Default + Add<Output=T> + Sub<Output=T> + Mul<Output=T> + Div<Output=T> {
+		let tri = self.get_tri_from_position(&Vec3::from([x, T::default(), z]));
+		let (v0, v1, v2) = (tri[0], tri[1], tri[2]);
+		// Barycentric coordinates of (x, z) in the triangle, on the XZ plane
+		let det = (v1[2] - v2[2]) * (v0[0] - v2[0]) + (v2[0] - v1[0]) * (v0[2] - v2[2]);
+		let l0 = ((v1[2] - v2[2]) * (x - v2[0]) + (v2[0] - v1[0]) * (z - v2[2])) / det;
+		let l1 = ((v2[2] - v0[2]) * (x - v2[0]) + (v0[0] - v2[0]) * (z - v2[2])) / det;
+		v2[1] + l0 * (v0[1] - v2[1]) + l1 * (v1[1] - v2[1])
+	}
+
+}
+
+#[cfg(test)]
+mod tests {
+	use linear_algebra::{Aabb, Vec3};
+	use super::Heightmap;
+
+	/// A single sloped triangle, for any position.
+	struct TriangleHeightmap;
+	impl<'a> Heightmap<'a, f32> for TriangleHeightmap {
+		fn get_tri_from_position(&self, _: &Vec3<f32>) -> [Vec3<f32>; 3] {
+			[Vec3::from([0.0, 1.0, 0.0]),
+				Vec3::from([2.0, 3.0, 0.0]),
+				Vec3::from([0.0, -1.0, 2.0])]
+		}
+		fn bounds(&self) -> Aabb<f32> {
+			Aabb { min: Vec3::from([0.0, -1.0, 0.0]), max: Vec3::from([2.0, 3.0, 2.0]) }
+		}
+		fn update_lod(&mut self, _: &Vec3<f32>) { }
+	}
+
+	#[test]
+	fn test_get_height_at() {
+		let map = TriangleHeightmap;
+		// Exactly the vertices' heights at the vertices
+		assert_eq!(1.0, map.get_height_at_xz(0.0, 0.0));
+		assert_eq!(3.0, map.get_height_at_xz(2.0, 0.0));
+		assert_eq!(-1.0, map.get_height_at_xz(0.0, 2.0));
+		// And on the plane through them in between
+		assert!((map.get_height_at_xz(0.5, 0.5) - 1.0).abs() < 1e-6);
+		assert!((map.get_height_at_xz(1.0, 0.5) - 1.5).abs() < 1e-6);
+		// Y is ignored
+		assert_eq!(map.get_height_at_xz(1.0, 0.5), map.get_height_at(&Vec3::from([1.0, 100.0, 0.5])));
+	}
+
+	#[test]
+	fn test_contains_position() {
+		let map = TriangleHeightmap;
+		assert!(map.contains_position(&Vec3::from([1.0, 100.0, 1.0])));
+		assert!(map.contains_position(&Vec3::from([2.0, 0.0, 0.0])));
+		assert!(!map.contains_position(&Vec3::from([-0.1, 0.0, 1.0])));
+		assert!(!map.contains_position(&Vec3::from([1.0, 0.0, 2.1])));
+	}
 }
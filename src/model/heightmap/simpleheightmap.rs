@@ -1,22 +1,59 @@
 
+use errors::*;
 use glium::backend::Facade;
-use linear_algebra::{Mat4, Vec3};
-use model::{gpu, mem, Vertex};
+use image::{DynamicImage, GrayAlphaImage, ImageOutputFormat, LumaA};
+use linear_algebra::{Aabb, Mat4, Vec3};
+use model::{gpu, mem, Vertex, WHITE};
 use model::heightmap::Heightmap;
+use model::heightmap::noise::{fractal_noise, NoiseParams};
+use rayon::prelude::*;
 use renderable::{DefaultRenderState, Renderable};
-use std::cmp::min;
+use std::cmp::{min, Ordering};
+use std::collections::{BTreeMap, HashMap};
 use std::f32;
+use std::hash::Hash;
+use std::io;
 use std::rc::Rc;
+use std::time::Instant;
+use timing;
 use glium::Frame;
 
 /// The spacing between rows of a mesh of equilateral triangles with sides of
 /// length one. This is equal to 0.5 * tan(pi / 3).
 const ROW_SPACING: f32 = 0.8660254037844386;
 
+/// Default number of tile meshes kept by the LoD cache, besides those being
+/// drawn.
+pub const DEFAULT_LOD_CACHE_SIZE: usize = 64;
+
 #[derive(Copy, Clone, Debug)]
 struct HeightmapVertex {
 	height: f32,
-	metadata: (),
+	metadata: VertexMetadata,
+}
+
+/// Per-vertex heightmap data other than height.
+#[derive(Copy, Clone, Debug, Default)]
+struct VertexMetadata {
+	/// Whether there's a hole in the terrain at this vertex. Triangles touching
+	/// it aren't drawn, and can't be stood on.
+	hole: bool,
+}
+
+/// Where a heightmap's vertices go in the world, and how they're split into
+/// tiles.
+#[derive(Copy, Clone, Debug)]
+pub struct TerrainLayout {
+	/// The heightmap is drawn in square tiles `tile_size` vertices on a side,
+	/// each with its own LoD. This must be a power of two, smaller than the
+	/// heightmap's width and height.
+	pub tile_size: usize,
+	/// World x position of the first vertex.
+	pub x_offset: f32,
+	/// World z position of the first vertex.
+	pub z_offset: f32,
+	/// Distance between neighbouring vertices.
+	pub resolution: f32,
 }
 
 /// A heightmap, with high-resolution geometry stored entirely in-memory.
@@ -24,70 +61,50 @@ pub struct SimpleHeightmap<'a> {
 	geometry: SimpleHeightmapGeometry,
 	display: &'a Facade,
 	material: Rc<mem::Material>,
-	lods: Vec<gpu::Model>,
+	sampler: gpu::SamplerSpec,
+	/// The meshes being drawn, by tile corner and LoD.
+	lods: Vec<((usize, usize, usize), gpu::Model)>,
+	/// Meshes for tiles at LoDs not currently drawn, in case they come back.
+	lod_cache: LruCache<(usize, usize, usize), gpu::Model>,
 	tile_size: usize,
 	lod_zone: (f32, f32),
+	lod_bias: f32,
+	lod_zone_scale: f32,
+	/// Lowest and highest heights. Only `set_height` changes heights, and it
+	/// keeps this up to date.
+	height_range: (f32, f32),
 }
 
 impl<'a> Heightmap<'a, f32> for SimpleHeightmap<'a> {
 
 	/// Get the triangle under the given position in 3D space
+	///
+	/// This is always from the full resolution mesh. Coarser LoD tiles away
+	/// from the camera may look higher or lower, but the tile under the camera
+	/// is always drawn at full resolution.
 	fn get_tri_from_position(&self, pos: &Vec3<f32>) -> [Vec3<f32>; 3] {
-		let g = &self.geometry;
-		// If we're not over the heightmap, collide at -infinity
-		//TODO: These bounds could certainly be tighter, but it's not likely to matter
-		if pos[0] < g.x_offset  + 1.0 * g.resolution ||
-				pos[0] > g.x_offset + (g.width as f32 - 1.0) * g.resolution ||
-				pos[2] < g.z_offset + 1.0 * g.resolution ||
-				pos[2] > g.z_offset + (g.height() as f32 - 1.0) * g.resolution * ROW_SPACING {
-			return [Vec3::from([0.0, f32::NEG_INFINITY, 0.0]),
-					Vec3::from([1.0, f32::NEG_INFINITY, 0.0]),
-					Vec3::from([0.0, f32::NEG_INFINITY, 1.0])];
-		}
-
-		// For reference
-		//
-		//    A-----B
-		//   /|\ 2 /|\
-		//  / |1\ /3| \
-		// C--k--D--l--E
-		//
-		let vtx_a = g.get_index_from_position(pos);
-		let vtx_a_pos = g.get_position(vtx_a);
-		let vtx_a_z = vtx_a / g.width;
-		let vtx_a_x = vtx_a % g.width;
-		let vtx_d_z = vtx_a_z + 1;
-		let vtx_d_x = if vtx_a_z % 2 == 0 { vtx_a_x } else { vtx_a_x + 1};
-		let vtx_d = g.get_index(vtx_d_x, vtx_d_z);
-		let vtx_d_pos = g.get_position(vtx_d);
+		self.geometry.get_tri_from_position(pos)
+	}
 
-		// Case 1 or 2/3: are we below A-D?
-		let m = (vtx_d_pos[2] - vtx_a_pos[2]) / (vtx_d_pos[0] - vtx_a_pos[0]);
-		let b = vtx_a_pos[2] - m * vtx_a_pos[0];
-		if pos[2] > m * pos[0] + b {
-			// Case 1
-			let vtx_c_pos = g.get_position(vtx_d - 1);
-			return [vtx_a_pos, vtx_d_pos, vtx_c_pos];
-		} else {
-			//Case 2 or 3: are we above B-D?
-			let vtx_b_pos = g.get_position(vtx_a + 1);
-			let m = (vtx_b_pos[2] - vtx_d_pos[2]) / (vtx_b_pos[0] - vtx_d_pos[0]);
-			let b = vtx_b_pos[2] - m * vtx_b_pos[0];
-			if pos[2] < m * pos[0] + b {
-				// Case 2
-				return [vtx_a_pos, vtx_b_pos, vtx_d_pos];
-			} else {
-				// Case 3
-				let vtx_e_pos = g.get_position(vtx_d + 1);
-				return [vtx_b_pos, vtx_e_pos, vtx_d_pos];
-			}
+	/// Get the extent of the heightmap
+	fn bounds(&self) -> Aabb<f32> {
+		let (min, max) = self.geometry.bounds();
+		Aabb {
+			min: Vec3::from([min[0], self.height_range.0, min[1]]),
+			max: Vec3::from([max[0], self.height_range.1, max[1]]),
 		}
 	}
 
+	/// Whether the position is over the heightmap
+	fn contains_position(&self, pos: &Vec3<f32>) -> bool {
+		self.geometry.contains_position(pos)
+	}
+
 	/// Update the GPU geometry to account for changing level of detail with location.
 	fn update_lod(&mut self, pos: &Vec3<f32>) {
 		// Compute LoD zone under pos
-		let lod_zone_size = self.tile_size as f32 * self.geometry.resolution;
+		let lod_zone_size = self.tile_size as f32 * self.geometry.resolution *
+				self.lod_zone_scale;
 		let diff = ((pos[0] - self.lod_zone.0).abs(), (pos[2] - self.lod_zone.1).abs());
 		if diff.0.is_nan() || diff.1.is_nan() ||
 			diff.0 > lod_zone_size || diff.0 < 0.0 ||
@@ -95,64 +112,229 @@ impl<'a> Heightmap<'a, f32> for SimpleHeightmap<'a> {
 			// Update LoD zone 
 			let new_lod_zone = (pos[0] - (pos[0] % (lod_zone_size / 2.0)),
 				pos[2] - (pos[2] % (lod_zone_size / 2.0)));
-			//TODO: Range.step_by is recent and unstable.
-//XXX
-self.lods.clear();
-			let mut x = 0;
-			while x < self.geometry.width {
-				let mut z = 0;
-				while z < self.geometry.height() {
-					let lod = gen_lod(&self, pos, x, z);
-					let top_z = z;
-					let left_x = x;
-					let bottom_z = z + self.tile_size;
-					let right_x = x + self.tile_size;
-					self.lods.push(gpu::Model::from_mem(self.display,
+			let start = Instant::now();
+			let keys = tile_corners(self.geometry.width, self.geometry.height(), self.tile_size)
+				.into_iter()
+				.map(|(x, z)| (x, z, gen_lod(&self.geometry, self.tile_size, self.lod_bias, pos, x, z)))
+				.collect();
+			let drawn = self.lods.drain(..).collect();
+			let tiles = reuse_tiles(keys, drawn, &mut self.lod_cache);
+			// Building meshes is pure computation, so do it in parallel; only
+			// uploading them needs the display.
+			let stale: Vec<_> = tiles.iter()
+				.filter(|&&(_, ref model)| model.is_none())
+				.map(|&(key, _)| key)
+				.collect();
+			let geometry = &self.geometry;
+			let tile_size = self.tile_size;
+			let meshes: Vec<Result<mem::Geometry>> = stale.par_iter()
+				.map(|&(x, z, lod)| geometry.as_geometry(lod, x, z, x + tile_size, z + tile_size))
+				.collect();
+			let built = meshes.len();
+			let mut meshes = meshes.into_iter();
+			for (key, model) in tiles {
+				let model = match model {
+					Some(model) => model,
+					None => match meshes.next().unwrap().and_then(|mesh|
+						gpu::Model::from_mem_with_sampler(self.display,
 							&mem::Model {
-								geometry: Rc::new(self.geometry.as_geometry(
-										lod, left_x, top_z, right_x, bottom_z)),
+								geometry: Rc::new(mesh),
 								material: self.material.clone(),
-							}).unwrap() );
-					z += self.tile_size;
-				}
-				x += self.tile_size;
+							},
+							self.sampler)) {
+						Ok(model) => model,
+						Err(e) => {
+							// Leave a hole rather than drawing garbage
+							error!("Could not build heightmap tile: {}", e);
+							continue;
+						},
+					},
+				};
+				self.lods.push((key, model));
 			}
+			info!("Updated heightmap LoDs in {:.1} ms, building {} of {} tiles",
+				timing::millis(start.elapsed()), built, self.lods.len());
 			self.lod_zone = new_lod_zone;
-		} else {
 		}
 	}
 
 }
 
-fn gen_lod(hm: &SimpleHeightmap, pos: &Vec3<f32>, x: usize, z: usize) -> usize {
+/// The triangle returned for positions with no ground under them, such as
+/// those off the edge of the heightmap or over holes. Everything collides with
+/// it at -infinity.
+fn fall_through() -> [Vec3<f32>; 3] {
+	[Vec3::from([0.0, f32::NEG_INFINITY, 0.0]),
+		Vec3::from([1.0, f32::NEG_INFINITY, 0.0]),
+		Vec3::from([0.0, f32::NEG_INFINITY, 1.0])]
+}
+
+/// Pair each tile to draw, by key, with its mesh if one can be reused: from
+/// the tiles currently `drawn` if its LoD hasn't changed, or else from
+/// `cache`. Tiles which have no mesh need building.
+///
+/// Drawn meshes which aren't reused go into `cache`, so unchanged tiles are
+/// never dropped, however small the cache is.
+fn reuse_tiles<K: Eq + Hash + Clone, V>(keys: Vec<K>, drawn: Vec<(K, V)>, cache: &mut LruCache<K, V>)
+		-> Vec<(K, Option<V>)> {
+	let mut drawn: HashMap<K, V> = drawn.into_iter().collect();
+	let tiles = keys.into_iter()
+		.map(|key| {
+			let model = drawn.remove(&key).or_else(|| cache.take(&key));
+			(key, model)
+		})
+		.collect();
+	for (key, model) in drawn {
+		cache.insert(key, model);
+	}
+	tiles
+}
+
+/// A fixed-size cache which, when full, drops whatever was inserted longest
+/// ago.
+///
+/// Values are moved out of the cache to be used, and reinserted when they're
+/// done with, so this drops the least recently used values.
+struct LruCache<K, V> {
+	entries: HashMap<K, (V, u64)>,
+	/// Keys by insertion time, oldest first.
+	ages: BTreeMap<u64, K>,
+	/// Incremented on each insertion, to order entries by age.
+	clock: u64,
+	capacity: usize,
+}
+
+impl<K: Eq + Hash + Clone, V> LruCache<K, V> {
+	fn new(capacity: usize) -> LruCache<K, V> {
+		LruCache { entries: HashMap::new(), ages: BTreeMap::new(), clock: 0, capacity: capacity }
+	}
+
+	/// Remove and return the value for `key`, if there is one.
+	fn take(&mut self, key: &K) -> Option<V> {
+		let (value, time) = match self.entries.remove(key) {
+			Some(entry) => entry,
+			None => return None,
+		};
+		self.ages.remove(&time);
+		Some(value)
+	}
+
+	/// Add a value, dropping the oldest if the cache is over capacity.
+	fn insert(&mut self, key: K, value: V) {
+		self.take(&key);
+		self.clock += 1;
+		self.ages.insert(self.clock, key.clone());
+		self.entries.insert(key, (value, self.clock));
+		self.evict();
+	}
+
+	fn set_capacity(&mut self, capacity: usize) {
+		self.capacity = capacity;
+		self.evict();
+	}
+
+	fn clear(&mut self) {
+		self.entries.clear();
+		self.ages.clear();
+	}
+
+	fn evict(&mut self) {
+		while self.entries.len() > self.capacity {
+			let oldest = self.ages.keys().next().cloned();
+			match oldest.and_then(|time| self.ages.remove(&time)) {
+				Some(key) => self.entries.remove(&key),
+				None => break,
+			};
+		}
+	}
+}
+
+/// Check that `tile_size` is a power of two, and smaller than a heightmap
+/// `width` by `height` vertices.
+fn check_tile_size(tile_size: usize, width: usize, height: usize) -> Result<()> {
+	if !tile_size.is_power_of_two() {
+		bail!("Heightmap tile size {} is not a power of two", tile_size);
+	}
+	if tile_size >= width || tile_size >= height {
+		bail!("Heightmap tile size {} is not smaller than the {}x{} heightmap",
+			tile_size, width, height);
+	}
+	Ok(())
+}
+
+/// Get the width (X) and height (Z) in vertices of a heightmap read from a
+/// texture, or an error if it's empty or its rows aren't all the same length.
+///
+/// Texture rows run along X, one per Z, so an image's width and height are
+/// the heightmap's; see `disk::load_texture`.
+fn map_dimensions(map: &Vec<Vec<(u8, u8, u8, u8)>>) -> Result<(usize, usize)> {
+	let width = match map.first() {
+		Some(row) if !row.is_empty() => row.len(),
+		_ => bail!("Heightmap image is empty"),
+	};
+	if let Some(z) = map.iter().position(|row| row.len() != width) {
+		bail!("Heightmap image row {} is {} pixels wide, not {}", z, map[z].len(), width);
+	}
+	Ok((width, map.len()))
+}
+
+/// The top left corners of the LoD tiles covering a heightmap `width` by
+/// `height` vertices, in the order their LoDs are stored.
+fn tile_corners(width: usize, height: usize, tile_size: usize) -> Vec<(usize, usize)> {
+	let mut corners = Vec::new();
+	for x in (0..width).step_by(tile_size) {
+		for z in (0..height).step_by(tile_size) {
+			corners.push((x, z));
+		}
+	}
+	corners
+}
+
+/// Compute the LoD for the tile with its top left corner at `x`, `z`, seen
+/// from `pos`.
+///
+/// `lod_bias` is added to the (base 2) log of the LoD before rounding, so
+/// positive values give coarser geometry and negative values finer.
+fn gen_lod(geometry: &SimpleHeightmapGeometry,
+		tile_size: usize,
+		lod_bias: f32,
+		pos: &Vec3<f32>,
+		x: usize,
+		z: usize) -> usize {
+	// The tile under the camera is always at full resolution, so the ground
+	// the character stands on looks the same as the ground it collides with.
+	let left = x as f32 * geometry.resolution + geometry.x_offset;
+	let top = z as f32 * geometry.resolution * ROW_SPACING + geometry.z_offset;
+	let size = tile_size as f32 * geometry.resolution;
+	if pos[0] >= left && pos[0] < left + size &&
+			pos[2] >= top && pos[2] < top + size * ROW_SPACING {
+		return 1;
+	}
+
 	// Compute tile center
-	let center_x = (x as f32 + hm.tile_size as f32 / 2.0) *
-			hm.geometry.resolution + hm.geometry.x_offset;
-	let center_z = (z as f32 + hm.tile_size as f32 / 2.0) *
-			hm.geometry.resolution * ROW_SPACING + hm.geometry.z_offset;
+	let center_x = (x as f32 + tile_size as f32 / 2.0) *
+			geometry.resolution + geometry.x_offset;
+	let center_z = (z as f32 + tile_size as f32 / 2.0) *
+			geometry.resolution * ROW_SPACING + geometry.z_offset;
 
 	// Compute distance between location and center
 	let distance_square = (pos[0] - center_x) * (pos[0] - center_x) +
 			(pos[2] - center_z) * (pos[2] - center_z);
 	let tile_distance_square = distance_square / 
-			(hm.tile_size as f32 * hm.geometry.resolution *
-			hm.tile_size as f32 * hm.geometry.resolution);
+			(tile_size as f32 * geometry.resolution *
+			tile_size as f32 * geometry.resolution);
 
 	// This is the greatest power of two less than distance_square
-	min(f32::max(1.0, tile_distance_square.log(2.0).floor().exp2()) as usize,
-			hm.tile_size)
+	min(f32::max(1.0, (tile_distance_square.log(2.0) + lod_bias).floor().exp2()) as usize,
+			tile_size)
 }
 
 impl<'a, 'b> Renderable<&'a DefaultRenderState<'a>, &'a mut Frame> for SimpleHeightmap<'b> {
 	fn render(&self, renderstate: &'a DefaultRenderState, target: &mut Frame) {
-		for model in self.lods.iter() {
+		for &(_, ref model) in self.lods.iter() {
 			gpu::ModelInstance {
 				model: &model,
-				model_matrix: Mat4::from( [
-					[1.0,		0.0,	0.0,	0.0],
-					[0.0,		1.0,	0.0,	0.0],
-					[0.0,		0.0,	1.0,	0.0],
-					[0.0,		0.0,	0.0,	1.0] ], ) }
+				model_matrix: Mat4::identity() }
 				.render(renderstate, target)
 			// Draw LoD HuD in center of tile
 		}
@@ -161,54 +343,212 @@ impl<'a, 'b> Renderable<&'a DefaultRenderState<'a>, &'a mut Frame> for SimpleHei
 
 impl<'a> SimpleHeightmap<'a> {
 
-	/// Create a heightmap at a particular size.
+	/// Create a heightmap at a particular size, laid out as `layout` says.
 	pub fn with_size(width: usize,
 			height: usize,
-			x_offset: f32,
-			z_offset: f32,
-			resolution: f32,
+			layout: TerrainLayout,
 			display: &'a Facade,
-			material: mem::Material) -> SimpleHeightmap<'a> {
+			material: mem::Material) -> Result<SimpleHeightmap<'a>> {
+		try!{ check_tile_size(layout.tile_size, width, height) };
 		let mut heightmap = SimpleHeightmap {
 			geometry: SimpleHeightmapGeometry {
 				width: width,
 				heights: Vec::with_capacity(width * height),
-				x_offset: x_offset,
-				z_offset: z_offset,
-				resolution: resolution, },
+				x_offset: layout.x_offset,
+				z_offset: layout.z_offset,
+				resolution: layout.resolution, },
 			display: display,
 			material: Rc::new(material),
+			sampler: gpu::SamplerSpec::default(),
 			lods: Vec::new(),
-			tile_size: 256, //FIXME: Probably shouldn't be hardcoded.
+			lod_cache: LruCache::new(DEFAULT_LOD_CACHE_SIZE),
+			tile_size: layout.tile_size,
 			lod_zone: (f32::NAN, f32::NAN),
+			lod_bias: 0.0,
+			lod_zone_scale: 1.0,
+			height_range: (0.0, 0.0),
 		};
 		heightmap.geometry.heights.resize(
 				width * height,
-				HeightmapVertex { height: 0.0, metadata: () });
-		heightmap
+				HeightmapVertex { height: 0.0, metadata: VertexMetadata::default() });
+		Ok(heightmap)
+	}
+
+	/// Get the LoD bias. See `set_lod_bias`.
+	pub fn lod_bias(&self) -> f32 {
+		self.lod_bias
+	}
+
+	/// Set the LoD bias, which is added to the (base 2) log of each tile's
+	/// LoD. Positive values give coarser geometry, and negative values finer.
+	///
+	/// LoDs are recomputed on the next `update_lod`.
+	pub fn set_lod_bias(&mut self, lod_bias: f32) {
+		self.lod_bias = lod_bias;
+		self.lod_zone = (f32::NAN, f32::NAN);
+	}
+
+	/// Get the LoD zone scale. See `set_lod_zone_scale`.
+	pub fn lod_zone_scale(&self) -> f32 {
+		self.lod_zone_scale
+	}
+
+	/// Set the size of the LoD zone, as a multiple of the tile size. LoDs are
+	/// recomputed when the camera leaves the zone.
+	///
+	/// LoDs are recomputed on the next `update_lod`.
+	pub fn set_lod_zone_scale(&mut self, lod_zone_scale: f32) {
+		self.lod_zone_scale = lod_zone_scale;
+		self.lod_zone = (f32::NAN, f32::NAN);
+	}
+
+	/// Get the number of tiles drawn, each with its own draw call.
+	pub fn tile_count(&self) -> usize {
+		self.lods.len()
+	}
+
+	/// Rebuild the in-memory models of the tiles being drawn, at their current
+	/// LoDs, e.g. to save them for debugging.
+	pub fn tile_models(&self) -> Result<Vec<mem::Model>> {
+		self.lods.iter().map(|&((x, z, lod), _)| {
+			let geometry = try!{ self.geometry.as_geometry(
+					lod, x, z, x + self.tile_size, z + self.tile_size) };
+			Ok(mem::Model { geometry: Rc::new(geometry), material: self.material.clone() })
+		}).collect()
+	}
+
+	/// Get the number of tile meshes kept for reuse. See
+	/// `set_lod_cache_size`.
+	pub fn lod_cache_size(&self) -> usize {
+		self.lod_cache.capacity
+	}
+
+	/// Set the number of tile meshes kept for reuse when their tiles change
+	/// LoD, besides those being drawn. The least recently drawn are dropped
+	/// first.
+	pub fn set_lod_cache_size(&mut self, size: usize) {
+		self.lod_cache.set_capacity(size);
+	}
+
+	/// Get how the terrain's texture is sampled. See `set_sampler`.
+	pub fn sampler(&self) -> gpu::SamplerSpec {
+		self.sampler
+	}
+
+	/// Set how the terrain's texture is sampled, for instance to use mipmaps
+	/// and anisotropic filtering so it doesn't shimmer in the distance.
+	///
+	/// The geometry is regenerated on the next `update_lod`.
+	pub fn set_sampler(&mut self, sampler: gpu::SamplerSpec) {
+		self.sampler = sampler;
+		self.lod_cache.clear();
+		self.lods.clear();
+		self.lod_zone = (f32::NAN, f32::NAN);
+	}
+
+	/// Set the height at a particular x/z coordinate.
+	fn set_height(&mut self, x: usize, z: usize, height: f32) {
+		self.geometry.set_height(x, z, height);
+		self.height_range = (self.height_range.0.min(height), self.height_range.1.max(height));
+	}
+
+	/// Get the slope, in radians from horizontal, of the terrain at the
+	/// vertex at a particular x/z coordinate.
+	pub fn slope_at(&self, x: usize, z: usize) -> f32 {
+		self.geometry.slope_at(x, z)
+	}
+
+	/// Smooth the terrain, reducing noise like the steps between heights in
+	/// an 8-bit heightmap image. Each iteration replaces every height with the
+	/// average of itself and its neighbors; more iterations give smoother
+	/// terrain, but lose more of its original shape.
+	///
+	/// The geometry is regenerated on the next `update_lod`.
+	pub fn smooth(&mut self, iterations: usize) {
+		self.height_range = self.geometry.smooth(iterations);
+		self.lod_cache.clear();
+		self.lods.clear();
+		self.lod_zone = (f32::NAN, f32::NAN);
+	}
+
+	/// Mark or unmark a hole in the terrain at a particular x/z coordinate.
+	/// The triangles around a hole aren't drawn, and characters fall through
+	/// them.
+	///
+	/// The geometry is regenerated on the next `update_lod`.
+	pub fn set_hole(&mut self, x: usize, z: usize, hole: bool) {
+		let index = self.geometry.get_index(x, z);
+		self.geometry.heights[index].metadata.hole = hole;
+		self.lod_cache.clear();
+		self.lods.clear();
+		self.lod_zone = (f32::NAN, f32::NAN);
 	}
 
 	/// Create a heightmap object from a texture
+	///
+	/// Image x is the heightmap's X and image y its Z, so each row of the
+	/// texture is a row of vertices along X. Fully transparent pixels are
+	/// holes in the terrain.
 	pub fn from_map(map: &Vec<Vec<(u8, u8, u8, u8)>>,
 			lowest: f32,
 			highest: f32,
-			x_offset: f32,
-			z_offset: f32,
-			resolution: f32,
+			layout: TerrainLayout,
 			display: &'a Facade,
-			material: mem::Material) -> SimpleHeightmap<'a> {
-		let width = map.len();
-		let height = map[0].len();
-		let mut heightmap = SimpleHeightmap::with_size(
-				width, height, x_offset, z_offset, resolution, display, material);
-		for (x, row) in map.iter().enumerate() {
-			for (z, cell) in row.iter().enumerate() {
-				let mut height = (cell.0 as f32 + cell.1 as f32 + cell.2 as f32) / 768.0;
-				height = height * (highest - lowest) + lowest;
-				heightmap.geometry.set_height(x, z, height);
+			material: mem::Material) -> Result<SimpleHeightmap<'a>> {
+		let (width, height) = try!{ map_dimensions(map) };
+		let mut heightmap = try!{ SimpleHeightmap::with_size(width, height, layout, display, material) };
+		heightmap.height_range = heightmap.geometry.set_heights_from_map(map, lowest, highest);
+		Ok(heightmap)
+	}
+
+	/// Write the heightmap as a grayscale PNG image, the inverse of
+	/// `from_map`. Heights are clamped to [`lowest`, `highest`], and holes are
+	/// fully transparent.
+	pub fn save_to_png<W: io::Write>(&self, writer: &mut W, lowest: f32, highest: f32)
+			-> Result<()> {
+		self.geometry.save_to_png(writer, lowest, highest)
+	}
+
+	/// Get the terrain's vertex normals as a texture, in the layout
+	/// `from_map` reads, with X, Y and Z mapped from [-1, 1] to [0, 255] in
+	/// the red, green and blue channels. Holes are fully transparent.
+	///
+	/// These are world space normals covering the whole heightmap, so they
+	/// aren't suitable for a material's (tangent space) `normal_map`.
+	pub fn generate_normal_map(&self) -> Vec<Vec<(u8, u8, u8, u8)>> {
+		self.geometry.generate_normal_map()
+	}
+
+	/// Create a heightmap from fractal noise, as described by `noise`.
+	pub fn from_noise(width: usize,
+			height: usize,
+			noise: NoiseParams,
+			layout: TerrainLayout,
+			display: &'a Facade,
+			material: mem::Material) -> Result<SimpleHeightmap<'a>> {
+		let mut heightmap = try!{ SimpleHeightmap::with_size(width, height, layout, display, material) };
+		for x in 0..width {
+			for z in 0..height {
+				let value = fractal_noise(
+						noise.seed, x as f32, z as f32, noise.octaves, noise.frequency);
+				heightmap.set_height(x, z, value * noise.amplitude);
 			}
 		}
-		heightmap
+		Ok(heightmap)
+	}
+
+	/// Create a heightmap from a function giving the height at each vertex's
+	/// world x/z position.
+	pub fn from_fn<F>(width: usize,
+			height: usize,
+			layout: TerrainLayout,
+			display: &'a Facade,
+			material: mem::Material,
+			f: F) -> Result<SimpleHeightmap<'a>>
+			where F: Fn(f32, f32) -> f32 {
+		let mut heightmap = try!{ SimpleHeightmap::with_size(width, height, layout, display, material) };
+		heightmap.height_range = heightmap.geometry.set_heights_from_fn(f);
+		Ok(heightmap)
 	}
 
 }
@@ -223,12 +563,161 @@ struct SimpleHeightmapGeometry {
 
 impl SimpleHeightmapGeometry {
 
+	/// Get the triangle under the given position in 3D space
+	fn get_tri_from_position(&self, pos: &Vec3<f32>) -> [Vec3<f32>; 3] {
+		let g = self;
+		// If we're not over the heightmap, collide at -infinity
+		if !g.contains_position(pos) {
+			return fall_through();
+		}
+
+		// For reference
+		//
+		//    A-----B
+		//   /|\ 2 /|\
+		//  / |1\ /3| \
+		// C--k--D--l--E
+		//
+		let vtx_a = g.get_index_from_position(pos);
+		let vtx_a_pos = g.get_position(vtx_a);
+		let vtx_a_z = vtx_a / g.width;
+		let vtx_a_x = vtx_a % g.width;
+		let vtx_d_z = vtx_a_z + 1;
+		let vtx_d_x = if vtx_a_z % 2 == 0 { vtx_a_x } else { vtx_a_x + 1};
+		let vtx_d = g.get_index(vtx_d_x, vtx_d_z);
+		let vtx_d_pos = g.get_position(vtx_d);
+
+		// Case 1 or 2/3: are we below A-D?
+		let m = (vtx_d_pos[2] - vtx_a_pos[2]) / (vtx_d_pos[0] - vtx_a_pos[0]);
+		let b = vtx_a_pos[2] - m * vtx_a_pos[0];
+		let tri = if pos[2] > m * pos[0] + b {
+			// Case 1
+			[vtx_a, vtx_d, vtx_d - 1]
+		} else {
+			//Case 2 or 3: are we above B-D?
+			let vtx_b_pos = g.get_position(vtx_a + 1);
+			let m = (vtx_b_pos[2] - vtx_d_pos[2]) / (vtx_b_pos[0] - vtx_d_pos[0]);
+			let b = vtx_b_pos[2] - m * vtx_b_pos[0];
+			if pos[2] < m * pos[0] + b {
+				// Case 2
+				[vtx_a, vtx_a + 1, vtx_d]
+			} else {
+				// Case 3
+				[vtx_a + 1, vtx_d + 1, vtx_d]
+			}
+		};
+
+		// Fall through holes
+		if tri.iter().any(|&index| g.heights[index].metadata.hole) {
+			return fall_through();
+		}
+		[g.get_position(tri[0]), g.get_position(tri[1]), g.get_position(tri[2])]
+	}
+
+	/// Get the minimum and maximum X and Z positions, as `[x, z]`, over which
+	/// every position has a full triangle under it.
+	///
+	/// Triangles under a position can use vertices one column to either side
+	/// and, on odd rows (which are shifted half a column right), two columns
+	/// to the right. So this leaves out the first column and the last two.
+	//TODO: These bounds could certainly be tighter, but it's not likely to matter
+	fn bounds(&self) -> ([f32; 2], [f32; 2]) {
+		([self.x_offset + 1.0 * self.resolution,
+				self.z_offset + 1.0 * self.resolution],
+			[self.x_offset + (self.width as f32 - 2.0) * self.resolution,
+				self.z_offset + (self.height() as f32 - 2.0) * self.resolution * ROW_SPACING])
+	}
+
+	/// Whether the position is within `bounds`.
+	fn contains_position(&self, pos: &Vec3<f32>) -> bool {
+		let (min, max) = self.bounds();
+		pos[0] >= min[0] && pos[0] <= max[0] && pos[2] >= min[1] && pos[2] <= max[1]
+	}
+
 	/// Set the height at a particular x/z coordinate.
 	fn set_height(&mut self, x: usize, y: usize, height: f32) {
 		let index = self.get_index(x, y);
 		self.heights[index].height = height;
 	}
 
+	/// Set every height from a function of the vertex's world x/z position.
+	/// Returns the lowest and highest heights set.
+	fn set_heights_from_fn<F>(&mut self, f: F) -> (f32, f32) where F: Fn(f32, f32) -> f32 {
+		let mut range = (f32::INFINITY, f32::NEG_INFINITY);
+		for index in 0..self.heights.len() {
+			let position = self.get_position(index);
+			let height = f(position[0], position[2]);
+			self.heights[index].height = height;
+			range = (range.0.min(height), range.1.max(height));
+		}
+		range
+	}
+
+	/// Set heights and holes from a texture, as described for
+	/// `SimpleHeightmap::from_map`. Returns the lowest and highest heights.
+	fn set_heights_from_map(&mut self,
+			map: &Vec<Vec<(u8, u8, u8, u8)>>,
+			lowest: f32,
+			highest: f32) -> (f32, f32) {
+		let mut range = (f32::INFINITY, f32::NEG_INFINITY);
+		for (z, row) in map.iter().enumerate() {
+			for (x, cell) in row.iter().enumerate() {
+				let mut height = (cell.0 as f32 + cell.1 as f32 + cell.2 as f32) / 768.0;
+				height = height * (highest - lowest) + lowest;
+				let index = self.get_index(x, z);
+				self.heights[index].height = height;
+				self.heights[index].metadata.hole = cell.3 == 0;
+				range = (range.0.min(height), range.1.max(height));
+			}
+		}
+		range
+	}
+
+	/// Write heights and holes as a grayscale PNG image which
+	/// `set_heights_from_map` reads back. Each gray level is three of the 768
+	/// steps between `lowest` and `highest` that a texture can encode.
+	fn save_to_png<W: io::Write>(&self, writer: &mut W, lowest: f32, highest: f32)
+			-> Result<()> {
+		let image = GrayAlphaImage::from_fn(self.width as u32, self.height() as u32, |x, z| {
+			let vertex = &self.heights[self.get_index(x as usize, z as usize)];
+			let level = (vertex.height - lowest) / (highest - lowest) * 256.0;
+			let alpha = if vertex.metadata.hole { 0 } else { 255 };
+			LumaA([level.round().max(0.0).min(255.0) as u8, alpha])
+		});
+		DynamicImage::ImageLumaA8(image).write_to(writer, ImageOutputFormat::Png)
+			.chain_err(|| "Could not write heightmap image")
+	}
+
+	/// Get vertex normals as a texture; see
+	/// `SimpleHeightmap::generate_normal_map`.
+	fn generate_normal_map(&self) -> Vec<Vec<(u8, u8, u8, u8)>> {
+		let channel = |c: f32| ((c * 0.5 + 0.5) * 255.0).round() as u8;
+		(0..self.height()).map(|z| (0..self.width).map(|x| {
+			let normal = self.get_vertex(x, z).normal;
+			let hole = self.heights[self.get_index(x, z)].metadata.hole;
+			(channel(normal[0]), channel(normal[1]), channel(normal[2]), if hole { 0 } else { 255 })
+		}).collect()).collect()
+	}
+
+	/// Smooth the terrain by replacing each height with the average of itself
+	/// and its neighbors, `iterations` times. Returns the new lowest and
+	/// highest heights.
+	fn smooth(&mut self, iterations: usize) -> (f32, f32) {
+		for _ in 0..iterations {
+			let smoothed: Vec<f32> = (0..self.heights.len()).map(|index| {
+				let adjacents = self.get_adjacent_vertices(index % self.width, index / self.width);
+				let sum = adjacents.iter()
+					.fold(self.heights[index].height, |sum, &adj| sum + self.heights[adj].height);
+				sum / (adjacents.len() + 1) as f32
+			}).collect();
+			for (vertex, height) in self.heights.iter_mut().zip(smoothed) {
+				vertex.height = height;
+			}
+		}
+		self.heights.iter().fold((f32::INFINITY, f32::NEG_INFINITY),
+			|range, vertex| (range.0.min(vertex.height), range.1.max(vertex.height)))
+	}
+
 	/// Get the vertex at a particular x/z coordinate.
 	fn get_vertex(&self, x: usize, z: usize) -> Vertex {
 		let index = self.get_index(x, z);
@@ -236,57 +725,76 @@ impl SimpleHeightmapGeometry {
 		// Compute the position.
 		let position = self.get_position(index);
 
-		// Compute the normal.
-		// For all adjacent vertices:
-		let adjacents = self.get_adjacent_vertices(x, z);
-		let norm = adjacents.len() as f32;
+		// Compute the normal, averaging the (area-weighted) normals of the
+		// triangles around the vertex. Each triangle is between two adjacent
+		// vertices 60 degrees apart around this one; wider gaps between them
+		// are off the edge of the heightmap.
+		let mut adjacents: Vec<(f32, Vec3<f32>)> = self.get_adjacent_vertices(x, z).into_iter()
+			.map(|adj_index| {
+				let offset = self.get_position(adj_index) - position;
+				(offset[2].atan2(offset[0]), offset)
+			})
+			.collect();
+		adjacents.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
 		let mut normal = Vec3::from([0f32; 3]);
-		for adj_index in adjacents {
-			// Compute the normal to the surface between this vertex and the adjacent
-			let adj_pos = self.get_position(adj_index);
-			let parallel = position - adj_pos;
-			let axis = {
-				let xz_norm = f32::sqrt(parallel[0] * parallel[0] + parallel[2] * parallel[2]);
-				Vec3::from([parallel[1] / xz_norm, 0.0, parallel[0] / xz_norm])
-			};
-			let cross = axis.cross(parallel);
-			let dot = axis.dot(parallel);
-			let adj_normal = cross + (axis * dot);
-			adj_normal.normalize();
-			// Add them all up
-			normal = normal + adj_normal;
+		for (i, &(angle, offset)) in adjacents.iter().enumerate() {
+			let (next_angle, next_offset) = adjacents[(i + 1) % adjacents.len()];
+			if (next_angle - angle).rem_euclid(2.0 * f32::consts::PI) < f32::consts::FRAC_PI_2 {
+				let face_normal = offset.cross(next_offset);
+				normal = normal + if face_normal[1] < 0.0 { -face_normal } else { face_normal };
+			}
 		}
-		// Normalize
-		normal = normal / norm;
+		normal = if normal.length_squared() > 0.0 {
+			normal.normalize()
+		} else {
+			Vec3::from([0.0, 1.0, 0.0])
+		};
 
-		// Texture mapping
+		// Texture mapping, with U along X
 		let tex_uv = [position[0], position[2]];
+		let tangent = mem::perpendicular_tangent(normal, Vec3::from([1.0, 0.0, 0.0]));
 
 		Vertex {
 			position: position.into(),
 			normal: normal.into(),
+			tangent: tangent.into(),
 			tex_uv: tex_uv,
+			color: WHITE,
 		}
 	}
 
+	/// Get the angle in radians between the terrain's normal at a particular
+	/// x/z coordinate and straight up.
+	fn slope_at(&self, x: usize, z: usize) -> f32 {
+		self.get_vertex(x, z).normal[1].max(-1.0).min(1.0).acos()
+	}
+
 	/// Get the height in rows of this Heightmap.
 	fn height(&self) -> usize {
 		self.heights.len() / self.width
 	}
 
 	/// Convert this heightmap to in-memory 3D geometry.
+	///
+	/// This fails if the tile has too many vertices for 16-bit indices.
 	fn as_geometry(&self,
 	               lod: usize,
 				   left_x: usize,
 				   top_z: usize,
 				   right_x: usize,
-				   bottom_z: usize) -> mem::Geometry {
+				   bottom_z: usize) -> Result<mem::Geometry> {
 		let left_x = min(left_x, self.width);
 		let top_z = min(top_z, self.height());
 		let right_x = min(right_x, self.width);
 		let bottom_z = min(bottom_z, self.height());
-		let width = (right_x - left_x) / lod;
-		let height = (bottom_z - top_z) / lod;
+		// Tiles at the edge of the heightmap may not be a multiple of the LoD
+		// across; they still get a vertex at the start of each step.
+		let width = (right_x - left_x + lod - 1) / lod;
+		let height = (bottom_z - top_z + lod - 1) / lod;
+		if width * height > u16::max_value() as usize + 1 {
+			bail!("Heightmap tile {},{}-{},{} has {} vertices at LoD {}, more than 16-bit \
+					indices can address", left_x, top_z, right_x, bottom_z, width * height, lod);
+		}
 		let mut vertices = Vec::with_capacity(width * height);
 		let mut indices = Vec::new();
 		let mut z = top_z;
@@ -296,28 +804,34 @@ impl SimpleHeightmapGeometry {
 			let mut idx_x = 0;
 			while x < right_x {
 				vertices.push(self.get_vertex(x, z));
-				// Compute indices
-				//TODO: If the tile dimensions are not evenly divisible by the
-				// LoD, this will generate out-of-bounds indices.
-				if x < right_x - lod && z < bottom_z - lod {
+				// Compute indices, matching the triangles
+				// `get_tri_from_position` collides with at full resolution.
+				if x + lod < right_x && z + lod < bottom_z {
+					let top_left = idx_x + idx_z * width;
+					let bottom_left = top_left + width;
 					if z % 2 == 0 {
-						// First triangle:
-						indices.push((idx_z + idx_x * width) as u16);
-						indices.push((idx_z + (idx_x + 1) * width) as u16);
-						indices.push((idx_z + 1 + idx_x * width) as u16);
-						// Second triangle:
-						indices.push((idx_z + 1 + idx_x * width) as u16);
-						indices.push((idx_z + (idx_x + 1) * width) as u16);
-						indices.push((idx_z + 1 + (idx_x + 1) * width) as u16);
+						// Odd rows are shifted right, so the row below
+						// leans right:
+						//   TL---TR
+						//    \ 1 / \
+						//     BL---BR
+						indices.push(top_left as u16);
+						indices.push(bottom_left as u16);
+						indices.push((top_left + 1) as u16);
+						indices.push(bottom_left as u16);
+						indices.push((bottom_left + 1) as u16);
+						indices.push((top_left + 1) as u16);
 					} else {
-						// First triangle:
-						indices.push((idx_z + idx_x * width) as u16);
-						indices.push((idx_z + 1 + (idx_x + 1) * width) as u16);
-						indices.push((idx_z + 1 + idx_x * width) as u16);
-						// Second triangle:
-						indices.push((idx_z + idx_x * width) as u16);
-						indices.push((idx_z + (idx_x + 1) * width) as u16);
-						indices.push((idx_z + 1 + (idx_x + 1) * width) as u16);
+						// And under an odd row, it leans left:
+						//     TL---TR
+						//    / 1 \ /
+						//  BL---BR
+						indices.push(top_left as u16);
+						indices.push(bottom_left as u16);
+						indices.push((bottom_left + 1) as u16);
+						indices.push(top_left as u16);
+						indices.push((bottom_left + 1) as u16);
+						indices.push((top_left + 1) as u16);
 					}
 				}
 				x += lod;
@@ -327,18 +841,44 @@ impl SimpleHeightmapGeometry {
 			idx_z += 1;
 		}
 
+		// Drop triangles touching holes
+		let holes: Vec<bool> = (0..vertices.len())
+				.map(|i| self.is_hole_near(
+						left_x + (i % width) * lod,
+						top_z + (i / width) * lod,
+						lod))
+				.collect();
+		let indices: Vec<u16> = indices.chunks(3)
+				.filter(|tri| !tri.iter().any(|&i|
+						holes.get(i as usize).cloned().unwrap_or(false)))
+				.flat_map(|tri| tri.iter().cloned())
+				.collect();
+
+		// Every index must refer to a vertex
 		let vs = vertices.len();
-		let mi = indices.iter().max().unwrap().clone() as usize;
-		if mi != vs || vs > u16::max_value() as usize + 1 {
-			error!("LoD vertices and indices mismatch for tile {},{}-{},{}: \
-					vertices: {}, max index: {}",
-					left_x, top_z, right_x, bottom_z, vs, mi);
+		if let Some(mi) = indices.iter().max().map(|&i| i as usize) {
+			if mi >= vs {
+				bail!("LoD vertices and indices mismatch for tile {},{}-{},{}: \
+						vertices: {}, max index: {}",
+						left_x, top_z, right_x, bottom_z, vs, mi);
+			}
 		}
 
-		mem::Geometry {
-			vertices: vertices,
-			indices: indices,
+		Ok(mem::Geometry::new(vertices, indices))
+	}
+
+	/// Whether there are any holes in the `lod` by `lod` block of vertices with
+	/// its top left corner at the given x/z coordinate, which a single vertex
+	/// covers at that LoD.
+	fn is_hole_near(&self, x: usize, z: usize, lod: usize) -> bool {
+		for hole_z in z..min(z + lod, self.height()) {
+			for hole_x in x..min(x + lod, self.width) {
+				if self.heights[self.get_index(hole_x, hole_z)].metadata.hole {
+					return true;
+				}
+			}
 		}
+		false
 	}
 
 	/// Get the index into the heights vector from an x/z coordinate pair.
@@ -420,7 +960,9 @@ impl SimpleHeightmapGeometry {
 #[cfg(test)]
 mod tests {
 	use super::SimpleHeightmapGeometry;
-	use super::HeightmapVertex;
+	use super::{HeightmapVertex, VertexMetadata};
+	use super::{check_tile_size, gen_lod, map_dimensions, reuse_tiles, tile_corners, LruCache};
+	use super::ROW_SPACING;
 	use linear_algebra::Vec3;
 
 	#[test]
@@ -441,7 +983,7 @@ mod tests {
 				resolution: 1.0, };
 		map.heights.resize(
 				4 * 4,
-				HeightmapVertex { height: 0.0, metadata: () });
+				HeightmapVertex { height: 0.0, metadata: VertexMetadata::default() });
 
 		// Top left: index 0
 		let expected = vec![4, 1];
@@ -512,7 +1054,7 @@ mod tests {
 				resolution: 1.0, };
 		map.heights.resize(
 				4 * 3,
-				HeightmapVertex { height: 0.0, metadata: () });
+				HeightmapVertex { height: 0.0, metadata: VertexMetadata::default() });
 
 		// Bottom left, even row: index 8
 		let expected = vec![4, 9];
@@ -541,7 +1083,7 @@ mod tests {
 				resolution: 1.0, };
 		map.heights.resize(
 				4 * 4,
-				HeightmapVertex { height: 0.0, metadata: () });
+				HeightmapVertex { height: 0.0, metadata: VertexMetadata::default() });
 
 		for index in 0..16 {
 			let pos = map.get_position(index);
@@ -557,4 +1099,433 @@ mod tests {
 		let pos = Vec3::from([1.51, 0.0, 1.0]);
 		assert_eq!(5, map.get_index_from_position(&pos));
 	}
+
+	#[test]
+	fn test_gen_lod_bias() {
+		let map = SimpleHeightmapGeometry {
+				width: 64,
+				heights: Vec::new(),
+				x_offset: 0.0,
+				z_offset: 0.0,
+				resolution: 1.0, };
+		// Two and a half tiles from the center of the first tile
+		let pos = Vec3::from([4.0 + 2.5 * 8.0, 0.0, 4.0 * ROW_SPACING]);
+		let unbiased = gen_lod(&map, 8, 0.0, &pos, 0, 0);
+		assert_eq!(unbiased, 4);
+		// Positive bias gives coarser tiles, negative finer
+		assert_eq!(gen_lod(&map, 8, 1.0, &pos, 0, 0), 8);
+		assert_eq!(gen_lod(&map, 8, -1.0, &pos, 0, 0), 2);
+		// But always between 1 and the tile size
+		assert_eq!(gen_lod(&map, 8, 10.0, &pos, 0, 0), 8);
+		assert_eq!(gen_lod(&map, 8, -10.0, &pos, 0, 0), 1);
+		// Except under the camera, which is always full resolution
+		let under = Vec3::from([4.0, 0.0, 4.0 * ROW_SPACING]);
+		assert_eq!(gen_lod(&map, 8, 10.0, &under, 0, 0), 1);
+	}
+
+	#[test]
+	fn test_tile_size() {
+		for &(tile_size, tiles) in [(32, 64 * 48), (64, 32 * 24), (128, 16 * 12)].iter() {
+			assert!(check_tile_size(tile_size, 2048, 1536).is_ok());
+			let corners = tile_corners(2048, 1536, tile_size);
+			assert_eq!(tiles, corners.len(), "tile size {}", tile_size);
+			assert_eq!(Some(&(2048 - tile_size, 1536 - tile_size)), corners.last());
+		}
+		// Partial tiles at the edges get a tile of their own
+		assert_eq!(3 * 2, tile_corners(130, 100, 64).len());
+
+		assert!(check_tile_size(48, 1024, 1024).is_err());
+		assert!(check_tile_size(0, 1024, 1024).is_err());
+		assert!(check_tile_size(128, 1024, 128).is_err());
+		assert!(check_tile_size(256, 128, 1024).is_err());
+	}
+
+	#[test]
+	fn test_lru_cache() {
+		let mut cache = LruCache::new(2);
+		cache.insert((0, 0, 1), "a");
+		cache.insert((0, 0, 2), "b");
+		assert_eq!(Some("a"), cache.take(&(0, 0, 1)));
+		assert_eq!(None, cache.take(&(0, 0, 1)));
+		cache.insert((0, 0, 1), "a");
+		// "b" is now the oldest
+		cache.insert((8, 0, 1), "c");
+		assert_eq!(None, cache.take(&(0, 0, 2)));
+		assert_eq!(Some("c"), cache.take(&(8, 0, 1)));
+		cache.insert((8, 0, 1), "c");
+
+		cache.set_capacity(1);
+		assert_eq!(None, cache.take(&(0, 0, 1)));
+		assert_eq!(Some("c"), cache.take(&(8, 0, 1)));
+	}
+
+	#[test]
+	fn test_reuse_tiles() {
+		// Tiles which keep their LoD are reused even with no cache at all
+		let mut cache = LruCache::new(0);
+		let drawn = vec![((0, 0, 1), "a"), ((8, 0, 1), "b")];
+		let tiles = reuse_tiles(vec![(0, 0, 1), (8, 0, 2)], drawn, &mut cache);
+		assert_eq!(vec![((0, 0, 1), Some("a")), ((8, 0, 2), None)], tiles);
+		assert_eq!(None, cache.take(&(8, 0, 1)));
+
+		// Tiles which change LoD go into the cache, and come back from it
+		let mut cache = LruCache::new(1);
+		let drawn = vec![((0, 0, 1), "a"), ((8, 0, 1), "b")];
+		let tiles = reuse_tiles(vec![(0, 0, 1), (8, 0, 2)], drawn, &mut cache);
+		let drawn = tiles.into_iter()
+			.map(|(key, model)| (key, model.unwrap_or("b2")))
+			.collect();
+		let tiles = reuse_tiles(vec![(0, 0, 1), (8, 0, 1)], drawn, &mut cache);
+		assert_eq!(vec![((0, 0, 1), Some("a")), ((8, 0, 1), Some("b"))], tiles);
+		assert_eq!(Some("b2"), cache.take(&(8, 0, 2)));
+	}
+
+	fn flat_map(size: usize) -> SimpleHeightmapGeometry {
+		flat_rect(size, size)
+	}
+
+	fn flat_rect(width: usize, height: usize) -> SimpleHeightmapGeometry {
+		let mut map = SimpleHeightmapGeometry {
+				width: width,
+				heights: Vec::with_capacity(width * height),
+				x_offset: 0.0,
+				z_offset: 0.0,
+				resolution: 1.0, };
+		map.heights.resize(
+				width * height,
+				HeightmapVertex { height: 0.0, metadata: VertexMetadata::default() });
+		map
+	}
+
+	#[test]
+	fn test_non_square_map() {
+		use image::{DynamicImage, ImageFormat, ImageOutputFormat, Rgba, RgbaImage};
+		use model::disk::load_texture;
+		use std::io::Cursor;
+
+		// 4 pixels wide and 2 high, with each pixel a different gray
+		let image = RgbaImage::from_fn(4, 2, |x, y| {
+			let gray = (y * 4 + x) as u8 * 3;
+			Rgba([gray, gray, gray, 255])
+		});
+		let mut png = Vec::new();
+		DynamicImage::ImageRgba8(image).write_to(&mut png, ImageOutputFormat::Png).unwrap();
+		let texture = load_texture(&mut Cursor::new(png), ImageFormat::Png).unwrap();
+		assert_eq!((4, 2), map_dimensions(&texture).unwrap());
+
+		// Image x is heightmap X, and image y is Z
+		let (width, height) = map_dimensions(&texture).unwrap();
+		let mut map = flat_rect(width, height);
+		map.set_heights_from_map(&texture, 0.0, 768.0);
+		assert_eq!(4, map.width);
+		assert_eq!(2, map.height());
+		for x in 0..4 {
+			for z in 0..2 {
+				let expected = (z * 4 + x) as f32 * 9.0;
+				assert_eq!(expected, map.heights[map.get_index(x, z)].height, "({}, {})", x, z);
+			}
+		}
+
+		assert!(map_dimensions(&vec![]).is_err());
+		assert!(map_dimensions(&vec![vec![]]).is_err());
+		assert!(map_dimensions(&vec![vec![(0, 0, 0, 255); 4], vec![(0, 0, 0, 255); 3]]).is_err());
+	}
+
+	#[test]
+	fn test_set_heights_from_fn() {
+		let mut map = flat_map(8);
+		map.x_offset = -4.0;
+		map.z_offset = 2.0;
+		assert_eq!((3.5, 3.5), map.set_heights_from_fn(|_, _| 3.5));
+		for x in 0..8 {
+			for z in 0..8 {
+				let vertex = map.get_vertex(x, z);
+				assert_eq!(3.5, vertex.position[1]);
+				let normal = Vec3::from(vertex.normal);
+				assert!((normal - Vec3::from([0.0, 1.0, 0.0])).length() < 1e-5,
+					"normal {:?} at {}, {}", normal, x, z);
+			}
+		}
+
+		// The function is given world positions
+		let (low, high) = map.set_heights_from_fn(|x, z| x + z);
+		for index in 0..map.heights.len() {
+			let position = map.get_position(index);
+			assert_eq!(position[0] + position[2], position[1]);
+		}
+		assert_eq!((-4.0 + 2.0, map.get_position(63)[1]), (low, high));
+	}
+
+	#[test]
+	fn test_slope_at() {
+		let mut map = flat_map(6);
+		let slopes = |map: &SimpleHeightmapGeometry|
+			(0..36).map(|i| map.slope_at(i % 6, i / 6)).collect::<Vec<_>>();
+		for slope in slopes(&map) {
+			assert!(slope.abs() < 1e-5, "{}", slope);
+		}
+
+		// Rising one unit per unit along X or Z, including at the edges
+		let quarter = ::std::f32::consts::FRAC_PI_4;
+		map.set_heights_from_fn(|x, _| x);
+		for slope in slopes(&map) {
+			assert!((slope - quarter).abs() < 1e-5, "{}", slope);
+		}
+		map.set_heights_from_fn(|_, z| -z);
+		for slope in slopes(&map) {
+			assert!((slope - quarter).abs() < 1e-5, "{}", slope);
+		}
+	}
+
+	#[test]
+	fn test_save_to_png() {
+		use image::{DynamicImage, ImageFormat, ImageOutputFormat, Rgba, RgbaImage};
+		use model::disk::load_texture;
+		use std::io::Cursor;
+
+		let image = RgbaImage::from_fn(4, 4, |x, y| {
+			let gray = (x * 70 + y * 9) as u8;
+			Rgba([gray, gray, gray, if (x, y) == (2, 1) { 0 } else { 255 }])
+		});
+		let mut png = Vec::new();
+		DynamicImage::ImageRgba8(image).write_to(&mut png, ImageOutputFormat::Png).unwrap();
+		let texture = load_texture(&mut Cursor::new(png), ImageFormat::Png).unwrap();
+		let mut map = flat_map(4);
+		map.set_heights_from_map(&texture, -10.0, 50.0);
+
+		let mut saved = Vec::new();
+		map.save_to_png(&mut saved, -10.0, 50.0).unwrap();
+		let reloaded_texture = load_texture(&mut Cursor::new(saved), ImageFormat::Png).unwrap();
+		assert_eq!(texture, reloaded_texture);
+		let mut reloaded = flat_map(4);
+		reloaded.set_heights_from_map(&reloaded_texture, -10.0, 50.0);
+		for (original, vertex) in map.heights.iter().zip(reloaded.heights.iter()) {
+			assert!((original.height - vertex.height).abs() <= 60.0 / 768.0,
+				"{} became {}", original.height, vertex.height);
+			assert_eq!(original.metadata.hole, vertex.metadata.hole);
+		}
+		assert!(reloaded.heights[reloaded.get_index(2, 1)].metadata.hole);
+
+		// Heights outside the range are clamped
+		let mut saved = Vec::new();
+		map.save_to_png(&mut saved, 0.0, 1.0).unwrap();
+		let clamped = load_texture(&mut Cursor::new(saved), ImageFormat::Png).unwrap();
+		assert_eq!((0, 0, 0, 255), clamped[0][0]);
+		assert_eq!((255, 255, 255, 255), clamped[3][3]);
+	}
+
+	#[test]
+	fn test_generate_normal_map() {
+		let mut map = flat_map(8);
+		let hole = map.get_index(4, 4);
+		map.heights[hole].metadata.hole = true;
+		let flat = map.generate_normal_map();
+		assert_eq!(8, flat.len());
+		for (z, row) in flat.iter().enumerate() {
+			assert_eq!(8, row.len());
+			for (x, &texel) in row.iter().enumerate() {
+				let alpha = if (x, z) == (4, 4) { 0 } else { 255 };
+				assert_eq!((128, 255, 128, alpha), texel, "at {}, {}", x, z);
+			}
+		}
+
+		// A dome, so the normals around the edge all point outwards
+		let (center_x, center_z) = (3.75, 3.5 * ROW_SPACING);
+		map.set_heights_from_fn(|x, z| -0.2 * ((x - center_x).powi(2) + (z - center_z).powi(2)));
+		let dome = map.generate_normal_map();
+		for i in 1..7 {
+			assert!(dome[i][0].0 < 128, "{:?} at 0, {}", dome[i][0], i);
+			assert!(dome[i][7].0 > 128, "{:?} at 7, {}", dome[i][7], i);
+			assert!(dome[0][i].2 < 128, "{:?} at {}, 0", dome[0][i], i);
+			assert!(dome[7][i].2 > 128, "{:?} at {}, 7", dome[7][i], i);
+		}
+		// Each channel is within one step of the normal
+		let normal = map.get_vertex(2, 5).normal;
+		let texel = dome[5][2];
+		for &(c, n) in [(texel.0, normal[0]), (texel.1, normal[1]), (texel.2, normal[2])].iter() {
+			assert!((c as f32 / 255.0 * 2.0 - 1.0 - n).abs() < 2.0 / 255.0,
+				"{:?} vs {:?}", texel, normal);
+		}
+	}
+
+	/// Compare building every tile of a 512x512 heightmap serially and in
+	/// parallel, as `update_lod` does. Run with
+	/// `cargo test --release -- --ignored --nocapture test_parallel_tiles`.
+	#[test]
+	#[ignore]
+	fn test_parallel_tiles() {
+		use rayon::prelude::*;
+		use std::time::Instant;
+		use timing;
+
+		let tile_size = 32;
+		let mut map = flat_rect(512, 512);
+		map.set_heights_from_fn(|x, z| (x * 0.1).sin() * (z * 0.07).cos() * 10.0);
+		let tiles = tile_corners(map.width, map.height(), tile_size);
+		let build = |&(x, z): &(usize, usize)|
+			map.as_geometry(1, x, z, x + tile_size, z + tile_size).unwrap();
+
+		let start = Instant::now();
+		let serial: Vec<_> = tiles.iter().map(&build).collect();
+		let serial_time = start.elapsed();
+		let start = Instant::now();
+		let parallel: Vec<_> = tiles.par_iter().map(&build).collect();
+		let parallel_time = start.elapsed();
+
+		println!("{} tiles: serial {:.1} ms, parallel {:.1} ms on {} threads",
+			tiles.len(), timing::millis(serial_time), timing::millis(parallel_time),
+			::rayon::current_num_threads());
+		for (a, b) in serial.iter().zip(parallel.iter()) {
+			assert_eq!(a.indices, b.indices);
+			assert!(a.vertices.iter().zip(b.vertices.iter()).all(|(a, b)| a.position == b.position));
+		}
+	}
+
+	#[test]
+	fn test_smooth() {
+		let mut map = flat_map(3);
+		let center = map.get_index(1, 1);
+		map.heights[center].height = 9.0;
+		let spread = |map: &SimpleHeightmapGeometry| {
+			let heights: Vec<f32> = map.heights.iter().map(|v| v.height).collect();
+			heights.iter().cloned().fold(f32::NEG_INFINITY, f32::max) -
+				heights.iter().cloned().fold(f32::INFINITY, f32::min)
+		};
+
+		let (low, high) = map.smooth(1);
+		assert_eq!(high - low, spread(&map));
+		assert!(map.heights[center].height < 9.0);
+		// The spike spreads to its neighbors
+		assert!(map.heights[map.get_index(1, 0)].height > 0.0);
+		let once = spread(&map);
+		assert!(once < 9.0, "{}", once);
+
+		map.smooth(4);
+		assert!(spread(&map) < once);
+		assert_eq!(0.0, flat_map(3).smooth(3).1);
+	}
+
+	#[test]
+	fn test_holes() {
+		let mut map = flat_map(8);
+		let solid = map.as_geometry(1, 0, 0, 8, 8).unwrap();
+		let hole = map.get_index(3, 3);
+		map.heights[hole].metadata.hole = true;
+		let holey = map.as_geometry(1, 0, 0, 8, 8).unwrap();
+
+		// Exactly the triangles around the hole are gone, and the rest are
+		// unchanged.
+		let expected: Vec<u16> = solid.indices.chunks(3)
+				.filter(|tri| !tri.contains(&(hole as u16)))
+				.flat_map(|tri| tri.iter().cloned())
+				.collect();
+		assert_eq!(solid.indices.len() - 6 * 3, expected.len());
+		assert_eq!(expected, holey.indices);
+		assert_eq!(solid.vertices.len(), holey.vertices.len());
+
+		// Coarser LoDs still have a hole
+		let coarse_solid = flat_map(8).as_geometry(2, 0, 0, 8, 8).unwrap();
+		let coarse_holey = map.as_geometry(2, 0, 0, 8, 8).unwrap();
+		assert!(coarse_holey.indices.len() < coarse_solid.indices.len());
+
+		// We fall through the hole, but not its neighbors
+		let over_hole = map.get_position(hole) + Vec3::from([0.25, 0.0, 0.25]);
+		assert_eq!(map.get_tri_from_position(&over_hole)[0][1], ::std::f32::NEG_INFINITY);
+		let beside_hole = map.get_position(map.get_index(5, 3)) + Vec3::from([0.25, 0.0, 0.25]);
+		assert_eq!(map.get_tri_from_position(&beside_hole)[0][1], 0.0);
+	}
+
+	#[test]
+	fn test_bounds() {
+		let map = flat_map(8);
+		let (min, max) = map.bounds();
+		// Every position inside the bounds, including on odd rows right up
+		// against the last column, has a triangle from the rows around it.
+		let steps = 64;
+		for i in 0..(steps + 1) {
+			for j in 0..(steps + 1) {
+				let x = min[0] + (max[0] - min[0]) * i as f32 / steps as f32;
+				let z = min[1] + (max[1] - min[1]) * j as f32 / steps as f32;
+				let pos = Vec3::from([x, 0.0, z]);
+				let tri = map.get_tri_from_position(&pos);
+				for vertex in tri.iter() {
+					assert_eq!(0.0, vertex[1], "fell through at {:?}", pos);
+					assert!((vertex[0] - x).abs() <= 1.0 + 1e-4 &&
+							(vertex[2] - z).abs() <= ROW_SPACING + 1e-4,
+						"{:?} is too far from {:?}", vertex, pos);
+				}
+			}
+		}
+	}
+
+	/// Height of the plane through a triangle at the given X/Z position, if
+	/// the position is inside the triangle's X/Z projection.
+	fn height_in_triangle(tri: [Vec3<f32>; 3], x: f32, z: f32) -> Option<f32> {
+		let (a, b, c) = (tri[0], tri[1], tri[2]);
+		let det = (b[2] - c[2]) * (a[0] - c[0]) + (c[0] - b[0]) * (a[2] - c[2]);
+		if det == 0.0 {
+			return None;
+		}
+		let u = ((b[2] - c[2]) * (x - c[0]) + (c[0] - b[0]) * (z - c[2])) / det;
+		let v = ((c[2] - a[2]) * (x - c[0]) + (a[0] - c[0]) * (z - c[2])) / det;
+		let w = 1.0 - u - v;
+		if u < -1e-4 || v < -1e-4 || w < -1e-4 {
+			return None;
+		}
+		Some(a[1] * u + b[1] * v + c[1] * w)
+	}
+
+	#[test]
+	fn test_as_geometry_counts() {
+		// (LoD, vertices, triangles) for a 3x3 tile; LoD 2 doesn't divide it
+		let map = flat_map(3);
+		for &(lod, vertices, triangles) in [(1, 9, 8), (2, 4, 2), (3, 1, 0), (4, 1, 0)].iter() {
+			let geometry = map.as_geometry(lod, 0, 0, 3, 3).unwrap();
+			assert_eq!(vertices, geometry.vertices.len(), "LoD {}", lod);
+			assert_eq!(triangles * 3, geometry.indices.len(), "LoD {}", lod);
+			assert!(geometry.indices.iter().all(|&i| (i as usize) < vertices), "LoD {}", lod);
+		}
+
+		// Too big for 16-bit indices
+		assert!(flat_map(257).as_geometry(1, 0, 0, 257, 257).is_err());
+		assert!(flat_map(257).as_geometry(2, 0, 0, 257, 257).is_ok());
+	}
+
+	#[test]
+	fn test_collision_matches_rendered_tile() {
+		let mut map = flat_map(8);
+		for z in 0..8 {
+			for x in 0..8 {
+				map.set_height(x, z, ((x * 7 + z * 13) % 5) as f32);
+			}
+		}
+		let geometry = map.as_geometry(1, 0, 0, 8, 8).unwrap();
+		// All triangles face up, for backface culling
+		for tri in geometry.indices.chunks(3) {
+			let p = |i: u16| Vec3::from(geometry.vertices[i as usize].position);
+			assert!((p(tri[1]) - p(tri[0])).cross(p(tri[2]) - p(tri[0]))[1] > 0.0,
+				"{:?} faces down", tri);
+		}
+		let (min, max) = map.bounds();
+		let steps = 16;
+		for i in 0..(steps + 1) {
+			for j in 0..(steps + 1) {
+				let x = min[0] + (max[0] - min[0]) * i as f32 / steps as f32;
+				let z = min[1] + (max[1] - min[1]) * j as f32 / steps as f32;
+				let collision = height_in_triangle(
+						map.get_tri_from_position(&Vec3::from([x, 0.0, z])), x, z)
+					.expect("collision triangle isn't under the position");
+				let rendered = geometry.indices.chunks(3)
+					.filter_map(|tri| height_in_triangle([
+							Vec3::from(geometry.vertices[tri[0] as usize].position),
+							Vec3::from(geometry.vertices[tri[1] as usize].position),
+							Vec3::from(geometry.vertices[tri[2] as usize].position)],
+						x, z))
+					.next()
+					.expect("no rendered triangle under the position");
+				assert!((collision - rendered).abs() < 1e-3,
+					"at {}, {}: collision {}, rendered {}", x, z, collision, rendered);
+			}
+		}
+	}
 }
@@ -1,31 +1,240 @@
 //! Objects that have been loaded from disk and cached in system memory.
 
 use errors::*;
-use model::{disk, Vertex};
+use linear_algebra::{Aabb, Vec3};
+use model::{disk, gltf, Vertex};
 use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs::{self, File};
 use std::io::Read;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
+/// Number of checkerboard cells along each side of the default texture.
+const DEFAULT_TEXTURE_CELLS: usize = 8;
+/// Size, in texels, of each checkerboard cell in the default texture.
+const DEFAULT_TEXTURE_CELL_SIZE: usize = 8;
+/// Specular exponent of materials which don't specify one.
+pub const DEFAULT_SHININESS: f32 = 32.0;
+
+/// Make a 1x1 texture of a single RGBA color, with components from 0 to 1,
+/// for materials without an image.
+pub fn color_texture(color: (f32, f32, f32, f32)) -> Vec<Vec<(u8, u8, u8, u8)>> {
+	let channel = |c: f32| (c.max(0.0).min(1.0) * 255.0).round() as u8;
+	vec![vec![(channel(color.0), channel(color.1), channel(color.2), channel(color.3))]]
+}
+
 /// Generate the default material to fill in if an object-specific material
 /// is not specified or cannot be loaded.
 ///
-/// This generates a magenta diffuse with green specular that should be very
-/// eye-catching.
+/// This generates a magenta and black checkerboard diffuse with green
+/// specular that should be very eye-catching, and shows how the object is
+/// UV mapped.
 pub fn default_mat() -> Material {
+	let size = DEFAULT_TEXTURE_CELLS * DEFAULT_TEXTURE_CELL_SIZE;
+	let texture = (0..size).map(|y| (0..size).map(|x| {
+			if (x / DEFAULT_TEXTURE_CELL_SIZE + y / DEFAULT_TEXTURE_CELL_SIZE) % 2 == 0 {
+				(255, 0, 255, 255)
+			} else {
+				(0, 0, 0, 255)
+			}
+		}).collect()).collect();
 	Material {
 		ambient: (0.0, 0.0, 0.0),
+		diffuse: (1.0, 1.0, 1.0),
 		specular: (0.0, 1.0, 0.0),
-		texture: vec![vec![(255, 0, 255, 255)]],
+		shininess: DEFAULT_SHININESS,
+		emissive: (0.0, 0.0, 0.0),
+		texture: texture,
+		normal_map: None,
+	}
+}
+
+/// Make a normal map from a texture, treating its brightness as height, so
+/// bright parts of the texture look raised. Larger `strength` makes the bumps
+/// steeper.
+///
+/// The texture is assumed to repeat, so the edges are bumpy too.
+pub fn normal_map_from_texture(texture: &Vec<Vec<(u8, u8, u8, u8)>>, strength: f32)
+		-> Vec<Vec<(u8, u8, u8, u8)>> {
+	let rows = texture.len();
+	let height = |row: usize, col: usize| {
+		let row = &texture[row % rows];
+		let texel = row[col % row.len()];
+		(texel.0 as f32 + texel.1 as f32 + texel.2 as f32) / (3.0 * 255.0)
+	};
+	let channel = |c: f32| ((c * 0.5 + 0.5) * 255.0).round() as u8;
+	(0..rows).map(|row| {
+		let cols = texture[row].len();
+		(0..cols).map(|col| {
+			// Rows are V and columns U; slopes are central differences
+			let du = (height(row, col + 1) - height(row, col + cols - 1)) / 2.0;
+			let dv = (height(row + 1, col) - height(row + rows - 1, col)) / 2.0;
+			let normal = Vec3::from([-du * strength, -dv * strength, 1.0]).normalize();
+			(channel(normal[0]), channel(normal[1]), channel(normal[2]), 255)
+		}).collect()
+	}).collect()
+}
+
+/// Make `direction` perpendicular to `normal`, for use as a tangent. If it's
+/// parallel to the normal, or zero, some other perpendicular direction is
+/// chosen.
+pub fn perpendicular_tangent(normal: Vec3<f32>, direction: Vec3<f32>) -> Vec3<f32> {
+	let tangent = direction - normal * normal.dot(direction);
+	if tangent.length_squared() > 1e-12 {
+		return tangent.normalize();
 	}
+	let axis = if normal[0].abs() < 0.9 {
+		Vec3::from([1.0, 0.0, 0.0])
+	} else {
+		Vec3::from([0.0, 1.0, 0.0])
+	};
+	(axis - normal * normal.dot(axis)).normalize()
 }
 
 /// In-memory geometry, that is, `Vertex`s.
+///
+/// Bounds are computed when the geometry is created with `Geometry::new`, so
+/// changing the vertices afterwards leaves them stale.
 #[derive(Debug)]
 pub struct Geometry {
 	/// The object's vertexes.
 	pub vertices: Vec<Vertex>,
 	/// The object's geometry, specified by indexes into the vertex vector.
 	pub indices: Vec<u16>,
+	bounds: Aabb<f32>,
+	sphere: (Vec3<f32>, f32),
+}
+
+impl Geometry {
+	/// Create new geometry from vertices and indexes into them, computing its
+	/// bounds.
+	pub fn new(vertices: Vec<Vertex>, indices: Vec<u16>) -> Geometry {
+		let points: Vec<Vec3<f32>> = vertices.iter()
+				.map(|v| Vec3::from(v.position))
+				.collect();
+		Geometry {
+			vertices: vertices,
+			indices: indices,
+			bounds: Aabb::from_points(points.iter().cloned()),
+			sphere: bounding_sphere(&points),
+		}
+	}
+
+	/// Get the minimum and maximum corners of the axis-aligned bounding box
+	/// of this geometry's vertices.
+	pub fn aabb(&self) -> (Vec3<f32>, Vec3<f32>) {
+		(self.bounds.min, self.bounds.max)
+	}
+
+	/// Get a sphere, as center and radius, containing all of this geometry's
+	/// vertices.
+	///
+	/// This uses Ritter's algorithm, so the sphere is close to, but not
+	/// necessarily, the smallest possible. Empty geometry has a sphere of zero
+	/// radius at the origin.
+	pub fn bounding_sphere(&self) -> (Vec3<f32>, f32) {
+		self.sphere
+	}
+
+	/// Get the center of this geometry's bounding sphere.
+	pub fn center(&self) -> Vec3<f32> {
+		self.sphere.0
+	}
+
+	/// Get the radius of this geometry's bounding sphere.
+	pub fn radius(&self) -> f32 {
+		self.sphere.1
+	}
+
+	/// Replace every vertex's normal with a smooth normal, the average of the
+	/// (area-weighted) normals of the triangles around its position.
+	///
+	/// Vertices at the same position, like either side of a texture seam, get
+	/// the same normal. Vertices which aren't part of any triangle keep their
+	/// normals.
+	pub fn recompute_normals(&mut self) {
+		// Sum of the face normals around each distinct position
+		let key = |v: &Vertex| {
+			let p = v.position;
+			[p[0].to_bits(), p[1].to_bits(), p[2].to_bits()]
+		};
+		let mut sums: HashMap<[u32; 3], Vec3<f32>> = HashMap::new();
+		for tri in self.indices.chunks(3).filter(|tri| tri.len() == 3) {
+			let vertex = |i: usize| &self.vertices[tri[i] as usize];
+			let p = |i: usize| Vec3::from(vertex(i).position);
+			let normal = (p(1) - p(0)).cross(p(2) - p(0));
+			for i in 0..3 {
+				*sums.entry(key(vertex(i))).or_insert(Vec3::from([0.0, 0.0, 0.0])) += normal;
+			}
+		}
+		for vertex in self.vertices.iter_mut() {
+			match sums.get(&key(vertex)) {
+				Some(sum) if sum.length_squared() > 0.0 => vertex.normal = sum.normalize().into(),
+				_ => (),
+			}
+		}
+	}
+
+	/// Replace every vertex's tangent with the direction its texture U
+	/// coordinate increases in, averaged over the triangles it's part of and
+	/// made perpendicular to its normal.
+	///
+	/// Unlike normals, tangents aren't shared between vertices at the same
+	/// position, since either side of a texture seam can run in different
+	/// directions. Vertices without usable texture coordinates get an
+	/// arbitrary tangent perpendicular to their normal.
+	pub fn recompute_tangents(&mut self) {
+		let mut sums = vec![Vec3::from([0.0, 0.0, 0.0]); self.vertices.len()];
+		for tri in self.indices.chunks(3).filter(|tri| tri.len() == 3) {
+			let vertex = |i: usize| &self.vertices[tri[i] as usize];
+			let p = |i: usize| Vec3::from(vertex(i).position);
+			let uv = |i: usize, j: usize| vertex(i).tex_uv[j] - vertex(0).tex_uv[j];
+			let (edge1, edge2) = (p(1) - p(0), p(2) - p(0));
+			let det = uv(1, 0) * uv(2, 1) - uv(2, 0) * uv(1, 1);
+			if det == 0.0 {
+				continue;
+			}
+			let tangent = (edge1 * uv(2, 1) - edge2 * uv(1, 1)) / det;
+			for &index in tri {
+				sums[index as usize] += tangent;
+			}
+		}
+		for (vertex, sum) in self.vertices.iter_mut().zip(sums) {
+			vertex.tangent = perpendicular_tangent(Vec3::from(vertex.normal), sum).into();
+		}
+	}
+}
+
+/// Compute a bounding sphere for some points with Ritter's algorithm.
+fn bounding_sphere(points: &[Vec3<f32>]) -> (Vec3<f32>, f32) {
+	let first = match points.first() {
+		Some(p) => *p,
+		None => return (Vec3::from([0.0, 0.0, 0.0]), 0.0),
+	};
+	let furthest_from = |from: Vec3<f32>| *points.iter()
+			.max_by(|a, b| (**a - from).length_squared()
+				.partial_cmp(&(**b - from).length_squared())
+				.unwrap_or(Ordering::Equal))
+			.unwrap();
+
+	// Start with a sphere spanning two distant points
+	let a = furthest_from(first);
+	let b = furthest_from(a);
+	let mut center = (a + b) / 2.0;
+	let mut radius = (b - a).length() / 2.0;
+
+	// Grow it to cover any points outside it
+	for p in points.iter() {
+		let distance = (*p - center).length();
+		if distance > radius {
+			let new_radius = (radius + distance) / 2.0;
+			center = center + (*p - center) * ((new_radius - radius) / distance);
+			radius = new_radius;
+		}
+	}
+	(center, radius)
 }
 
 /// In-memory material and texture specification.
@@ -34,14 +243,24 @@ pub struct Material {
 	/// The ambient color. This is multiplied by the texture color in unlit
 	/// pixels.
 	pub ambient: (f32, f32, f32),
+	/// The diffuse color. The texture color is multiplied by this.
+	pub diffuse: (f32, f32, f32),
 	/// The specular color. This is added to the matte color in the specular
 	/// highlight.
 	pub specular: (f32, f32, f32),
+	/// The specular exponent. Higher values make smaller, sharper highlights.
+	pub shininess: f32,
+	/// The emissive color. This is added regardless of lighting, so the
+	/// material glows.
+	pub emissive: (f32, f32, f32),
 	/// The texture.
 	///
 	/// This is a nested `Vec` instead of a `glium::texture::RawImage2D`
 	/// because `RawImage2D` lacks needed traits.
 	pub texture: Vec<Vec<(u8, u8, u8, u8)>>,
+	/// The tangent space normal map, if any, in the same layout as `texture`.
+	/// Surfaces without one are lit with their geometric normals.
+	pub normal_map: Option<Vec<Vec<(u8, u8, u8, u8)>>>,
 }
 
 /// In-memory model, including geometry and material.
@@ -56,11 +275,16 @@ pub struct Model {
 /// A library of in-memory models.
 ///
 /// This enables sharing of materials between objects and (eventually)
-/// management of object lifecycle, loading and caching.
+/// management of object lifecycle. Models loaded by path are cached, as are
+/// material libraries, so each file is only loaded once.
 #[derive(Debug)]
 pub struct ModelLibrary {
 	geoms: RefCell<Vec<Rc<Geometry>>>,
 	mats: RefCell<Vec<Rc<Material>>>,
+	/// Models loaded by path, keyed by their canonical path.
+	models_by_path: RefCell<HashMap<PathBuf, Rc<Model>>>,
+	/// Material libraries, keyed by their canonical path, then material name.
+	mat_libs: RefCell<HashMap<PathBuf, HashMap<String, Rc<Material>>>>,
 	/// The set of models in this library.
 	pub models: RefCell<Vec<Rc<Model>>>,
 }
@@ -71,38 +295,428 @@ impl ModelLibrary {
 		ModelLibrary {
 			geoms: RefCell::new(Vec::new()),
 			mats: RefCell::new(Vec::new()),
+			models_by_path: RefCell::new(HashMap::new()),
+			mat_libs: RefCell::new(HashMap::new()),
 			models: RefCell::new(Vec::new()),
 		}
 	}
 
-	/// Load a model into this library, and return an `Rc` to the loaded
-	/// model.
-	pub fn load_model(&self, read: &mut Read) -> Result<Rc<Model>> {
-		//TODO While probably correct, this is fantastically inelegant.
-		let (geom, mat) = try!{ disk::load_model(read) };
-		self.geoms.borrow_mut().push(Rc::new(geom));
-		self.mats.borrow_mut().push(Rc::new(mat));
+	/// Load a model from a `.obj` file with a single object into this library,
+	/// and return an `Rc` to the loaded model.
+	///
+	/// Material and texture paths are relative to `base_dir`, or the working
+	/// directory if it's `None`. Materials already loaded from the same `.mtl`
+	/// file are shared. Objects without a material
+	/// get `default_mat()`. Files with more than one object are an error; see
+	/// `load_models`.
+	pub fn load_model(&self, read: &mut Read, base_dir: Option<&Path>) -> Result<Rc<Model>> {
+		let (objects, mat_path) = try!{ disk::load_geometries(read) };
+		if objects.len() != 1 {
+			bail!("Expected one object in model file, found {}", objects.len());
+		}
+		let mat_path = mat_path.map(|mat_path| disk::resolve_path(base_dir, &mat_path));
+		let mut models = try!{ self.add_objects(objects, mat_path.as_ref()) };
+		Ok(models.pop().unwrap())
+	}
+
+	/// Load every object from a `.obj` file into this library, and return an
+	/// `Rc` to each loaded model, in the order they're in the file.
+	///
+	/// Materials are handled as in `load_model`.
+	pub fn load_models(&self, read: &mut Read, base_dir: Option<&Path>)
+			-> Result<Vec<Rc<Model>>> {
+		let (objects, mat_path) = try!{ disk::load_geometries(read) };
+		let mat_path = mat_path.map(|mat_path| disk::resolve_path(base_dir, &mat_path));
+		self.add_objects(objects, mat_path.as_ref())
+	}
+
+	/// Add objects loaded by `disk::load_geometries` to this library, with
+	/// their materials from the `.mtl` file at `mat_path`, if there is one.
+	/// Objects without a material share one `default_mat()`.
+	fn add_objects(&self, objects: Vec<(Geometry, Option<String>)>, mat_path: Option<&PathBuf>)
+			-> Result<Vec<Rc<Model>>> {
+		let mut models = Vec::with_capacity(objects.len());
+		let mut default = None;
+		for (geom, mat_name) in objects {
+			let mat = match (mat_name, mat_path) {
+				(Some(ref name), Some(mat_path)) => {
+					let key = try!{ self.load_mat_lib(mat_path)
+							.chain_err(|| "Could not load materials") };
+					let mat = self.mat_libs.borrow()[&key].get(name).cloned();
+					if mat.is_none() {
+						error!("Missing material: {:?}", name);
+					}
+					mat
+				},
+				(Some(ref name), None) => {
+					error!("No material library for material: {:?}", name);
+					None
+				},
+				(None, _) => None,
+			};
+			let mat = match mat {
+				Some(mat) => mat,
+				None => default.get_or_insert_with(|| self.add_material(default_mat())).clone(),
+			};
+			self.geoms.borrow_mut().push(Rc::new(geom));
+			let model = Rc::new(Model {
+				//Because we just pushed this, unwrapping last() is safe.
+				geometry: self.geoms.borrow().last().unwrap().clone(),
+				material: mat,
+			});
+			self.models.borrow_mut().push(model.clone());
+			models.push(model);
+		}
+		Ok(models)
+	}
+
+	/// Load a model from a glTF or GLB file with a single mesh into this
+	/// library, and return an `Rc` to the loaded model. See
+	/// `gltf::load_gltf`.
+	pub fn load_gltf_model(&self, read: &mut Read, base_dir: Option<&Path>) -> Result<Rc<Model>> {
+		let mut meshes = try!{ gltf::load_gltf(read, base_dir) };
+		if meshes.len() != 1 {
+			bail!("Expected one mesh in model file, found {}", meshes.len());
+		}
+		let (geom, mat) = meshes.pop().unwrap();
 		let model = Rc::new(Model {
-			//Because we just pushed these, unwrapping last() is safe.
-			geometry: self.geoms.borrow().last().unwrap().clone(),
-			material: self.mats.borrow().last().unwrap().clone(),
+			geometry: Rc::new(geom),
+			material: self.add_material(mat),
 		});
+		self.geoms.borrow_mut().push(model.geometry.clone());
 		self.models.borrow_mut().push(model.clone());
 		Ok(model)
 	}
 
+	/// Load a model from a `.obj`, `.gltf` or `.glb` file (by its extension)
+	/// into this library, and return an `Rc` to the loaded model.
+	///
+	/// Material, texture and buffer paths are relative to the file's
+	/// directory. If the same file has been loaded before, the same model is
+	/// returned without loading it again.
+	pub fn load_model_from_path<P: AsRef<Path>>(&self, path: P) -> Result<Rc<Model>> {
+		let path = path.as_ref();
+		let key = canonical_path(path);
+		if let Some(model) = self.models_by_path.borrow().get(&key) {
+			return Ok(model.clone());
+		}
+		let mut file = try!{ File::open(path)
+				.chain_err(|| format!("Could not load model {}", path.display())) };
+		let extension = path.extension()
+			.and_then(|extension| extension.to_str())
+			.map(|extension| extension.to_lowercase());
+		let model = match extension.as_ref().map(|extension| extension.as_str()) {
+			Some("gltf") | Some("glb") => self.load_gltf_model(&mut file, path.parent()),
+			_ => self.load_model(&mut file, path.parent()),
+		};
+		let model = try!{ model.chain_err(|| format!("Could not load model {}", path.display())) };
+		self.models_by_path.borrow_mut().insert(key, model.clone());
+		Ok(model)
+	}
+
+	/// Load the material called `name` from a `.mtl` file into this library,
+	/// and return an `Rc` to it.
+	///
+	/// Texture paths are relative to the `.mtl` file's directory. Each `.mtl`
+	/// file is only loaded once, and later requests for its materials share
+	/// them.
+	pub fn load_material<P: AsRef<Path>>(&self, path: P, name: &str) -> Result<Rc<Material>> {
+		let path = path.as_ref();
+		let key = try!{ self.load_mat_lib(path) };
+		self.mat_libs.borrow()[&key].get(name).cloned()
+			.ok_or(Error::from(format!("No material \"{}\" in {}", name, path.display())))
+	}
+
+	/// Load a `.mtl` file into this library if it isn't already, and return
+	/// its key in `mat_libs`.
+	fn load_mat_lib(&self, path: &Path) -> Result<PathBuf> {
+		let key = canonical_path(path);
+		if !self.mat_libs.borrow().contains_key(&key) {
+			let mats = try!{ disk::load_mats_from_path(path) };
+			let mats: HashMap<String, Rc<Material>> = mats.into_iter()
+				.map(|(name, mat)| (name, Rc::new(mat)))
+				.collect();
+			self.mats.borrow_mut().extend(mats.values().cloned());
+			self.mat_libs.borrow_mut().insert(key.clone(), mats);
+		}
+		Ok(key)
+	}
+
 	/// Add an existing (already loaded or hardcoded) model into this library,
 	/// and return an `Rc` to the loaded model.
 	pub fn add_model(&self, geom: Geometry, mat: Material) -> Result<Rc<Model>> {
 		self.geoms.borrow_mut().push(Rc::new(geom));
-		self.mats.borrow_mut().push(Rc::new(mat));
+		let material = self.add_material(mat);
 		let model = Rc::new(Model {
-			//Because we just pushed these, unwrapping last() is safe.
+			//Because we just pushed this, unwrapping last() is safe.
 			geometry: self.geoms.borrow().last().unwrap().clone(),
-			material: self.mats.borrow().last().unwrap().clone(),
+			material: material,
 		});
 		self.models.borrow_mut().push(model.clone());
 		Ok(model)
 	}
+
+	/// Add an existing material into this library, and return an `Rc` to it.
+	fn add_material(&self, mat: Material) -> Rc<Material> {
+		let mat = Rc::new(mat);
+		self.mats.borrow_mut().push(mat.clone());
+		mat
+	}
+}
+
+/// The key to cache a file under: its canonical path if it exists, so
+/// different paths to the same file share an entry.
+fn canonical_path(path: &Path) -> PathBuf {
+	fs::canonicalize(path).unwrap_or(path.to_path_buf())
 }
 
+#[cfg(test)]
+mod tests {
+	use linear_algebra::Vec3;
+	use model::{TestDir, Vertex, WHITE};
+	use image;
+	use std::fs::{self, File};
+	use std::io::Write;
+	use std::rc::Rc;
+	use super::{DEFAULT_TEXTURE_CELL_SIZE, DEFAULT_TEXTURE_CELLS};
+	use super::{Geometry, ModelLibrary, default_mat, normal_map_from_texture};
+
+	#[test]
+	fn test_bounding_sphere() {
+		let vertex = |x, y, z| Vertex {
+			position: [x, y, z],
+			normal: [0.0, 1.0, 0.0],
+			tangent: [1.0, 0.0, 0.0],
+			tex_uv: [0.0, 0.0],
+			color: WHITE,
+		};
+		// An irregular octahedron with some interior points
+		let geometry = Geometry::new(
+			vec![
+				vertex(3.0, 0.0, 0.0),
+				vertex(-1.0, 0.0, 0.0),
+				vertex(0.0, 2.5, 0.0),
+				vertex(0.0, -2.0, 0.5),
+				vertex(0.5, 0.0, 4.0),
+				vertex(0.0, 0.0, -1.5),
+				vertex(0.2, 0.3, 0.1),
+				vertex(-0.5, -0.5, -0.5),
+			],
+			vec![0, 2, 4, 1, 3, 5]);
+		let (center, radius) = geometry.bounding_sphere();
+		for v in geometry.vertices.iter() {
+			let distance = (Vec3::from(v.position) - center).length();
+			assert!(distance <= radius + 1e-5, "{:?} outside {:?}, {}", v, center, radius);
+		}
+		// Not much bigger than the furthest pair of points
+		assert!(radius < 3.5, "{}", radius);
+
+		let empty = Geometry::new(vec![], vec![]);
+		assert_eq!(empty.bounding_sphere().1, 0.0);
+	}
+
+	#[test]
+	fn test_bounds() {
+		let vertex = |x, y, z| Vertex {
+			position: [x, y, z],
+			normal: [0.0, 1.0, 0.0],
+			tangent: [1.0, 0.0, 0.0],
+			tex_uv: [0.0, 0.0],
+			color: WHITE,
+		};
+		let geometry = Geometry::new(
+			vec![
+				vertex(1.0, -2.0, 0.5),
+				vertex(-3.0, 4.0, 0.0),
+				vertex(0.0, 0.0, 6.0),
+			],
+			vec![0, 1, 2]);
+		let (min, max) = geometry.aabb();
+		assert_eq!(Vec3::from([-3.0, -2.0, 0.0]), min);
+		assert_eq!(Vec3::from([1.0, 4.0, 6.0]), max);
+		assert_eq!(geometry.bounding_sphere(), (geometry.center(), geometry.radius()));
+	}
+
+	#[test]
+	fn test_recompute_normals() {
+		let vertex = |x, y, z| Vertex {
+			position: [x, y, z],
+			normal: [1.0, 0.0, 0.0],
+			tangent: [0.0, 1.0, 0.0],
+			tex_uv: [0.0, 0.0],
+			color: WHITE,
+		};
+		// A square pyramid with its apex on +Y, and the apex duplicated as if
+		// along a texture seam
+		let mut geometry = Geometry::new(
+			vec![
+				vertex(0.0, 1.0, 0.0),
+				vertex(-1.0, 0.0, -1.0),
+				vertex(-1.0, 0.0, 1.0),
+				vertex(1.0, 0.0, 1.0),
+				vertex(1.0, 0.0, -1.0),
+				vertex(0.0, 1.0, 0.0),
+				vertex(5.0, 5.0, 5.0),
+			],
+			vec![0, 1, 2, 0, 2, 3, 0, 3, 4, 5, 4, 1]);
+		geometry.recompute_normals();
+		let normal = |i: usize| Vec3::from(geometry.vertices[i].normal);
+		assert!((normal(0) - Vec3::from([0.0, 1.0, 0.0])).length() < 1e-5, "{:?}", normal(0));
+		assert_eq!(normal(0), normal(5));
+		// Base corners point out and up
+		let corner = normal(3);
+		assert!(corner[0] > 0.0 && corner[1] > 0.0 && corner[2] > 0.0, "{:?}", corner);
+		assert!((corner.length() - 1.0).abs() < 1e-5);
+		// Not part of any triangle
+		assert_eq!([1.0, 0.0, 0.0], geometry.vertices[6].normal);
+	}
+
+	#[test]
+	fn test_recompute_tangents() {
+		let vertex = |x, y, z, u, v| Vertex {
+			position: [x, y, z],
+			normal: [0.0, 1.0, 0.0],
+			tangent: [0.0, 0.0, 0.0],
+			tex_uv: [u, v],
+			color: WHITE,
+		};
+		// A quad on the XZ plane with U along -Z and V along X, then a
+		// triangle with no texture coordinates
+		let mut geometry = Geometry::new(
+			vec![
+				vertex(0.0, 0.0, 0.0, 0.0, 0.0),
+				vertex(2.0, 0.0, 0.0, 0.0, 1.0),
+				vertex(2.0, 0.0, -2.0, 1.0, 1.0),
+				vertex(0.0, 0.0, -2.0, 1.0, 0.0),
+				vertex(5.0, 0.0, 0.0, 0.0, 0.0),
+				vertex(6.0, 0.0, 0.0, 0.0, 0.0),
+				vertex(5.0, 0.0, 1.0, 0.0, 0.0),
+			],
+			vec![0, 1, 2, 0, 2, 3, 4, 5, 6]);
+		geometry.recompute_tangents();
+		for v in geometry.vertices[0..4].iter() {
+			assert!((Vec3::from(v.tangent) - Vec3::from([0.0, 0.0, -1.0])).length() < 1e-5,
+				"{:?}", v);
+		}
+		for v in geometry.vertices[4..].iter() {
+			let tangent = Vec3::from(v.tangent);
+			assert!((tangent.length() - 1.0).abs() < 1e-5, "{:?}", v);
+			assert!(tangent.dot(Vec3::from(v.normal)).abs() < 1e-5, "{:?}", v);
+		}
+	}
+
+	#[test]
+	fn test_normal_map_from_texture() {
+		// Flat textures are flat
+		let flat = vec![vec![(90, 90, 90, 255); 3]; 2];
+		assert_eq!(vec![vec![(128, 128, 255, 255); 3]; 2], normal_map_from_texture(&flat, 4.0));
+
+		// Brightening along U tilts normals back along -U, except where the
+		// texture wraps around
+		let ramp: Vec<Vec<_>> = (0..2)
+			.map(|_| (0..4).map(|u| (u * 60, u * 60, u * 60, 255)).collect())
+			.collect();
+		let bumps = normal_map_from_texture(&ramp, 4.0);
+		assert!(bumps[0][1].0 < 128 && bumps[0][1].1 == 128 && bumps[0][1].2 < 255,
+			"{:?}", bumps[0][1]);
+		assert_eq!(bumps[0][1], bumps[1][2]);
+		assert!(bumps[0][0].0 > 128, "{:?}", bumps[0][0]);
+	}
+
+	#[test]
+	fn test_default_mat() {
+		let texture = default_mat().texture;
+		let size = DEFAULT_TEXTURE_CELLS * DEFAULT_TEXTURE_CELL_SIZE;
+		assert_eq!(size, texture.len());
+		assert!(texture.iter().all(|row| row.len() == size));
+		let cell = DEFAULT_TEXTURE_CELL_SIZE;
+		let magenta = (255, 0, 255, 255);
+		let black = (0, 0, 0, 255);
+		// Cells alternate along rows and columns, and are uniform inside
+		assert_eq!(magenta, texture[0][0]);
+		assert_eq!(magenta, texture[cell - 1][cell - 1]);
+		assert_eq!(black, texture[0][cell]);
+		assert_eq!(black, texture[cell][0]);
+		assert_eq!(magenta, texture[cell][cell]);
+		assert_eq!(black, texture[size - 1][0]);
+	}
+
+	#[test]
+	fn test_load_model_from_path() {
+		// A model in its own directory, with paths relative to that directory
+		let dir = TestDir::new("library");
+		let model_dir = dir.join("models");
+		fs::create_dir_all(model_dir.join("textures")).unwrap();
+		File::create(model_dir.join("quad.obj")).unwrap().write_all(b"mtllib quad.mtl\n\
+			o quad\n\
+			v 0 0 0\n\
+			v 0 0 1\n\
+			v 1 0 1\n\
+			v 1 0 0\n\
+			usemtl Quad\n\
+			f 1 2 3 4\n").unwrap();
+		File::create(model_dir.join("quad.mtl")).unwrap().write_all(b"newmtl Quad\n\
+			Ns 1.0\n\
+			Ka 0.0 0.0 0.0\n\
+			Kd 1.0 1.0 1.0\n\
+			Ks 0.5 0.5 0.5\n\
+			d 1.0\n\
+			illum 2\n\
+			map_Kd textures/quad.png\n").unwrap();
+		image::RgbaImage::new(2, 2).save(model_dir.join("textures").join("quad.png")).unwrap();
+
+		let library = ModelLibrary::new();
+		let model = library.load_model_from_path(model_dir.join("quad.obj"));
+		let again = library.load_model_from_path(dir.join("models/../models/quad.obj"));
+		let material = library.load_material(model_dir.join("quad.mtl"), "Quad");
+		let missing = library.load_model_from_path(model_dir.join("missing.obj"));
+
+		// Loading the same files again shares what was already loaded
+		let model = model.unwrap();
+		assert!(Rc::ptr_eq(&model, &again.unwrap()));
+		assert!(Rc::ptr_eq(&model.material, &material.unwrap()));
+		assert_eq!(1, library.models.borrow().len());
+
+		assert_eq!(6, model.geometry.indices.len());
+		assert_eq!(2, model.material.texture.len());
+		let error = missing.unwrap_err().to_string();
+		assert!(error.contains("missing.obj"), "{}", error);
+	}
+
+	#[test]
+	fn test_load_models() {
+		// Two objects sharing a material, which has no texture
+		let dir = TestDir::new("models");
+		File::create(dir.join("pair.mtl")).unwrap().write_all(b"newmtl Gray\n\
+			Ns 1.0\n\
+			Ka 0.0 0.0 0.0\n\
+			Kd 0.5 0.5 0.5\n\
+			Ks 0.5 0.5 0.5\n\
+			d 1.0\n\
+			illum 2\n").unwrap();
+		let pair = b"mtllib pair.mtl\n\
+			o first\n\
+			v 0 0 0\n\
+			v 0 0 1\n\
+			v 1 0 0\n\
+			usemtl Gray\n\
+			f 1 2 3\n\
+			o second\n\
+			v 0 1 0\n\
+			v 0 1 1\n\
+			v 1 1 0\n\
+			usemtl Gray\n\
+			f 4 5 6\n";
+		let library = ModelLibrary::new();
+		let models = library.load_models(&mut &pair[..], Some(dir.path()));
+		let single = library.load_model(&mut &pair[..], Some(dir.path()));
+
+		let models = models.unwrap();
+		assert_eq!(2, models.len());
+		assert!(Rc::ptr_eq(&models[0].material, &models[1].material));
+		assert!(models[1].geometry.vertices.iter().all(|v| v.position[1] == 1.0));
+		// Nothing is added for a file with the wrong number of objects
+		assert!(single.is_err());
+		assert_eq!(2, library.models.borrow().len());
+	}
+}
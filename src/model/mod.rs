@@ -4,9 +4,18 @@
 //! system memory, and upload them to GPU memory.
 
 pub mod disk;
+pub mod gltf;
 pub mod gpu;
 pub mod heightmap;
 pub mod mem;
+pub mod scatter;
+
+#[cfg(test)]
+use std::fs;
+#[cfg(test)]
+use std::ops::Deref;
+#[cfg(test)]
+use std::path::{Path, PathBuf};
 
 /// A vertex and associated data.
 #[derive(Copy, Clone, Debug)]
@@ -15,8 +24,59 @@ pub struct Vertex {
     pub position: [f32; 3],
 	/// The normal corresponding to this vertex.
 	pub normal: [f32; 3],
+	/// The direction the texture U coordinate increases in along the surface,
+	/// perpendicular to the normal. Normal maps are relative to this.
+	pub tangent: [f32; 3],
 	/// The texture UV coordinates at this vertex.
 	pub tex_uv: [f32; 2],
+	/// The color at this vertex, which the texture color is multiplied by.
+	/// Uncolored vertices are white.
+	pub color: [f32; 3],
+}
+implement_vertex!(Vertex, position, normal, tangent, tex_uv, color);
+
+/// The color of vertices which aren't given one.
+pub const WHITE: [f32; 3] = [1.0, 1.0, 1.0];
+
+
+/// An empty scratch directory for a test, which is removed along with
+/// everything in it when dropped, even if the test fails.
+#[cfg(test)]
+pub struct TestDir(PathBuf);
+
+#[cfg(test)]
+impl TestDir {
+	/// Create a directory for the test called `name`, in the system's
+	/// temporary directory. It's named after the process as well, so
+	/// concurrent test runs don't collide.
+	pub fn new(name: &str) -> TestDir {
+		let dir = ::std::env::temp_dir()
+			.join(format!("gl-demo-{}-test-{}", name, ::std::process::id()));
+		// Left over from a run which was killed
+		let _ = fs::remove_dir_all(&dir);
+		fs::create_dir_all(&dir).unwrap();
+		TestDir(dir)
+	}
+
+	/// The directory's path.
+	pub fn path(&self) -> &Path {
+		&self.0
+	}
 }
-implement_vertex!(Vertex, position, normal, tex_uv);
 
+#[cfg(test)]
+impl Deref for TestDir {
+	type Target = Path;
+	fn deref(&self) -> &Path {
+		&self.0
+	}
+}
+
+#[cfg(test)]
+impl Drop for TestDir {
+	fn drop(&mut self) {
+		if let Err(e) = fs::remove_dir_all(&self.0) {
+			warn!("Could not remove test directory {}: {}", self.0.display(), e);
+		}
+	}
+}
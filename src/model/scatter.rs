@@ -0,0 +1,130 @@
+//! Scatter models randomly, but repeatably, across a heightmap.
+
+use linear_algebra::{Mat4, Vec3};
+use model::gpu::{Model, ModelInstance};
+use model::heightmap::Heightmap;
+use physics::{ground_height, ground_slope};
+
+/// Smallest scale a scattered model is given.
+const MIN_SCALE: f32 = 0.5;
+/// Largest scale a scattered model is given.
+const MAX_SCALE: f32 = 1.5;
+/// How many random positions to try per model before giving up, in case most
+/// of the heightmap is too steep.
+const ATTEMPTS_PER_MODEL: usize = 16;
+
+/// A small xorshift random number generator, so the same seed always scatters
+/// models the same way.
+struct Rng(u64);
+impl Rng {
+	fn new(seed: u64) -> Rng {
+		// The state must never be zero
+		Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+	}
+
+	/// Get a random number in [0, 1).
+	fn next(&mut self) -> f32 {
+		self.0 ^= self.0 >> 12;
+		self.0 ^= self.0 << 25;
+		self.0 ^= self.0 >> 27;
+		let bits = self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 40;
+		bits as f32 / (1u64 << 24) as f32
+	}
+
+	/// Get a random number in [low, high).
+	fn range(&mut self, low: f32, high: f32) -> f32 {
+		low + (high - low) * self.next()
+	}
+}
+
+/// Place up to `count` instances of `model` on `heightmap`, at positions,
+/// rotations about Y and scales chosen randomly from `seed`.
+///
+/// Models aren't placed where the ground is steeper than `max_slope` radians,
+/// or where there's no ground at all, so fewer than `count` may be placed if
+/// little of the heightmap is flat enough.
+pub fn scatter_on_terrain<'a>(model: &'a Model,
+		heightmap: &Heightmap<f32>,
+		count: usize,
+		seed: u64,
+		max_slope: f32) -> Vec<ModelInstance<'a>> {
+	scatter_matrices(heightmap, count, seed, max_slope).into_iter()
+		.map(|model_matrix| ModelInstance {
+			model: model,
+			model_matrix: model_matrix,
+		})
+		.collect()
+}
+
+/// Choose model matrices for `scatter_on_terrain`.
+fn scatter_matrices(heightmap: &Heightmap<f32>,
+		count: usize,
+		seed: u64,
+		max_slope: f32) -> Vec<Mat4<f32>> {
+	let mut rng = Rng::new(seed);
+	let bounds = heightmap.bounds();
+	let mut matrices = Vec::with_capacity(count);
+	for _ in 0..count * ATTEMPTS_PER_MODEL {
+		if matrices.len() >= count {
+			break;
+		}
+		let mut pos = Vec3::from([rng.range(bounds.min[0], bounds.max[0]),
+			0.0,
+			rng.range(bounds.min[2], bounds.max[2])]);
+		let angle = rng.range(0.0, 2.0 * ::std::f32::consts::PI);
+		let scale = rng.range(MIN_SCALE, MAX_SCALE);
+		pos[1] = ground_height(heightmap, &pos);
+		// Written so NaN slopes and heights (off the heightmap) are skipped
+		if !(ground_slope(heightmap, &pos) <= max_slope) || !pos[1].is_finite() {
+			continue;
+		}
+		matrices.push(Mat4::trs(pos, Mat4::rotation_y(angle), Vec3::from([scale, scale, scale])));
+	}
+	matrices
+}
+
+#[cfg(test)]
+mod tests {
+	use linear_algebra::{Aabb, Vec3};
+	use model::heightmap::Heightmap;
+	use physics::{ground_height, ground_slope};
+	use std::f32::consts::PI;
+	use super::scatter_matrices;
+
+	/// Flat ground for X < 5, and a slope rising at 2 units per unit X after
+	/// that, over [0, 10] in X and Z.
+	struct HalfSteepHeightmap;
+	impl<'a> Heightmap<'a, f32> for HalfSteepHeightmap {
+		fn get_tri_from_position(&self, pos: &Vec3<f32>) -> [Vec3<f32>; 3] {
+			let height = |x: f32| if pos[0] < 5.0 { 0.0 } else { (x - 5.0) * 2.0 };
+			[Vec3::from([pos[0], height(pos[0]), pos[2]]),
+				Vec3::from([pos[0] + 0.1, height(pos[0] + 0.1), pos[2]]),
+				Vec3::from([pos[0], height(pos[0]), pos[2] + 0.1])]
+		}
+		fn bounds(&self) -> Aabb<f32> {
+			Aabb { min: Vec3::from([0.0, 0.0, 0.0]), max: Vec3::from([10.0, 10.0, 10.0]) }
+		}
+		fn update_lod(&mut self, _: &Vec3<f32>) { }
+	}
+
+	#[test]
+	fn test_scatter_on_terrain() {
+		let max_slope = PI * 0.25;
+		let matrices = scatter_matrices(&HalfSteepHeightmap, 50, 1234, max_slope);
+		assert_eq!(50, matrices.len());
+		for m in matrices.iter() {
+			let pos = Vec3::from([m[3][0], m[3][1], m[3][2]]);
+			assert!(pos[0] >= 0.0 && pos[0] < 5.0, "placed on the slope at {:?}", pos);
+			assert!((pos[1] - ground_height(&HalfSteepHeightmap, &pos)).abs() < 1e-5,
+				"not on the ground at {:?}", pos);
+			assert!(ground_slope(&HalfSteepHeightmap, &pos) <= max_slope);
+		}
+
+		// The same seed scatters the same way
+		assert_eq!(matrices, scatter_matrices(&HalfSteepHeightmap, 50, 1234, max_slope));
+		assert!(matrices != scatter_matrices(&HalfSteepHeightmap, 50, 4321, max_slope));
+
+		// Everything is too steep, so nothing can be placed
+		assert!(scatter_matrices(&HalfSteepHeightmap, 10, 1234, -1.0).is_empty());
+	}
+}
@@ -3,138 +3,840 @@
 //! Right now, this is just character movement and gravity.
 
 use MovementState;
-use linear_algebra::Vec3;
+use linear_algebra::{Aabb, Float, Vec3};
+use model::heightmap::Heightmap;
+use std::f32;
 
+/// Time, in seconds, a character takes to reach maximum speed on the XZ plane
+/// from a standstill.
+pub const ACCEL_TIME: f32 = 5.0 / 60.0;
+
+/// Time, in seconds, a character can continue to accelerate upwards while
+/// jumping.
+pub const JUMP_TIME: f32 = 5.0 / 60.0;
+
+/// Default time, in seconds, after walking off the ground during which a
+/// character can still jump.
+pub const DEFAULT_COYOTE_TIME: f32 = 0.1;
+
+/// Default time, in seconds, before landing during which a jump press is
+/// remembered and fires on landing.
+pub const DEFAULT_JUMP_BUFFER: f32 = 0.1;
+
+/// Distance, in units, above the ground at which a character still counts as
+/// standing on it.
+const GROUND_TOLERANCE: f32 = 1.0e-3;
+
+/// Rounding error, in units of `f32::EPSILON` relative to the ground's height,
+/// a character can be above the ground and still count as standing on it, for
+/// heights too large for `GROUND_TOLERANCE` to cover rounding error.
+const GROUND_TOLERANCE_ULPS: f32 = 4.0;
+
+/// Get the height of the ground under the given position on a heightmap.
+///
+/// Positions which aren't over the heightmap will get a height which compares
+/// false against everything.
+pub fn ground_height<T: Float>(heightmap: &Heightmap<T>, pos: &Vec3<T>) -> T {
+	ground_plane(heightmap, pos).0
+}
+
+/// Get the angle, in radians from horizontal, of the ground under the given
+/// position.
+///
+/// Like `ground_height`, positions which aren't over the heightmap get a slope
+/// which compares false against everything.
+pub fn ground_slope<T: Float>(heightmap: &Heightmap<T>, pos: &Vec3<T>) -> T {
+	ground_plane(heightmap, pos).1[1].acos()
+}
+
+/// Get the height of the ground under the given position, and the unit normal
+/// of the ground there, pointing upwards.
+fn ground_plane<T: Float>(heightmap: &Heightmap<T>, pos: &Vec3<T>) -> (T, Vec3<T>) {
+	// Off the edge of the heightmap, there's no ground at all
+	if !heightmap.contains_position(pos) {
+		let nan = T::from_f32(f32::NAN);
+		return (nan, Vec3::from([nan; 3]));
+	}
+	let hm_vertices = heightmap.get_tri_from_position(pos);
+	let hm_normal = (hm_vertices[0] - hm_vertices[2])
+			.cross(hm_vertices[0] - hm_vertices[1]);
+	let height = heightmap.get_height_at(pos);
+	let normal = if hm_normal[1] < T::from_f32(0.0) { -hm_normal } else { hm_normal };
+	(height, normal.normalize())
+}
+
+/// Whether a character at height `y` is standing on ground at `height`,
+/// allowing for rounding error. Ground off the heightmap is never stood on.
+fn is_grounded<T: Float>(y: T, height: T) -> bool {
+	let tolerance = T::from_f32(GROUND_TOLERANCE)
+			.max(height.abs() * T::from_f32(::std::f32::EPSILON * GROUND_TOLERANCE_ULPS));
+	height.is_finite() && y - height <= tolerance
+}
+
+/// Whether ground with the given unit normal is too steep to walk on.
+fn is_steep<T: Float>(normal: Vec3<T>, max_slope: T) -> bool {
+	// Slopes off the heightmap have NaN normals, and aren't steep.
+	normal[1] < max_slope.cos()
+}
+
+/// Remove any uphill component from an XZ vector (velocity or acceleration) on
+/// a slope with the given unit normal.
+fn remove_uphill<T: Float>(vel: &mut Vec3<T>, normal: Vec3<T>) {
+	// The normal's XZ part points downhill.
+	let downhill_len = normal[0].hypot(normal[2]);
+	if downhill_len <= T::from_f32(0.0) {
+		return;
+	}
+	let downhill = [normal[0] / downhill_len, normal[2] / downhill_len];
+	let along = vel[0] * downhill[0] + vel[2] * downhill[1];
+	if along < T::from_f32(0.0) {
+		vel[0] -= downhill[0] * along;
+		vel[2] -= downhill[1] * along;
+	}
+}
+
+/// What happens to a character at the edge of the heightmap.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EdgePolicy {
+	/// Stop at the edge.
+	Clamp,
+	/// Come back in at the opposite edge, for tiling heightmaps.
+	Wrap,
+	/// Walk off the edge and fall forever.
+	Fall,
+}
 
 /// A character's physical state.
 ///
 /// This includes location and velocity, as well as relevant constants like
 /// maximum XZ movement speed, XZ deceleration due to friction, maximum jump
-/// speed, and acceleration due to gravity.
+/// speed, acceleration due to gravity, the steepest walkable slope, and what
+/// happens at the edge of the heightmap. It also tracks the jump in progress,
+/// and how long ago the character left the ground or pressed jump, for coyote
+/// time and jump buffering.
+///
+/// All of these can be `f32` or `f64`; see `Float`.
 #[derive(Clone, Copy, Debug)]
-pub struct CharacterState {
-	loc: Vec3<f32>,
-	vel: Vec3<f32>,
-	max_speed: f32,
-	decel: f32,
-	max_jump: f32,
-	gravity: f32
+pub struct CharacterState<T: Copy = f32> {
+	loc: Vec3<T>,
+	prev_loc: Vec3<T>,
+	vel: Vec3<T>,
+	max_speed: T,
+	decel: T,
+	max_jump: T,
+	gravity: T,
+	max_slope: T,
+	edge_policy: EdgePolicy,
+	coyote_time: T,
+	jump_buffer: T,
+	/// Time since the character last stood on walkable ground, or infinity
+	/// once it has jumped.
+	airborne_time: T,
+	/// Time remaining before a jump press is forgotten.
+	buffered_jump: T,
+	/// Time the character can continue to accelerate upwards while jumping.
+	jump_time: T,
+	/// Whether jump was held last tick, to tell presses and releases apart.
+	jump_held: bool,
 }
-impl CharacterState {
+impl<T: Float> CharacterState<T> {
 	/// Create a new CharacterState.
 	///
 	///  * `loc`: The location of this character.
 	///  * `vel`: The velocity of this character, typically
 	///		`[0.0, 0.0, 0.0f32]` initially.
-	///  * `max_speed`: The maximum speed, in units/frame, this character can
+	///  * `max_speed`: The maximum speed, in units/s, this character can
 	///		achieve on the XZ plane.
-	///  * `decel`: The rate, in units/frame^2, at which this character
+	///  * `decel`: The rate, in units/s^2, at which this character
 	///		decelerates due to friction in the absence of movement input.
-	///  * `max_jump`: The maximum speed, in units/frame, this character can
+	///  * `max_jump`: The maximum speed, in units/s, this character can
 	///		achieve on the Y axis while jumping.
-	///  * `gravity`: The acceleration, in units/frame^2, this character
+	///  * `gravity`: The acceleration, in units/s^2, this character
 	///		experiences downward on the Y axis due to gravity. Note that this
 	///		value should be positive.
-	pub fn new(loc: Vec3<f32>,
-			vel: Vec3<f32>,
-			max_speed: f32,
-			decel: f32,
-			max_jump: f32,
-			gravity: f32) -> CharacterState {
+	///  * `max_slope`: The steepest slope, in radians from horizontal, this
+	///		character can walk up. Steeper ground can't be climbed or jumped
+	///		from, and the character slides down it.
+	///  * `edge_policy`: What happens when this character reaches the edge of
+	///		the heightmap.
+	///
+	/// Coyote time and jump buffering start at `DEFAULT_COYOTE_TIME` and
+	/// `DEFAULT_JUMP_BUFFER`.
+	pub fn new(loc: Vec3<T>,
+			vel: Vec3<T>,
+			max_speed: T,
+			decel: T,
+			max_jump: T,
+			gravity: T,
+			max_slope: T,
+			edge_policy: EdgePolicy) -> CharacterState<T> {
 	CharacterState {
 		loc: loc,
+		prev_loc: loc,
 		vel: vel,
 		max_speed: max_speed,
 		decel: decel,
 		max_jump: max_jump,
-		gravity: gravity}
+		gravity: gravity,
+		max_slope: max_slope,
+		edge_policy: edge_policy,
+		coyote_time: T::from_f32(DEFAULT_COYOTE_TIME),
+		jump_buffer: T::from_f32(DEFAULT_JUMP_BUFFER),
+		airborne_time: T::from_f32(f32::INFINITY),
+		buffered_jump: T::from_f32(0.0),
+		jump_time: T::from_f32(0.0),
+		jump_held: false}
+	}
+
+	/// Set the time, in seconds, after walking off the ground during which
+	/// this character can still jump.
+	pub fn coyote_time(mut self, coyote_time: T) -> CharacterState<T> {
+		self.coyote_time = coyote_time;
+		self
+	}
+
+	/// Set the time, in seconds, before landing during which a jump press is
+	/// remembered and fires on landing.
+	pub fn jump_buffer(mut self, jump_buffer: T) -> CharacterState<T> {
+		self.jump_buffer = jump_buffer;
+		self
 	}
 
 	/// Update the character's location and velocity based on inputs, gravity and
-	/// friction.
+	/// friction, advancing the simulation by `dt` seconds.
 	///
 	/// This does all of the following:
 	///
 	///  * Accelerates the character on the XZ plane according to movement inputs.
-	///		Acceleration takes five frames to reach maximum speed.
+	///		Acceleration takes `ACCEL_TIME` to reach maximum speed.
 	///  * Decelerates the character on the XZ plane according to friction
 	///		(`CharacterState.decel`).
-	///  * Handle jump acceleration and timeout. Jumping takes five frames to
-	///		reach maximum speed.
+	///  * Handle jump acceleration and timeout. Jumping takes `JUMP_TIME` to
+	///		reach maximum speed, and releasing jump ends it early. Jumps can
+	///		start up to the coyote time after leaving the ground, and presses
+	///		up to the jump buffer time before landing fire on landing.
 	///  * Apply static gravitational acceleration.
-	///  * Clamp Y location above zero for floor clipping.
-	pub fn do_char_movement(&mut self, dir: &Vec3<f32>, movement: &mut MovementState,
-			/*XXX*/ heightmap: &::model::heightmap::Heightmap<f32> ) {
-
-		// Figure out ground height at our location
-		let hm_vertices = heightmap.get_tri_from_position(&self.loc);
-		let hm_normal = (hm_vertices[0] - hm_vertices[2])
-				.cross(hm_vertices[0] - hm_vertices[1]);
-		let hm_d = hm_normal.dot(hm_vertices[0]);
-		let height = (hm_d -
-				hm_normal[0] * self.loc[0] -
-				hm_normal[2] * self.loc[2]) /
-				hm_normal[1];
+	///  * Clamp Y location above the ground for floor clipping.
+	///  * On ground steeper than `CharacterState.max_slope`, prevent uphill
+	///		movement and jumping, and slide down the slope without friction.
+	///  * Apply `CharacterState.edge_policy` at the edge of the heightmap.
+	///
+	/// All of the character's constants are per-second, so behavior is the
+	/// same regardless of how often this is called, as long as `dt` is the
+	/// length of the physics tick.
+	pub fn do_char_movement(&mut self, dir: &Vec3<T>, movement: &MovementState,
+			/*XXX*/ heightmap: &Heightmap<T>, dt: T) {
+		let (zero, one) = (T::from_f32(0.0), T::from_f32(1.0));
+
+		self.prev_loc = self.loc;
+
+		// Figure out ground height and slope at our location
+		let (height, normal) = ground_plane(heightmap, &self.loc);
+		let on_ground = is_grounded(self.loc[1], height);
+		let steep = on_ground && is_steep(normal, self.max_slope);
+		if on_ground && !steep {
+			self.airborne_time = zero;
+		}
 
 		// Apply accelerations
 
-		// Acceleration such that we reach max_speed in five frames
-		let accel = self.decel + (self.max_speed / 5.0);
-		let jump_accel = self.gravity + (self.max_jump / 5.0);
+		// Acceleration such that we reach max_speed in ACCEL_TIME
+		let accel = (self.decel + (self.max_speed / T::from_f32(ACCEL_TIME))) * dt;
+		let jump_accel = (self.gravity + (self.max_jump / T::from_f32(JUMP_TIME))) * dt;
+
+		// Movement is on the XZ plane regardless of where we're looking, and
+		// at the same speed however far up or down that is
+		let yaw = dir[2].atan2(dir[0]);
+		let forward = Vec3::from([yaw.cos(), zero, yaw.sin()]);
+		let left = Vec3::from([-yaw.sin(), zero, yaw.cos()]);
+		let up = Vec3::from([zero, one, zero]);
 
+		let mut walk = Vec3::from([zero, zero, zero]);
 		if movement.forward {
-			self.vel[0] += dir[0] * accel;
-			self.vel[2] += dir[2] * accel;
+			walk += forward * accel;
 		}
 		if movement.backward {
-			self.vel[0] -= dir[0] * accel;
-			self.vel[2] -= dir[2] * accel;
+			walk -= forward * accel;
 		}
 		if movement.left {
-			self.vel[0] -= dir[2] * accel;
-			self.vel[2] += dir[0] * accel;
+			walk += left * accel;
 		}
 		if movement.right {
-			self.vel[0] += dir[2] * accel;
-			self.vel[2] -= dir[0] * accel;
-		}
-		if movement.jumping {
-			if self.loc[1] <= height {
-				movement.can_jump = 5;
-				self.vel[1] += jump_accel;
-			} else if movement.can_jump > 0 {
-				movement.can_jump -= 1;
-				self.vel[1] += jump_accel;
+			walk -= left * accel;
+		}
+		if steep {
+			remove_uphill(&mut walk, normal);
+		}
+		self.vel += walk;
+
+		// Jumping: a press is remembered for the jump buffer time, and
+		// releasing jump stops the ascent
+		if movement.jumping && !self.jump_held {
+			self.buffered_jump = self.jump_buffer;
+		} else if !movement.jumping && self.jump_held {
+			self.jump_time = zero;
+		}
+		self.jump_held = movement.jumping;
+		let wants_jump = movement.jumping || self.buffered_jump > zero;
+		if wants_jump && !steep && self.airborne_time <= self.coyote_time {
+			self.jump_time = T::from_f32(JUMP_TIME);
+			self.buffered_jump = zero;
+			// Jumping in coyote time cancels the fall so far
+			self.vel[1] = self.vel[1].max(zero);
+			// No more jumps until we land
+			self.airborne_time = T::from_f32(f32::INFINITY);
+			self.vel += up * jump_accel;
+		} else {
+			// Compare against half a tick, so rounding error in the countdown
+			// can't add or drop a tick of acceleration
+			if self.jump_time > dt * T::from_f32(0.5) {
+				self.jump_time -= dt;
+				self.vel += up * jump_accel;
 			}
+			self.buffered_jump -= dt;
+			self.airborne_time += dt;
 		}
 
 		// Apply decelerations
 
-		let char_speed = f32::hypot(self.vel[0], self.vel[2]);
-		let multiplier = if char_speed - self.decel > self.max_speed {
+		// There's no friction while sliding down a steep slope
+		let decel = if steep { zero } else { self.decel * dt };
+		let char_speed = self.vel[0].hypot(self.vel[2]);
+		let multiplier = if char_speed - decel > self.max_speed {
 			self.max_speed / char_speed } else {
-			f32::max(0.0, (char_speed - self.decel) / char_speed)};
+			zero.max((char_speed - decel) / char_speed)};
 		self.vel[0] *= multiplier;
 		self.vel[2] *= multiplier;
 
 		// Gravity:
-		self.vel[1] -= self.gravity;
+		self.vel -= up * (self.gravity * dt);
 
-		// Update locations
-		self.loc[0] += self.vel[0];
-		self.loc[1] += self.vel[1];
-		self.loc[2] += self.vel[2];
+		if steep {
+			remove_uphill(&mut self.vel, normal);
+		}
 
+		// Update locations
+		let old_loc = self.loc;
+		self.loc += self.vel * dt;
+		self.apply_edge_policy(heightmap.bounds());
 
 		// Collision with ground
+		let (mut height, mut normal) = ground_plane(heightmap, &self.loc);
 		if self.loc[1] <= height {
+			if is_steep(normal, self.max_slope) {
+				if height > old_loc[1] {
+					// We ran into a steep slope from below; slide along it
+					// instead of climbing it.
+					remove_uphill(&mut self.vel, normal);
+					self.loc[0] = old_loc[0] + self.vel[0] * dt;
+					self.loc[2] = old_loc[2] + self.vel[2] * dt;
+					let plane = ground_plane(heightmap, &self.loc);
+					height = plane.0;
+					normal = plane.1;
+				}
+				// Keep only the velocity along the slope
+				let into = self.vel.dot(normal);
+				if into < zero {
+					self.vel -= normal * into;
+				}
+			} else {
+				self.vel[1] = zero;
+			}
 			self.loc[1] = height;
-			self.vel[1] = 0.0;
 		}
 	}
 
+	/// Keep the character on a heightmap with the given X/Z bounds, according
+	/// to its edge policy. X and Z are handled independently, so a character
+	/// at a corner stops (or wraps) on both axes.
+	fn apply_edge_policy(&mut self, bounds: Aabb<T>) {
+		let zero = T::from_f32(0.0);
+		for &i in [0, 2].iter() {
+			let (min, max) = (bounds.min[i], bounds.max[i]);
+			match self.edge_policy {
+				EdgePolicy::Clamp => {
+					// Stop dead against the edge
+					if self.loc[i] < min {
+						self.loc[i] = min;
+						self.vel[i] = self.vel[i].max(zero);
+					} else if self.loc[i] > max {
+						self.loc[i] = max;
+						self.vel[i] = self.vel[i].min(zero);
+					}
+				},
+				EdgePolicy::Wrap => {
+					let size = max - min;
+					if !(size > zero && size.is_finite()) {
+						continue;
+					}
+					let wrapped = ((self.loc[i] - min) % size + size) % size + min;
+					// Move the previous location along with us, so we don't
+					// interpolate across the whole heightmap.
+					self.prev_loc[i] += wrapped - self.loc[i];
+					self.loc[i] = wrapped;
+				},
+				EdgePolicy::Fall => (),
+			}
+		}
+	}
+
+	/// Move this character to `loc`, and stop it.
+	pub fn teleport(&mut self, loc: Vec3<T>) {
+		let zero = T::from_f32(0.0);
+		self.loc = loc;
+		self.prev_loc = loc;
+		self.vel = Vec3::from([zero, zero, zero]);
+		self.airborne_time = T::from_f32(f32::INFINITY);
+		self.buffered_jump = zero;
+		self.jump_time = zero;
+	}
+
 	/// Get the location of this character.
-	pub fn loc(&self) -> &Vec3<f32> {
+	pub fn loc(&self) -> &Vec3<T> {
 		&self.loc
 	}
+
+	/// Get the location of this character `alpha` of the way from its location
+	/// before the last `do_char_movement` to its current location.
+	///
+	/// Use this to render smoothly when physics ticks don't line up with
+	/// frames; see `FixedTimestep::alpha`.
+	pub fn interpolated_loc(&self, alpha: T) -> Vec3<T> {
+		self.prev_loc.lerp(self.loc, alpha)
+	}
+
+	/// Get the velocity of this character.
+	pub fn vel(&self) -> &Vec3<T> {
+		&self.vel
+	}
+}
+
+/// A clock for running physics at a fixed tick rate, independent of the
+/// render frame rate.
+#[derive(Clone, Copy, Debug)]
+pub struct FixedTimestep {
+	tick: f32,
+	accumulated: f32,
+}
+impl FixedTimestep {
+	/// Create a new FixedTimestep with ticks `tick` seconds long.
+	pub fn new(tick: f32) -> FixedTimestep {
+		FixedTimestep {
+			tick: tick,
+			accumulated: 0.0,
+		}
+	}
+
+	/// Get the length, in seconds, of a tick.
+	pub fn tick(&self) -> f32 {
+		self.tick
+	}
+
+	/// Add `elapsed` seconds of wall time, and return the number of ticks
+	/// which should now be run. Any remainder carries over to the next call.
+	pub fn advance(&mut self, elapsed: f32) -> usize {
+		self.accumulated += elapsed;
+		let mut ticks = 0;
+		while self.accumulated >= self.tick {
+			self.accumulated -= self.tick;
+			ticks += 1;
+		}
+		ticks
+	}
+
+	/// Get the fraction of a tick which has elapsed since the last tick, for
+	/// interpolating between physics states.
+	pub fn alpha(&self) -> f32 {
+		self.accumulated / self.tick
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use MovementState;
+	use linear_algebra::{Aabb, Float, Vec3};
+	use model::heightmap::Heightmap;
+	use super::{ACCEL_TIME, CharacterState, EdgePolicy, FixedTimestep};
+	use std::f32::consts::PI;
+
+	/// An infinite flat plane at Y = 0.
+	struct FlatHeightmap;
+	impl<'a, T: Float> Heightmap<'a, T> for FlatHeightmap {
+		fn get_tri_from_position(&self, pos: &Vec3<T>) -> [Vec3<T>; 3] {
+			let (zero, one) = (T::from_f32(0.0), T::from_f32(1.0));
+			[Vec3::from([pos[0], zero, pos[2]]),
+				Vec3::from([pos[0] + one, zero, pos[2]]),
+				Vec3::from([pos[0], zero, pos[2] + one])]
+		}
+		fn bounds(&self) -> Aabb<T> {
+			Aabb {
+				min: Vec3::from([T::from_f32(::std::f32::NEG_INFINITY); 3]),
+				max: Vec3::from([T::from_f32(::std::f32::INFINITY); 3]),
+			}
+		}
+		fn update_lod(&mut self, _: &Vec3<T>) { }
+	}
+
+	/// An infinite plane rising steeply (about 63 degrees) towards +X.
+	struct SteepHeightmap;
+	impl<'a> Heightmap<'a, f32> for SteepHeightmap {
+		fn get_tri_from_position(&self, pos: &Vec3<f32>) -> [Vec3<f32>; 3] {
+			let height = |x: f32| x * 2.0;
+			[Vec3::from([pos[0], height(pos[0]), pos[2]]),
+				Vec3::from([pos[0] + 1.0, height(pos[0] + 1.0), pos[2]]),
+				Vec3::from([pos[0], height(pos[0]), pos[2] + 1.0])]
+		}
+		fn bounds(&self) -> Aabb<f32> {
+			Aabb {
+				min: Vec3::from([::std::f32::NEG_INFINITY; 3]),
+				max: Vec3::from([::std::f32::INFINITY; 3]),
+			}
+		}
+		fn update_lod(&mut self, _: &Vec3<f32>) { }
+	}
+
+	/// A flat square from 0 to 10 on X and Z, with nothing around it.
+	struct SmallHeightmap;
+	impl<'a> Heightmap<'a, f32> for SmallHeightmap {
+		fn get_tri_from_position(&self, pos: &Vec3<f32>) -> [Vec3<f32>; 3] {
+			let height = if pos[0] < 0.0 || pos[0] > 10.0 || pos[2] < 0.0 || pos[2] > 10.0 {
+				::std::f32::NEG_INFINITY } else { 0.0 };
+			[Vec3::from([pos[0], height, pos[2]]),
+				Vec3::from([pos[0] + 1.0, height, pos[2]]),
+				Vec3::from([pos[0], height, pos[2] + 1.0])]
+		}
+		fn bounds(&self) -> Aabb<f32> {
+			Aabb { min: Vec3::from([0.0, 0.0, 0.0]), max: Vec3::from([10.0, 0.0, 10.0]) }
+		}
+		fn update_lod(&mut self, _: &Vec3<f32>) { }
+	}
+
+	/// Walk diagonally towards the far corner of `SmallHeightmap` from near
+	/// its center for `ticks` ticks.
+	fn walk_to_corner(edge_policy: EdgePolicy, ticks: usize) -> CharacterState {
+		let mut character = CharacterState::new(
+			Vec3::from([6.0, 0.0, 7.0]),
+			Vec3::from([0.0, 0.0, 0.0]),
+			12.0,
+			180.0,
+			12.0,
+			72.0,
+			PI * 0.25,
+			edge_policy);
+		let movement = movement(true, false);
+		let dir = Vec3::from([0.8, 0.0, 0.6]);
+		for _ in 0..ticks {
+			character.do_char_movement(&dir, &movement, &SmallHeightmap, 1.0 / 60.0);
+		}
+		character
+	}
+
+	fn movement(forward: bool, jumping: bool) -> MovementState {
+		MovementState {
+			forward: forward,
+			backward: false,
+			left: false,
+			right: false,
+			jumping: jumping,
+		}
+	}
+
+	fn steep_character(max_slope: f32) -> CharacterState {
+		CharacterState::new(
+			Vec3::from([0.0, 0.0, 0.0]),
+			Vec3::from([0.0, 0.0, 0.0]),
+			12.0,
+			180.0,
+			12.0,
+			72.0,
+			max_slope,
+			EdgePolicy::Fall)
+	}
+
+	/// Walk from a standstill for `seconds` (a whole number of ticks at
+	/// `tick_rate`), and get the speed reached.
+	fn speed_after(seconds: f32, tick_rate: u32, dir: Vec3<f32>) -> f32 {
+		let mut character = CharacterState::new(
+			Vec3::from([0.0, 0.0, 0.0]),
+			Vec3::from([0.0, 0.0, 0.0]),
+			12.0,
+			180.0,
+			12.0,
+			72.0,
+			PI * 0.25,
+			EdgePolicy::Fall);
+		let movement = movement(true, false);
+		let dt = 1.0 / tick_rate as f32;
+		for _ in 0..(seconds * tick_rate as f32).round() as u32 {
+			character.do_char_movement(&dir, &movement, &FlatHeightmap, dt);
+		}
+		f32::hypot(character.vel()[0], character.vel()[2])
+	}
+
+	#[test]
+	fn test_tick_rate_independence() {
+		// Partway through accelerating, since both rates reach the same
+		// max_speed in the end. This is one tick at 30 Hz and four at 120.
+		let dir = Vec3::from([1.0, 0.0, 0.0]);
+		let seconds = 1.0 / 30.0;
+		assert!(seconds < ACCEL_TIME);
+		let slow = speed_after(seconds, 30, dir);
+		let fast = speed_after(seconds, 120, dir);
+		assert!(slow < 11.0, "30 Hz reached {} in {} s", slow, seconds);
+		assert!((slow - fast).abs() < 0.01,
+			"30 Hz: {}, 120 Hz: {}", slow, fast);
+		// Speed builds linearly to max_speed over ACCEL_TIME
+		assert!((slow - 12.0 * seconds / ACCEL_TIME).abs() < 0.01, "{}", slow);
+	}
+
+	#[test]
+	fn test_speed_independent_of_pitch() {
+		let level = speed_after(1.0, 60, Vec3::from([1.0, 0.0, 0.0]));
+		let down = speed_after(1.0, 60, Vec3::from([0.1, -0.99, 0.1]));
+		let up = speed_after(1.0, 60, Vec3::from([-2.0, 20.0, 0.0]));
+		assert!((level - down).abs() < 1e-4, "level: {}, looking down: {}", level, down);
+		assert!((level - up).abs() < 1e-4, "level: {}, looking up: {}", level, up);
+	}
+
+	/// Walk and jump around `FlatHeightmap` for two seconds, recording the
+	/// character's location every tick.
+	fn trajectory<T: Float>() -> Vec<Vec3<T>> {
+		let f = T::from_f32;
+		let mut character = CharacterState::new(
+			Vec3::from([f(3.0), f(0.0), f(-2.0)]),
+			Vec3::from([f(0.0), f(0.0), f(0.0)]),
+			f(12.0),
+			f(180.0),
+			f(12.0),
+			f(72.0),
+			f(PI * 0.25),
+			EdgePolicy::Fall);
+		let mut movement = movement(true, true);
+		let mut locs = Vec::new();
+		for tick in 0..120 {
+			let yaw = f(tick as f32 * 0.05);
+			let dir = Vec3::from([yaw.cos(), f(0.2), yaw.sin()]);
+			movement.left = tick >= 60;
+			character.do_char_movement(&dir, &movement, &FlatHeightmap, f(1.0 / 60.0));
+			locs.push(*character.loc());
+		}
+		locs
+	}
+
+	#[test]
+	fn test_f32_f64_trajectories_match() {
+		let single = trajectory::<f32>();
+		let double = trajectory::<f64>();
+		// Make sure we actually went somewhere, and jumped
+		assert!((single[119] - single[0]).length() > 2.0, "ended at {:?}", single[119]);
+		assert!(single.iter().any(|loc| loc[1] > 0.5));
+		for (a, b) in single.iter().zip(double.iter()) {
+			for i in 0..3 {
+				assert!((a[i] as f64 - b[i]).abs() < 1e-3, "f32: {:?}, f64: {:?}", a, b);
+			}
+		}
+	}
+
+	#[test]
+	fn test_steep_slope_cannot_be_climbed() {
+		let mut character = steep_character(PI * 0.25);
+		let movement = movement(true, true);
+		let uphill = Vec3::from([1.0, 0.0, 0.0]);
+		let start = character.loc()[1];
+		for _ in 0..600 {
+			character.do_char_movement(&uphill, &movement, &SteepHeightmap, 1.0 / 60.0);
+			assert!(character.loc()[1] <= start + 0.01,
+				"climbed to {:?}", character.loc());
+		}
+		// We should have slid down, too.
+		assert!(character.loc()[1] < start - 1.0, "ended at {:?}", character.loc());
+	}
+
+	#[test]
+	fn test_walkable_slope_can_be_climbed() {
+		let mut character = steep_character(PI * 0.4);
+		let movement = movement(true, false);
+		let uphill = Vec3::from([1.0, 0.0, 0.0]);
+		for _ in 0..60 {
+			character.do_char_movement(&uphill, &movement, &SteepHeightmap, 1.0 / 60.0);
+		}
+		assert!(character.loc()[1] > 1.0, "ended at {:?}", character.loc());
+	}
+
+	/// Run a character for the given frame times, and return its final state.
+	fn run_frames(frames: &[f32]) -> (CharacterState, usize) {
+		let mut character = steep_character(PI * 0.25);
+		let movement = movement(true, true);
+		let dir = Vec3::from([0.6, 0.0, 0.8]);
+		let mut timestep = FixedTimestep::new(1.0 / 64.0);
+		let mut total_ticks = 0;
+		for &frame in frames {
+			let ticks = timestep.advance(frame);
+			for _ in 0..ticks {
+				character.do_char_movement(&dir, &movement, &FlatHeightmap, timestep.tick());
+			}
+			total_ticks += ticks;
+		}
+		(character, total_ticks)
+	}
+
+	#[test]
+	fn test_tick_batching() {
+		let (steady, steady_ticks) = run_frames(&[1.0 / 64.0; 64]);
+		let (fast, fast_ticks) = run_frames(&[1.0 / 128.0; 128]);
+		let (uneven, uneven_ticks) = run_frames(&[0.25, 0.0, 1.0 / 32.0, 0.5, 7.0 / 32.0]);
+		let (single, single_ticks) = run_frames(&[1.0]);
+		assert_eq!(64, steady_ticks);
+		assert_eq!(64, fast_ticks);
+		assert_eq!(64, uneven_ticks);
+		assert_eq!(64, single_ticks);
+		for other in [fast, uneven, single].iter() {
+			assert_eq!(steady.loc(), other.loc());
+			assert_eq!(steady.vel(), other.vel());
+		}
+	}
+
+	#[test]
+	fn test_interpolation() {
+		let mut timestep = FixedTimestep::new(0.25);
+		assert_eq!(1, timestep.advance(0.375));
+		assert_eq!(0.5, timestep.alpha());
+
+		let mut character = steep_character(PI * 0.25);
+		let movement = movement(true, false);
+		let dir = Vec3::from([1.0, 0.0, 0.0]);
+		for _ in 0..10 {
+			character.do_char_movement(&dir, &movement, &FlatHeightmap, 1.0 / 60.0);
+		}
+		let before = character.interpolated_loc(0.0);
+		let after = character.interpolated_loc(1.0);
+		let halfway = character.interpolated_loc(0.5);
+		assert!(before[0] < halfway[0] && halfway[0] < after[0]);
+		assert_eq!(after[0], character.loc()[0]);
+	}
+
+	/// Run off the +X edge of `SmallHeightmap`, then press jump `delay` ticks
+	/// after leaving it, and return whether the character started going up.
+	fn jump_off_ledge(delay: usize) -> bool {
+		let mut character = CharacterState::new(
+			Vec3::from([8.0, 0.0, 5.0]),
+			Vec3::from([0.0, 0.0, 0.0]),
+			12.0,
+			180.0,
+			12.0,
+			72.0,
+			PI * 0.25,
+			EdgePolicy::Fall);
+		let mut movement = movement(true, false);
+		let dir = Vec3::from([1.0, 0.0, 0.0]);
+		while character.loc()[0] <= 10.0 {
+			character.do_char_movement(&dir, &movement, &SmallHeightmap, 1.0 / 60.0);
+		}
+		for _ in 0..delay {
+			character.do_char_movement(&dir, &movement, &SmallHeightmap, 1.0 / 60.0);
+		}
+		movement.jumping = true;
+		character.do_char_movement(&dir, &movement, &SmallHeightmap, 1.0 / 60.0);
+		character.vel()[1] > 0.0
+	}
+
+	#[test]
+	fn test_coyote_time() {
+		// The default coyote time is six ticks
+		assert!(jump_off_ledge(0));
+		assert!(jump_off_ledge(3));
+		assert!(!jump_off_ledge(12));
+		assert!(!jump_off_ledge(30));
+	}
+
+	/// Drop `steep_character` onto `FlatHeightmap` from 5 units up, tapping
+	/// jump for one tick on tick `press`, and return whether it jumped after
+	/// landing.
+	fn tap_jump_while_falling(press: Option<usize>) -> bool {
+		let mut character = steep_character(PI * 0.25);
+		character.teleport(Vec3::from([0.0, 5.0, 0.0]));
+		let mut movement = movement(false, false);
+		let dir = Vec3::from([1.0, 0.0, 0.0]);
+		let mut landed = false;
+		for tick in 0..60 {
+			movement.jumping = press == Some(tick);
+			character.do_char_movement(&dir, &movement, &FlatHeightmap, 1.0 / 60.0);
+			if landed && character.loc()[1] > 0.1 {
+				return true;
+			}
+			landed = landed || character.loc()[1] <= 0.0;
+		}
+		false
+	}
+
+	/// Find the tick on which `tap_jump_while_falling` lands.
+	fn landing_tick() -> usize {
+		let mut character = steep_character(PI * 0.25);
+		character.teleport(Vec3::from([0.0, 5.0, 0.0]));
+		let movement = movement(false, false);
+		let dir = Vec3::from([1.0, 0.0, 0.0]);
+		(0..).find(|_| {
+			character.do_char_movement(&dir, &movement, &FlatHeightmap, 1.0 / 60.0);
+			character.loc()[1] <= 0.0
+		}).unwrap()
+	}
+
+	#[test]
+	fn test_jump_buffer() {
+		let landing = landing_tick();
+		assert!(landing > 15, "landed on tick {}", landing);
+		assert!(!tap_jump_while_falling(None));
+		assert!(tap_jump_while_falling(Some(landing - 2)));
+		assert!(!tap_jump_while_falling(Some(landing - 15)));
+	}
+
+	#[test]
+	fn test_grounded_tolerance() {
+		assert!(super::is_grounded(0.0005f32, 0.0));
+		assert!(!super::is_grounded(0.01f32, 0.0));
+		// Rounding error is bigger far from the origin, where a few ULPs is
+		// more than GROUND_TOLERANCE, but half a unit is still in the air
+		assert!(super::is_grounded(100_000.03f32, 100_000.0));
+		assert!(!super::is_grounded(100_000.1f32, 100_000.0));
+		assert!(!super::is_grounded(100_000.5f32, 100_000.0));
+		assert!(!super::is_grounded(::std::f32::NAN, 0.0));
+		assert!(!super::is_grounded(0.0, ::std::f32::NAN));
+		assert!(!super::is_grounded(0.0, ::std::f32::NEG_INFINITY));
+	}
+
+	#[test]
+	fn test_edge_clamp() {
+		let character = walk_to_corner(EdgePolicy::Clamp, 120);
+		// Both axes stop at the corner, even though we hit the X edge first
+		assert_eq!(Vec3::from([10.0, 0.0, 10.0]), *character.loc());
+		assert_eq!(0.0, character.vel()[0]);
+		assert_eq!(0.0, character.vel()[2]);
+	}
+
+	#[test]
+	fn test_edge_wrap() {
+		let mut last = walk_to_corner(EdgePolicy::Wrap, 0);
+		let mut wraps = 0;
+		for ticks in 1..180 {
+			let character = walk_to_corner(EdgePolicy::Wrap, ticks);
+			let loc = character.loc();
+			assert!(loc[0] >= 0.0 && loc[0] <= 10.0 && loc[2] >= 0.0 && loc[2] <= 10.0,
+				"{:?} is off the heightmap", loc);
+			assert_eq!(0.0, loc[1]);
+			if loc[0] < last.loc()[0] {
+				wraps += 1;
+				// The interpolated location doesn't jump back across
+				assert!(character.interpolated_loc(0.0)[0] <= loc[0]);
+			}
+			last = character;
+		}
+		assert!(wraps >= 2, "only wrapped {} times", wraps);
+	}
+
+	#[test]
+	fn test_edge_fall() {
+		let character = walk_to_corner(EdgePolicy::Fall, 120);
+		assert!(character.loc()[0] > 10.0);
+		assert!(character.loc()[1] < -10.0);
+	}
 }
@@ -1,10 +1,15 @@
 //! Trait to allow objects to render themselves
 
-use glium::{BlitTarget, DrawParameters, Frame, Program, Rect, Surface};
+use errors::*;
+use glium::{Blend, BlitTarget, Depth, DrawParameters, Frame, PolygonMode, Program, Rect, Surface};
+use glium::VertexBuffer;
+use glium::backend::Facade;
+use glium::draw_parameters::{BackfaceCullingMode, DepthTest};
+use glium::index::{NoIndices, PrimitiveType};
 use glium::texture::Texture2d;
-use glium::uniforms::{MagnifySamplerFilter, SamplerWrapFunction};
-use linear_algebra::{Mat3, Mat4, Vec3};
-use model::gpu::ModelInstance;
+use glium::uniforms::{MagnifySamplerFilter, SamplerWrapFunction, UniformValue, Uniforms};
+use linear_algebra::{Mat3, Mat4, Vec3, Vec4};
+use model::gpu::{ModelInstance, ModelInstanceBatch};
 
 /// Trait for an object which may be rendered.
 ///
@@ -15,20 +20,190 @@ pub trait Renderable<Param, Target> {
 	fn render(&self, render_state: Param, target: Target);
 }
 
+/// Maximum number of directional lights which affect an object. Lights past
+/// this many in a `DefaultRenderState` are ignored.
+///
+/// This must match `MAX_DIRECTIONAL_LIGHTS` in the fragment shader.
+pub const MAX_DIRECTIONAL_LIGHTS: usize = 4;
+
+/// Maximum number of point lights which affect an object. Lights past this
+/// many in a `DefaultRenderState` are ignored.
+///
+/// This must match `MAX_POINT_LIGHTS` in the fragment shader.
+pub const MAX_POINT_LIGHTS: usize = 8;
+
+/// A light infinitely far away, which shines in the same direction
+/// everywhere, like the sun.
+#[derive(Clone, Copy, Debug)]
+pub struct DirectionalLight {
+	/// Direction towards the light
+	pub direction: Vec3<f32>,
+	/// Color of the light
+	pub color: (f32, f32, f32),
+}
+
+/// A light which shines in all directions from a point, and gets dimmer with
+/// distance.
+///
+/// At distance `d`, the light's color is scaled by
+/// `1 / (constant + linear * d + quadratic * d^2)`.
+#[derive(Clone, Copy, Debug)]
+pub struct PointLight {
+	/// Position of the light
+	pub position: Vec3<f32>,
+	/// Color of the light
+	pub color: (f32, f32, f32),
+	/// Constant attenuation coefficient
+	pub constant: f32,
+	/// Linear attenuation coefficient
+	pub linear: f32,
+	/// Quadratic attenuation coefficient
+	pub quadratic: f32,
+}
+
+impl PointLight {
+
+	/// Create a point light which falls off as `1 / d^2`, reaching half its
+	/// brightness at `radius`.
+	pub fn with_radius(position: Vec3<f32>, color: (f32, f32, f32), radius: f32) -> PointLight {
+		PointLight {
+			position: position,
+			color: color,
+			constant: 1.0,
+			linear: 0.0,
+			quadratic: 1.0 / (radius * radius),
+		}
+	}
+}
+
+/// Color of the sun high in the sky.
+const NOON_SUN_COLOR: (f32, f32, f32) = (1.0, 1.0, 1.0);
+/// Color of the sun near the horizon.
+const HORIZON_SUN_COLOR: (f32, f32, f32) = (1.0, 0.55, 0.3);
+/// Color of the moon, which lights the scene when the sun is down.
+const MOON_COLOR: (f32, f32, f32) = (0.1, 0.12, 0.2);
+/// Clear color during the day.
+const DAY_SKY_COLOR: (f32, f32, f32) = (0.5, 0.5, 1.0);
+/// Clear color at sunrise and sunset.
+const HORIZON_SKY_COLOR: (f32, f32, f32) = (0.8, 0.5, 0.4);
+/// Clear color at night.
+const NIGHT_SKY_COLOR: (f32, f32, f32) = (0.02, 0.02, 0.08);
+
+/// Get the sun (or moon) light and the sky's clear color at a time of day.
+///
+/// `time_of_day` runs from 0 at midnight through 0.25 at sunrise, 0.5 at noon
+/// and 0.75 at sunset, and wraps at 1.
+pub fn sun_and_sky(time_of_day: f32) -> (DirectionalLight, (f32, f32, f32, f32)) {
+	let angle = (time_of_day - 0.25) * 2.0 * std::f32::consts::PI;
+	// The sun rises in +X and sets in -X, slightly off to the side
+	let sun_direction = Vec3::from([angle.cos(), angle.sin(), 0.3]).normalize();
+	let elevation = angle.sin();
+
+	let lerp = |a: (f32, f32, f32), b: (f32, f32, f32), t: f32| (
+		a.0 + (b.0 - a.0) * t,
+		a.1 + (b.1 - a.1) * t,
+		a.2 + (b.2 - a.2) * t);
+	let clamp = |t: f32| t.max(0.0).min(1.0);
+	// How far into the day it is, fading in around sunrise
+	let daylight = clamp((elevation + 0.1) / 0.4);
+	// How close the sun is to the horizon
+	let warmth = clamp(1.0 - elevation.abs() / 0.25);
+
+	let sun_color = lerp(NOON_SUN_COLOR, HORIZON_SUN_COLOR, warmth);
+	let sky_color = lerp(DAY_SKY_COLOR, HORIZON_SKY_COLOR, warmth);
+	let light = DirectionalLight {
+		direction: if elevation >= 0.0 { sun_direction } else { -sun_direction },
+		color: lerp(MOON_COLOR, sun_color, daylight),
+	};
+	let sky = lerp(NIGHT_SKY_COLOR, sky_color, daylight);
+	(light, (sky.0, sky.1, sky.2, 1.0))
+}
+
+/// Get the tint for a daytime skybox texture which makes it match
+/// `sky_color`, from `sun_and_sky`.
+pub fn sky_tint(sky_color: (f32, f32, f32)) -> (f32, f32, f32) {
+	(sky_color.0 / DAY_SKY_COLOR.0,
+		sky_color.1 / DAY_SKY_COLOR.1,
+		sky_color.2 / DAY_SKY_COLOR.2)
+}
+
+/// Debug views of the world's geometry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DebugMode {
+	/// Normal rendering.
+	Off,
+	/// Draw only triangle edges.
+	Wireframe,
+	/// Color surfaces by their normals.
+	Normals,
+}
+
+impl DebugMode {
+	/// The next mode, cycling back to `Off` after the last.
+	pub fn next(self) -> DebugMode {
+		match self {
+			DebugMode::Off => DebugMode::Wireframe,
+			DebugMode::Wireframe => DebugMode::Normals,
+			DebugMode::Normals => DebugMode::Off,
+		}
+	}
+
+	/// A short name for the mode, for display.
+	pub fn name(self) -> &'static str {
+		match self {
+			DebugMode::Off => "off",
+			DebugMode::Wireframe => "wireframe",
+			DebugMode::Normals => "normals",
+		}
+	}
+
+	/// Drawing parameters for this mode, based on `params`. Wireframes keep
+	/// `params`' depth test, so hidden edges stay hidden.
+	pub fn draw_parameters<'a>(self, params: &DrawParameters<'a>) -> DrawParameters<'a> {
+		DrawParameters {
+			polygon_mode: match self {
+				DebugMode::Wireframe => PolygonMode::Line,
+				_ => params.polygon_mode,
+			},
+			.. params.clone()
+		}
+	}
+
+	/// The value of the fragment shader's `u_debug_mode` uniform for this
+	/// mode.
+	fn uniform(self) -> i32 {
+		match self {
+			DebugMode::Normals => 1,
+			_ => 0,
+		}
+	}
+}
+
 /// Struct to hold render state for a typical OpenGL 3D object.
 pub struct DefaultRenderState<'a> {
 	/// View matrix
 	pub view: Mat4<f32>,
 	/// Perspective matrix
 	pub perspective: Mat4<f32>,
-	/// Direction of the global light
-	pub light_pos: Vec3<f32>,
-	/// Color of the global light
-	pub light_color: (f32, f32, f32),
+	/// Directional lights, up to `MAX_DIRECTIONAL_LIGHTS`
+	pub directional_lights: Vec<DirectionalLight>,
+	/// Point lights, up to `MAX_POINT_LIGHTS`
+	pub point_lights: Vec<PointLight>,
 	/// OpenGL drawing parameters
 	pub params: &'a DrawParameters<'a>,
 	/// Shader program to run
 	pub program: &'a Program,
+	/// Debug view to draw; `params` should come from its `draw_parameters`
+	pub debug_mode: DebugMode,
+	/// Opacity of models drawn. Anything less than 1 needs `params` to blend.
+	pub alpha: f32,
+	/// Color distant surfaces fade towards; this should match the sky.
+	pub fog_color: (f32, f32, f32),
+	/// Distance from the camera at which fog starts.
+	pub fog_start: f32,
+	/// Distance from the camera beyond which surfaces are entirely fog. Fog
+	/// is disabled if this isn't greater than `fog_start`.
+	pub fog_end: f32,
 }
 
 /// Default implementation for model::gpu::ModelInstances.
@@ -36,86 +211,1004 @@ impl<'a> Renderable<&'a DefaultRenderState<'a>, &'a mut Frame> for ModelInstance
 
 	/// Render this ModelInstance.
 	///
-	/// This computes model/view, model/view/perspective, normal and lighting
-	/// matrices and uses them to 3D render the model instance to the target.
+	/// This computes model/view, model/view/perspective and normal matrices
+	/// and eye space lights and uses them to 3D render the model instance to
+	/// the target.
 	fn render(&self, render_state: &DefaultRenderState, target: &mut Frame) {
-		let light_vector_raw: [f32; 3] = render_state.light_pos.into();
-		let x: Mat3<f32> = render_state.view.into();
-		let light_matrix_raw: [[f32; 3]; 3] = x.into();
 		let model_view = self.model_matrix * render_state.view;
 		let model_view_perspective_raw: [[f32; 4]; 4] =
 				(model_view * render_state.perspective).into();
+		let model_view_raw: [[f32; 4]; 4] = model_view.into();
 		let x: Mat3<f32> = model_view.into();
 		let normal_raw: [[f32; 3]; 3] = x.into();
+		let uniforms = uniform! {
+				model_view_matrix: model_view_raw,
+				model_view_perspective_matrix: model_view_perspective_raw,
+				normal_matrix: normal_raw,
+				u_mat_ambient: self.model.material.ambient,
+				u_mat_diffuse: self.model.material.diffuse,
+				u_mat_specular: self.model.material.specular,
+				u_mat_shininess: self.model.material.shininess,
+				u_mat_emissive: self.model.material.emissive,
+				u_mat_texture: self.model.material.sampler.sample(&self.model.material.texture),
+				// Something must be bound even without a normal map
+				u_mat_normal_map: self.model.material.sampler.sample(
+					self.model.material.normal_map.as_ref().unwrap_or(&self.model.material.texture)),
+				u_has_normal_map: self.model.material.normal_map.is_some(),
+				u_debug_mode: render_state.debug_mode.uniform(),
+				u_alpha: render_state.alpha,
+				u_fog_color: render_state.fog_color,
+				u_fog_start: render_state.fog_start,
+				u_fog_end: render_state.fog_end,
+				};
 		target.draw(
 			&self.model.geometry.vertices,
 			&self.model.geometry.indices,
 			render_state.program,
-			&uniform! {
-				model_view_perspective_matrix: model_view_perspective_raw,
-				normal_matrix: normal_raw,
-				light_matrix: light_matrix_raw,
-				u_light_pos: light_vector_raw,
-				u_light_color: render_state.light_color,
+			&LightUniforms::new(
+				&render_state.directional_lights,
+				&render_state.point_lights,
+				render_state.view,
+				uniforms),
+			render_state.params).unwrap();
+	}
+}
+
+/// Instanced implementation for model::gpu::ModelInstanceBatches.
+///
+/// The render state's program must take each instance's model matrix as a
+/// `mat4 instance_model_matrix` attribute, and view and perspective matrices
+/// as uniforms, rather than the per-object matrices `ModelInstance` uses.
+impl<'a, 'b> Renderable<&'a DefaultRenderState<'a>, &'a mut Frame> for ModelInstanceBatch<'b> {
+
+	/// Render every instance in this ModelInstanceBatch with one draw call.
+	fn render(&self, render_state: &DefaultRenderState, target: &mut Frame) {
+		let view_raw: [[f32; 4]; 4] = render_state.view.into();
+		let perspective_raw: [[f32; 4]; 4] = render_state.perspective.into();
+		let uniforms = uniform! {
+				view_matrix: view_raw,
+				perspective_matrix: perspective_raw,
 				u_mat_ambient: self.model.material.ambient,
+				u_mat_diffuse: self.model.material.diffuse,
 				u_mat_specular: self.model.material.specular,
-				u_mat_texture: self.model.material.texture
-					.sampled().wrap_function(SamplerWrapFunction::Repeat),
-				},
+				u_mat_shininess: self.model.material.shininess,
+				u_mat_emissive: self.model.material.emissive,
+				u_mat_texture: self.model.material.sampler.sample(&self.model.material.texture),
+				// Something must be bound even without a normal map
+				u_mat_normal_map: self.model.material.sampler.sample(
+					self.model.material.normal_map.as_ref().unwrap_or(&self.model.material.texture)),
+				u_has_normal_map: self.model.material.normal_map.is_some(),
+				u_debug_mode: render_state.debug_mode.uniform(),
+				u_alpha: render_state.alpha,
+				u_fog_color: render_state.fog_color,
+				u_fog_start: render_state.fog_start,
+				u_fog_end: render_state.fog_end,
+				};
+		target.draw(
+			(&self.model.geometry.vertices, self.instances.per_instance().unwrap()),
+			&self.model.geometry.indices,
+			render_state.program,
+			&LightUniforms::new(
+				&render_state.directional_lights,
+				&render_state.point_lights,
+				render_state.view,
+				uniforms),
 			render_state.params).unwrap();
 	}
 }
 
+/// Uniforms for arrays of lights, in eye space, alongside other uniforms.
+///
+/// `uniform!` can't express arrays of structs, so this sets each element of
+/// `u_directional_lights` and `u_point_lights` by name.
+struct LightUniforms<U: Uniforms> {
+	directional_lights: Vec<DirectionalLight>,
+	point_lights: Vec<PointLight>,
+	uniforms: U,
+}
+
+impl<U: Uniforms> LightUniforms<U> {
+	fn new(directional_lights: &[DirectionalLight],
+			point_lights: &[PointLight],
+			view: Mat4<f32>,
+			uniforms: U) -> LightUniforms<U> {
+		let to_eye = |v: Vec3<f32>, w: f32| Vec3::from(Vec4::from([v[0], v[1], v[2], w]) * view);
+		LightUniforms {
+			directional_lights: directional_lights.iter().take(MAX_DIRECTIONAL_LIGHTS)
+				.map(|light| DirectionalLight {
+					// Directions aren't affected by translation
+					direction: to_eye(light.direction, 0.0),
+					.. *light
+				})
+				.collect(),
+			point_lights: point_lights.iter().take(MAX_POINT_LIGHTS)
+				.map(|light| PointLight {
+					position: to_eye(light.position, 1.0),
+					.. *light
+				})
+				.collect(),
+			uniforms: uniforms,
+		}
+	}
+}
+
+impl<U: Uniforms> Uniforms for LightUniforms<U> {
+	fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut f: F) {
+		f("u_directional_light_count",
+				UniformValue::SignedInt(self.directional_lights.len() as i32));
+		for (i, light) in self.directional_lights.iter().enumerate() {
+			let name = |field| format!("u_directional_lights[{}].{}", i, field);
+			let (r, g, b) = light.color;
+			f(&name("direction"), UniformValue::Vec3(light.direction.into()));
+			f(&name("color"), UniformValue::Vec3([r, g, b]));
+		}
+		f("u_point_light_count", UniformValue::SignedInt(self.point_lights.len() as i32));
+		for (i, light) in self.point_lights.iter().enumerate() {
+			let name = |field| format!("u_point_lights[{}].{}", i, field);
+			let (r, g, b) = light.color;
+			f(&name("position"), UniformValue::Vec3(light.position.into()));
+			f(&name("color"), UniformValue::Vec3([r, g, b]));
+			f(&name("constant"), UniformValue::Float(light.constant));
+			f(&name("linear"), UniformValue::Float(light.linear));
+			f(&name("quadratic"), UniformValue::Float(light.quadratic));
+		}
+		self.uniforms.visit_values(f);
+	}
+}
+
+
+/// A corner of a glyph's quad.
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct TextVertex {
+	/// Position on screen, in clip space.
+	position: [f32; 2],
+	/// Position in the font texture, in UV.
+	tex_uv: [f32; 2],
+}
+implement_vertex!(TextVertex, position, tex_uv);
+
+/// The display and shader program text is drawn with, shared by all
+/// `TextRenderable2d`s.
+pub struct TextRenderer<'a> {
+	display: &'a Facade,
+	program: &'a Program,
+}
+
+impl<'a> TextRenderer<'a> {
+	/// Create a new TextRenderer.
+	///
+	/// `program` should take `position` (in clip space) and `tex_uv`
+	/// attributes, a `u_font` sampler and a `u_color` tint. Each text's glyph
+	/// quads are uploaded to `display` together when it's drawn.
+	pub fn new(display: &'a Facade, program: &'a Program) -> TextRenderer<'a> {
+		TextRenderer {
+			display: display,
+			program: program,
+		}
+	}
+}
 
 /// Render text to the screen
+///
+/// Text is rendered in rows from a position in pixels from the top left of
+/// the screen. Newlines start a new row, as does running out of room at the
+/// right edge of the screen.
+///
+/// The font texture is multiplied by the text's color, including alpha, and
+/// blended over whatever is already drawn.
+///
+/// Each character is drawn from a cell of the font, counting across rows from
+/// the top left. By default the cell is the character's code point, less the
+/// font's first character; see `glyph_map` to map characters differently.
 pub struct TextRenderable2d<'a> {
-	text: Vec<u8>,
+	renderer: &'a TextRenderer<'a>,
+	text: String,
 	font: &'a Texture2d,
 	chars_wide: u8,
 	chars_high: u8,
 	char_width: u32,
 	char_height: u32,
+	first_char: u8,
+	glyph_map: Option<fn(char) -> Option<u32>>,
+	missing_glyph: Option<u32>,
+	x: u32,
+	y: u32,
+	scale: f32,
+	color: (f32, f32, f32, f32),
 }
 
+/// Character drawn in place of those the font has no glyph for.
+const FALLBACK_CHAR: char = '?';
+
 impl<'a> TextRenderable2d<'a> {
 	/// Create a new TextRenderable2d containing the given text in the given
 	/// font (which is the given number of characters wide).
-	pub fn new(text: Vec<u8>, font: &Texture2d, chars_wide: u8) -> TextRenderable2d {
+	///
+	/// The text is positioned at the top left of the screen at the font's
+	/// native scale and color; see `at`, `scale` and `color` to change this.
+	/// A `chars_wide` of 0 is treated as 1.
+	pub fn new(renderer: &'a TextRenderer<'a>, text: &str, font: &'a Texture2d, chars_wide: u8)
+			-> TextRenderable2d<'a> {
+		let chars_wide = chars_wide.max(1);
 		let chars_high = (256 / chars_wide as u16) as u8;
 		let char_width = font.width() / chars_wide as u32;
 		let char_height = font.height() / chars_high as u32;
 		TextRenderable2d {
-			text: text,
+			renderer: renderer,
+			text: text.to_string(),
 			font: font,
 			chars_wide: chars_wide,
 			chars_high: chars_high,
 			char_width: char_width,
 			char_height: char_height,
+			first_char: 0,
+			glyph_map: None,
+			missing_glyph: None,
+			x: 0,
+			y: 0,
+			scale: 1.0,
+			color: (1.0, 1.0, 1.0, 1.0),
 		}
 	}
+
+	/// Use only part of the character set: the font is `chars_high`
+	/// characters high, and its first glyph is `first_char`, e.g. 6 rows
+	/// starting at `b' '` for printable ASCII in a 16-wide font.
+	///
+	/// Characters outside the font are drawn as `?`, or the missing glyph;
+	/// see `missing_glyph`. A `chars_high` of 0 is treated as 1.
+	pub fn glyphs(mut self, chars_high: u8, first_char: u8) -> TextRenderable2d<'a> {
+		let chars_high = chars_high.max(1);
+		self.chars_high = chars_high;
+		self.char_height = self.font.height() / chars_high as u32;
+		self.first_char = first_char;
+		self
+	}
+
+	/// Map characters to font cells with `map` instead of by code point, e.g.
+	/// for a font with accented letters in the cells of unused control
+	/// characters. Characters it returns `None` for, or a cell past the end
+	/// of the font, are missing.
+	pub fn glyph_map(mut self, map: fn(char) -> Option<u32>) -> TextRenderable2d<'a> {
+		self.glyph_map = Some(map);
+		self
+	}
+
+	/// Draw missing characters from font cell `cell`, instead of as `?`.
+	pub fn missing_glyph(mut self, cell: u32) -> TextRenderable2d<'a> {
+		self.missing_glyph = Some(cell);
+		self
+	}
+
+	/// Position the top left of this text `x` pixels from the left and `y`
+	/// pixels from the top of the screen.
+	pub fn at(mut self, x: u32, y: u32) -> TextRenderable2d<'a> {
+		self.x = x;
+		self.y = y;
+		self
+	}
+
+	/// Scale this text, e.g. by the window's DPI factor. Glyphs are filtered
+	/// linearly, so integer scales look sharpest.
+	pub fn scale(mut self, scale: f32) -> TextRenderable2d<'a> {
+		self.scale = scale;
+		self
+	}
+
+	/// Tint this text: the font's color and alpha are multiplied by `color`.
+	pub fn color(mut self, color: (f32, f32, f32, f32)) -> TextRenderable2d<'a> {
+		self.color = color;
+		self
+	}
+
+	/// The size in pixels of a character of this text, including scaling.
+	/// This is also how far each character advances.
+	pub fn char_size(&self) -> (u32, u32) {
+		(scale_size(self.char_width, self.scale), scale_size(self.char_height, self.scale))
+	}
 }
 
 impl<'a> Renderable<&'a DefaultRenderState<'a>, &'a mut Frame> for TextRenderable2d<'a> {
 	fn render(&self, _: &DefaultRenderState, target: &mut Frame) {
-		let font_surface = &self.font.as_surface();
-		let mut idx = 0u32;
-		for character in self.text.iter() {
-			let char_origin_x = (character % self.chars_wide) as u32 * self.char_width;
-			let char_origin_y = (self.chars_high - character / self.chars_high - 1) as u32 *
+		self.draw(target);
+	}
+}
+
+impl<'a> TextRenderable2d<'a> {
+	/// Draw this text; it doesn't need any render state.
+	fn draw(&self, target: &mut Frame) {
+		let params = DrawParameters {
+			blend: Blend::alpha_blending(),
+			.. Default::default()
+		};
+		let font_size = (self.font.width(), self.font.height());
+		let target_size = target.get_dimensions();
+		let layout = TextLayout {
+			chars_wide: self.chars_wide,
+			chars_high: self.chars_high,
+			char_width: self.char_width,
+			char_height: self.char_height,
+			first_char: self.first_char,
+			glyph_map: self.glyph_map,
+			missing_glyph: self.missing_glyph,
+			x: self.x,
+			y: self.y,
+			scale: self.scale,
+		};
+		let vertices: Vec<TextVertex> = layout.blits(&self.text, target_size).iter()
+			.flat_map(|&(ref source, ref dest)|
+				glyph_quad(source, dest, font_size, target_size).to_vec())
+			.collect();
+		if vertices.is_empty() {
+			return;
+		}
+		let drawn = VertexBuffer::new(self.renderer.display, &vertices)
+			.chain_err(|| "Could not upload text to GPU")
+			.and_then(|vertices| target.draw(
+					&vertices,
+					NoIndices(PrimitiveType::TrianglesList),
+					self.renderer.program,
+					&uniform! {
+						u_font: self.font.sampled()
+							.magnify_filter(MagnifySamplerFilter::Linear),
+						u_color: self.color,
+					},
+					&params)
+				.chain_err(|| "Could not draw text"));
+		if let Err(e) = drawn {
+			error!("{}", e);
+		}
+	}
+}
+
+/// A 2D element drawn over the 3D scene.
+pub enum OverlayElement<'a> {
+	/// A rectangle filled with a solid color, e.g. a panel behind text.
+	Panel(Rect, (f32, f32, f32, f32)),
+	/// A block of text.
+	Text(TextRenderable2d<'a>),
+}
+
+/// 2D elements to draw over the 3D scene, in order of layer.
+///
+/// Elements in lower layers are drawn first, so higher layers cover them.
+/// Elements in the same layer are drawn in the order they were added.
+pub struct Overlay<'a> {
+	elements: Vec<(i32, OverlayElement<'a>)>,
+}
+
+impl<'a> Overlay<'a> {
+	/// Create a new, empty Overlay.
+	pub fn new() -> Overlay<'a> {
+		Overlay { elements: Vec::new() }
+	}
+
+	/// Add an element to be drawn in the given layer.
+	pub fn add(&mut self, layer: i32, element: OverlayElement<'a>) {
+		self.elements.push((layer, element));
+	}
+
+	/// The elements in the order they'll be drawn.
+	fn ordered(&self) -> Vec<&OverlayElement<'a>> {
+		let mut elements: Vec<&(i32, OverlayElement<'a>)> = self.elements.iter().collect();
+		elements.sort_by_key(|&&(layer, _)| layer);
+		elements.into_iter().map(|&(_, ref element)| element).collect()
+	}
+}
+
+impl<'a, 'b> Renderable<&'b DefaultRenderState<'b>, &'b mut Frame> for Overlay<'a> {
+	fn render(&self, _: &DefaultRenderState, target: &mut Frame) {
+		for element in self.ordered() {
+			match *element {
+				OverlayElement::Panel(ref rect, color) =>
+					target.clear(Some(rect), Some(color), false, None, None),
+				OverlayElement::Text(ref text) => text.draw(target),
+			}
+		}
+	}
+}
+
+/// Font metrics and placement of a block of text.
+struct TextLayout {
+	chars_wide: u8,
+	chars_high: u8,
+	char_width: u32,
+	char_height: u32,
+	first_char: u8,
+	glyph_map: Option<fn(char) -> Option<u32>>,
+	missing_glyph: Option<u32>,
+	x: u32,
+	y: u32,
+	scale: f32,
+}
+
+/// Get the two triangles drawing a glyph from its place in a font texture of
+/// `font_size` to its place on a frame of `target_size`, both in pixels.
+fn glyph_quad(source: &Rect,
+		dest: &BlitTarget,
+		(font_width, font_height): (u32, u32),
+		(target_width, target_height): (u32, u32)) -> [TextVertex; 6] {
+	let (font_width, font_height) = (font_width as f32, font_height as f32);
+	let (target_width, target_height) = (target_width as f32, target_height as f32);
+	let (u, v) = (source.left as f32 / font_width, source.bottom as f32 / font_height);
+	let (uv_width, uv_height) = (source.width as f32 / font_width,
+			source.height as f32 / font_height);
+	let (x, y) = (dest.left as f32 / target_width * 2.0 - 1.0,
+			dest.bottom as f32 / target_height * 2.0 - 1.0);
+	let (width, height) = (dest.width as f32 / target_width * 2.0,
+			dest.height as f32 / target_height * 2.0);
+	// From (0, 0) at the bottom left to (1, 1) at the top right
+	let corner = |right: f32, top: f32| TextVertex {
+		position: [x + right * width, y + top * height],
+		tex_uv: [u + right * uv_width, v + top * uv_height],
+	};
+	let (bottom_left, bottom_right) = (corner(0.0, 0.0), corner(1.0, 0.0));
+	let (top_left, top_right) = (corner(0.0, 1.0), corner(1.0, 1.0));
+	[bottom_left, bottom_right, top_left, top_left, bottom_right, top_right]
+}
+
+/// Scale a glyph dimension to whole pixels, keeping at least one.
+fn scale_size(size: u32, scale: f32) -> u32 {
+	((size as f32 * scale).round() as u32).max(1)
+}
+
+impl TextLayout {
+	/// The cell of `character`'s glyph in the font, counting across rows from
+	/// the top left, or the missing glyph's if the font doesn't have it. That
+	/// is `missing_glyph`, or `FALLBACK_CHAR`'s cell. `None` if there's no
+	/// glyph to draw.
+	fn glyph(&self, character: char) -> Option<u32> {
+		let glyphs = self.chars_wide as u32 * self.chars_high as u32;
+		let in_font = |i: u32| if i < glyphs { Some(i) } else { None };
+		let cell = |c: char| match self.glyph_map {
+			Some(map) => map(c),
+			None => (c as u32).checked_sub(self.first_char as u32),
+		}.and_then(in_font);
+		cell(character).or_else(|| match self.missing_glyph {
+			Some(missing) => in_font(missing),
+			None => cell(FALLBACK_CHAR),
+		})
+	}
+
+	/// Compute the font texture source and frame target of each character of
+	/// `text` visible in a frame of the given dimensions.
+	///
+	/// Characters which are partially off the frame are clipped, and those
+	/// entirely off it are skipped.
+	fn blits(&self, text: &str, (target_width, target_height): (u32, u32))
+			-> Vec<(Rect, BlitTarget)> {
+		let (width, height) = (scale_size(self.char_width, self.scale),
+				scale_size(self.char_height, self.scale));
+		let mut blits = Vec::new();
+		let (mut x, mut y) = (self.x, self.y);
+		for character in text.chars() {
+			if character == '\n' {
+				x = self.x;
+				y += height;
+				continue;
+			}
+			// Wrap rather than running off the right of the screen
+			if x + width > target_width && x > self.x {
+				x = self.x;
+				y += height;
+			}
+			// Rows below the bottom of the screen can't be drawn
+			if y >= target_height {
+				break;
+			}
+			let char_x = x;
+			x += width;
+			if char_x >= target_width {
+				continue;
+			}
+
+			// Clip to the right and bottom edges of the frame
+			let visible_width = width.min(target_width - char_x);
+			let visible_height = height.min(target_height - y);
+			let source_width = self.char_width * visible_width / width;
+			let source_height = self.char_height * visible_height / height;
+			if source_width == 0 || source_height == 0 {
+				continue;
+			}
+
+			let glyph = match self.glyph(character) {
+				Some(glyph) => glyph,
+				None => continue,
+			};
+			// Textures have the origin at the bottom left, so the top of the
+			// character is at the top of its cell.
+			let chars_wide = self.chars_wide as u32;
+			let char_origin_x = glyph % chars_wide * self.char_width;
+			let char_origin_y = (self.chars_high as u32 - glyph / chars_wide - 1) *
 					self.char_height;
-			target.blit_from_simple_framebuffer(
-					font_surface,
-					&Rect {left: char_origin_x,
-							bottom: char_origin_y,
-							width: self.char_width,
-							height: self.char_height },
-					&BlitTarget {left: idx * self.char_width,
-							bottom: target.get_dimensions().1 - self.char_height,
-							width: self.char_width as i32,
-							height: self.char_height as i32 },
-					MagnifySamplerFilter::Linear);
-
-			idx += 1;
+			blits.push( (
+				Rect {left: char_origin_x,
+						bottom: char_origin_y + self.char_height - source_height,
+						width: source_width,
+						height: source_height },
+				BlitTarget {left: char_x,
+						bottom: target_height - y - visible_height,
+						width: visible_width as i32,
+						height: visible_height as i32 } ) );
+		}
+		blits
+	}
+}
+
+/// A vertex for debug lines.
+#[derive(Copy, Clone, Debug)]
+pub struct DebugVertex {
+	/// The location of this vertex.
+	pub position: [f32; 3],
+	/// The color of this vertex.
+	pub color: [f32; 3],
+}
+implement_vertex!(DebugVertex, position, color);
+
+/// Render the edges of a view frustum as lines, e.g. to visualize culling
+/// from another viewpoint.
+pub struct FrustumDebugRenderable<'a> {
+	vertices: VertexBuffer<DebugVertex>,
+	program: &'a Program,
+}
+
+impl<'a> FrustumDebugRenderable<'a> {
+	/// Create a new FrustumDebugRenderable for the frustum with the given
+	/// corners (as from `display_math::frustum_corners`), in the given color.
+	///
+	/// `program` should take `position` and `color` attributes and a
+	/// `model_view_perspective_matrix` uniform.
+	pub fn new(display: &Facade,
+			corners: &[Vec3<f32>; 8],
+			color: [f32; 3],
+			program: &'a Program) -> Result<FrustumDebugRenderable<'a>> {
+		let mut lines = Vec::with_capacity(24);
+		// Corners whose indices differ by one bit share an edge
+		for a in 0..8 {
+			for bit in 0..3 {
+				let b = a | (1 << bit);
+				if b != a {
+					lines.push(DebugVertex { position: corners[a].into(), color: color });
+					lines.push(DebugVertex { position: corners[b].into(), color: color });
+				}
+			}
+		}
+		Ok( FrustumDebugRenderable {
+			vertices: try!{ VertexBuffer::new(display, &lines)
+					.chain_err(|| "Could not upload frustum lines to GPU") },
+			program: program,
+		} )
+	}
+}
+
+impl<'a, 'b> Renderable<&'b DefaultRenderState<'b>, &'b mut Frame> for FrustumDebugRenderable<'a> {
+	fn render(&self, render_state: &DefaultRenderState, target: &mut Frame) {
+		let view_perspective_raw: [[f32; 4]; 4] =
+				(render_state.view * render_state.perspective).into();
+		target.draw(
+			&self.vertices,
+			NoIndices(PrimitiveType::LinesList),
+			self.program,
+			&uniform! { model_view_perspective_matrix: view_perspective_raw },
+			render_state.params).unwrap();
+	}
+}
+
+/// Height of the skybox's haze, as the sine of the elevation above the
+/// horizon at which it clears.
+const SKY_HAZE_HEIGHT: f32 = 0.2;
+
+/// A vertex for skybox faces.
+#[derive(Copy, Clone, Debug)]
+pub struct SkyboxVertex {
+	/// The location of this vertex.
+	pub position: [f32; 3],
+	/// The texture UV coordinates at this vertex.
+	pub tex_uv: [f32; 2],
+}
+implement_vertex!(SkyboxVertex, position, tex_uv);
+
+/// Render a textured cube around the camera, which stays still as the camera
+/// moves so it looks infinitely far away.
+///
+/// This should be rendered before anything else, as it doesn't write depth.
+/// The textures are tinted by `sky_tint` of the render state's fog color, and
+/// fade into the fog color at the horizon when fog is on, so distant surfaces
+/// blend into the sky.
+pub struct Skybox<'a> {
+	faces: Vec<(VertexBuffer<SkyboxVertex>, Texture2d)>,
+	program: &'a Program,
+}
+
+impl<'a> Skybox<'a> {
+	/// Create a new Skybox from its six face textures, in the order +X, -X,
+	/// +Y, -Y, +Z, -Z (as in OpenGL cubemaps).
+	///
+	/// Side faces are upright, the top face has +Z at its top, and the
+	/// bottom face has -Z at its top. `program` should take `position` and
+	/// `tex_uv` attributes and `view_perspective_matrix`, `u_texture`,
+	/// `u_tint`, `u_fog_color` and `u_haze_height` uniforms.
+	pub fn new(display: &Facade,
+			textures: Vec<Texture2d>,
+			program: &'a Program) -> Result<Skybox<'a>> {
+		if textures.len() != 6 {
+			bail!("Skybox needs 6 faces, got {}", textures.len());
+		}
+		// Outward normal and up for each face
+		let axes: [([f32; 3], [f32; 3]); 6] = [
+			([1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+			([-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+			([0.0, 1.0, 0.0], [0.0, 0.0, 1.0]),
+			([0.0, -1.0, 0.0], [0.0, 0.0, -1.0]),
+			([0.0, 0.0, 1.0], [0.0, 1.0, 0.0]),
+			([0.0, 0.0, -1.0], [0.0, 1.0, 0.0]),
+		];
+		let mut faces = Vec::with_capacity(6);
+		for (&(normal, up), texture) in axes.iter().zip(textures.into_iter()) {
+			let (n, u) = (Vec3::from(normal), Vec3::from(up));
+			// Right as seen from inside the cube
+			let r = u.cross(n);
+			// Textures are stored top row first, so V increases downwards
+			let vertex = |p: Vec3<f32>, uv: [f32; 2]| SkyboxVertex {
+				position: p.into(),
+				tex_uv: uv,
+			};
+			let top_left = vertex(n + u - r, [0.0, 0.0]);
+			let top_right = vertex(n + u + r, [1.0, 0.0]);
+			let bottom_left = vertex(n - u - r, [0.0, 1.0]);
+			let bottom_right = vertex(n - u + r, [1.0, 1.0]);
+			let vertices = [top_left, bottom_left, top_right, top_right, bottom_left, bottom_right];
+			faces.push( (
+				try!{ VertexBuffer::new(display, &vertices)
+						.chain_err(|| "Could not upload skybox to GPU") },
+				texture) );
+		}
+		Ok( Skybox {
+			faces: faces,
+			program: program,
+		} )
+	}
+}
+
+impl<'a, 'b> Renderable<&'b DefaultRenderState<'b>, &'b mut Frame> for Skybox<'a> {
+	fn render(&self, render_state: &DefaultRenderState, target: &mut Frame) {
+		// Strip translation from the view so the sky is always around us
+		let mut view = render_state.view;
+		view[3] = [0.0, 0.0, 0.0, 1.0];
+		let view_perspective_raw: [[f32; 4]; 4] = (view * render_state.perspective).into();
+		let params = DrawParameters {
+			depth: Depth {
+				test: DepthTest::Overwrite,
+				write: false,
+				.. Default::default()
+			},
+			backface_culling: BackfaceCullingMode::CullingDisabled,
+			viewport: render_state.params.viewport,
+			.. Default::default()
+		};
+		let haze_height = if render_state.fog_end > render_state.fog_start {
+			SKY_HAZE_HEIGHT
+		} else {
+			0.0
+		};
+		for &(ref vertices, ref texture) in self.faces.iter() {
+			target.draw(
+				vertices,
+				NoIndices(PrimitiveType::TrianglesList),
+				self.program,
+				&uniform! {
+					view_perspective_matrix: view_perspective_raw,
+					u_texture: texture.sampled()
+						.wrap_function(SamplerWrapFunction::Clamp)
+						.magnify_filter(MagnifySamplerFilter::Linear),
+					u_tint: sky_tint(render_state.fog_color),
+					u_fog_color: render_state.fog_color,
+					u_haze_height: haze_height,
+				},
+				&params).unwrap();
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use glium::{BlitTarget, Rect};
+	use glium::uniforms::{EmptyUniforms, UniformValue, Uniforms};
+	use linear_algebra::{Mat4, Vec3};
+	use super::{DebugMode, DirectionalLight, LightUniforms, MAX_POINT_LIGHTS, PointLight};
+	use super::{TextLayout, TextVertex, glyph_quad};
+	use super::{Overlay, OverlayElement, sky_tint, sun_and_sky};
+
+	fn layout(x: u32, y: u32, scale: f32) -> TextLayout {
+		TextLayout {
+			chars_wide: 16,
+			chars_high: 16,
+			char_width: 8,
+			char_height: 8,
+			first_char: 0,
+			glyph_map: None,
+			missing_glyph: None,
+			x: x,
+			y: y,
+			scale: scale,
+		}
+	}
+
+	#[test]
+	fn test_glyph_quad() {
+		// The top right glyph of a 4x4 font, in the bottom left quarter of
+		// the frame
+		let quad = glyph_quad(
+			&Rect { left: 24, bottom: 24, width: 8, height: 8 },
+			&BlitTarget { left: 0, bottom: 0, width: 320, height: 240 },
+			(32, 32),
+			(640, 480));
+		let bottom_left = TextVertex { position: [-1.0, -1.0], tex_uv: [0.75, 0.75] };
+		let bottom_right = TextVertex { position: [0.0, -1.0], tex_uv: [1.0, 0.75] };
+		let top_left = TextVertex { position: [-1.0, 0.0], tex_uv: [0.75, 1.0] };
+		let top_right = TextVertex { position: [0.0, 0.0], tex_uv: [1.0, 1.0] };
+		assert_eq!([bottom_left, bottom_right, top_left, top_left, bottom_right, top_right], quad);
+	}
+
+	#[test]
+	fn test_glyph_sources() {
+		// A 16x8 font of 8x16 pixel characters covers all of ASCII
+		let ascii = TextLayout { chars_high: 8, char_height: 16, .. layout(0, 0, 1.0) };
+		for character in 0x20u8..0x7f {
+			let blits = ascii.blits(&(character as char).to_string(), (8, 16));
+			assert_eq!(vec![Rect {
+					left: (character % 16) as u32 * 8,
+					bottom: (7 - character / 16) as u32 * 16,
+					width: 8,
+					height: 16 }],
+				blits.iter().map(|&(source, _)| source).collect::<Vec<_>>(),
+				"character {:?}", character as char);
+		}
+		// Past the end of the font is drawn as '?', in column 15 of row 3
+		let question = Rect { left: 15 * 8, bottom: 4 * 16, width: 8, height: 16 };
+		assert_eq!(question, ascii.blits("\u{80}", (8, 16))[0].0);
+		assert_eq!(question, ascii.blits("\u{ff}", (8, 16))[0].0);
+
+		// A 16x6 font starting at ' ' has '!' in column 1 of the top row
+		let printable = TextLayout { chars_high: 6, first_char: b' ', .. layout(0, 0, 1.0) };
+		assert_eq!(Rect { left: 8, bottom: 5 * 8, width: 8, height: 8 },
+			printable.blits("!", (8, 8))[0].0);
+		assert_eq!(Some(b'?' as u32 - 0x20), printable.glyph('\t'));
+		assert_eq!(Some(0x7f - 0x20), printable.glyph('\u{7f}'));
+
+		// With no '?' in the font, unknown characters are skipped
+		let digits = TextLayout { chars_wide: 10, chars_high: 1, first_char: b'0', .. layout(0, 0, 1.0) };
+		let blits = digits.blits("1a2", (24, 8));
+		assert_eq!(2, blits.len());
+		assert_eq!(16, blits[1].1.left);
+	}
+
+	/// Map accented letters to the cells of control characters 1 to 4, as
+	/// some fonts put them.
+	fn accented_cells(character: char) -> Option<u32> {
+		match character {
+			'é' => Some(1),
+			'è' => Some(2),
+			'ü' => Some(3),
+			'ñ' => Some(4),
+			'\u{0}'..='\u{1f}' => None,
+			_ => Some(character as u32),
+		}
+	}
+
+	#[test]
+	fn test_non_ascii_glyphs() {
+		let cells = |layout: &TextLayout, text: &str| text.chars()
+			.map(|c| layout.glyph(c))
+			.collect::<Vec<_>>();
+		// A 256 character font covers Latin-1; anything past it is missing
+		let latin1 = layout(0, 0, 1.0);
+		assert_eq!(vec![Some(0x5a), Some(0xfc), Some(0x72), Some(0x3f), Some(0x3f)],
+			cells(&latin1, "Zür€✓"));
+		// Multi-byte characters are a single glyph
+		assert_eq!(3, latin1.blits("née", (100, 100)).len());
+
+		let mapped = TextLayout {
+			glyph_map: Some(accented_cells),
+			missing_glyph: Some(0),
+			.. layout(0, 0, 1.0)
+		};
+		assert_eq!(vec![Some(0x6e), Some(1), Some(3), Some(0), Some(0)],
+			cells(&mapped, "néü\u{7}€"));
+		// A missing glyph outside the font isn't drawn
+		let outside = TextLayout { missing_glyph: Some(256), .. layout(0, 0, 1.0) };
+		assert_eq!(None, outside.glyph('€'));
+	}
+
+	#[test]
+	fn test_text_clipping() {
+		// Character 0x41 is in column 1 of row 4 from the top of the font
+		let source = |width, height| Rect {
+			left: 8,
+			bottom: 11 * 8 + 8 - height,
+			width: width,
+			height: height,
+		};
+		let target = |left, bottom, width, height| BlitTarget {
+			left: left,
+			bottom: bottom,
+			width: width,
+			height: height,
+		};
+
+		// Two full characters, then wrap to the next row
+		let blits = layout(0, 0, 1.0).blits("AAAA", (20, 20));
+		assert_eq!(blits, vec![
+			(source(8, 8), target(0, 12, 8, 8)),
+			(source(8, 8), target(8, 12, 8, 8)),
+			(source(8, 8), target(0, 4, 8, 8)),
+			(source(8, 8), target(8, 4, 8, 8))]);
+
+		// Clipped at the right, then at the bottom, then skipped
+		let blits = layout(14, 6, 1.0).blits("A\nA\nA", (20, 20));
+		assert_eq!(blits, vec![
+			(source(6, 8), target(14, 6, 6, 8)),
+			(source(6, 6), target(14, 0, 6, 6))]);
+
+		// A character bigger than the window is clipped to the window
+		let blits = layout(0, 0, 4.0).blits("A", (20, 20));
+		assert_eq!(blits, vec![(source(5, 5), target(0, 0, 20, 20))]);
+
+		// Text entirely off the window isn't drawn
+		assert!(layout(20, 0, 1.0).blits("AA", (20, 20)).is_empty());
+		assert!(layout(0, 20, 1.0).blits("AA", (20, 20)).is_empty());
+		assert!(layout(0, 0, 1.0).blits("AA", (0, 0)).is_empty());
+	}
+
+	#[test]
+	fn test_text_position() {
+		// Text at (30, 40) in a 100x100 frame, scaled up, with a second row
+		// starting back at X = 30
+		let targets: Vec<BlitTarget> = layout(30, 40, 2.0).blits("AB\nC", (100, 100))
+			.into_iter().map(|(_, target)| target).collect();
+		let target = |left, bottom| BlitTarget { left: left, bottom: bottom, width: 16, height: 16 };
+		assert_eq!(vec![target(30, 44), target(46, 44), target(30, 28)], targets);
+	}
+
+	#[test]
+	fn test_text_fractional_scale() {
+		// At 1.5x, 8x8 glyphs are drawn 12x12 from the whole glyph, and each
+		// one advances 12 pixels
+		let blits = layout(0, 0, 1.5).blits("AA\nA", (100, 100));
+		let target = |left, bottom| BlitTarget { left: left, bottom: bottom, width: 12, height: 12 };
+		let source = Rect { left: 8, bottom: 11 * 8, width: 8, height: 8 };
+		assert_eq!(vec![
+				(source, target(0, 88)),
+				(source, target(12, 88)),
+				(source, target(0, 76))],
+			blits);
+		// Tiny scales still draw something
+		assert_eq!(1, layout(0, 0, 0.01).blits("A", (100, 100))[0].1.width);
+	}
+
+	#[test]
+	fn test_light_uniforms() {
+		let light = PointLight {
+			position: Vec3::from([1.0, 2.0, 3.0]),
+			color: (1.0, 0.5, 0.25),
+			constant: 1.0,
+			linear: 0.5,
+			quadratic: 0.25,
+		};
+		// Moves the world 10 units down X
+		let view = Mat4::from([
+			[1.0, 0.0, 0.0, 0.0],
+			[0.0, 1.0, 0.0, 0.0],
+			[0.0, 0.0, 1.0, 0.0],
+			[-10.0, 0.0, 0.0, 1.0]]);
+		let lights = vec![light; MAX_POINT_LIGHTS + 2];
+		let sun = DirectionalLight {
+			direction: Vec3::from([0.0, 1.0, 0.0]),
+			color: (1.0, 1.0, 1.0),
+		};
+		let uniforms = LightUniforms::new(&[sun], &lights, view, EmptyUniforms);
+		let mut count = None;
+		let mut directional_count = None;
+		let mut positions = Vec::new();
+		let mut directions = Vec::new();
+		uniforms.visit_values(|name, value| match (name, value) {
+			("u_point_light_count", UniformValue::SignedInt(n)) => count = Some(n),
+			("u_directional_light_count", UniformValue::SignedInt(n)) =>
+				directional_count = Some(n),
+			(name, UniformValue::Vec3(p)) if name.ends_with(".position") =>
+				positions.push((name.to_string(), p)),
+			(name, UniformValue::Vec3(d)) if name.ends_with(".direction") =>
+				directions.push((name.to_string(), d)),
+			_ => (),
+		});
+		// Extra lights are dropped, and the rest are in eye space
+		assert_eq!(count, Some(MAX_POINT_LIGHTS as i32));
+		assert_eq!(positions.len(), MAX_POINT_LIGHTS);
+		assert_eq!(positions[0], ("u_point_lights[0].position".to_string(), [-9.0, 2.0, 3.0]));
+		// Directions aren't translated
+		assert_eq!(directional_count, Some(1));
+		assert_eq!(directions,
+			vec![("u_directional_lights[0].direction".to_string(), [0.0, 1.0, 0.0])]);
+	}
+
+	#[test]
+	fn test_debug_mode_cycle() {
+		let mut mode = DebugMode::Off;
+		let mut names = Vec::new();
+		for _ in 0..3 {
+			mode = mode.next();
+			names.push(mode.name());
+		}
+		assert_eq!(vec!["wireframe", "normals", "off"], names);
+		assert_eq!(0, DebugMode::Wireframe.uniform());
+		assert_eq!(1, DebugMode::Normals.uniform());
+	}
+
+	#[test]
+	fn test_point_light_radius() {
+		let light = PointLight::with_radius(Vec3::from([0.0, 0.0, 0.0]), (1.0, 1.0, 1.0), 4.0);
+		let attenuation = |d: f32| 1.0 / (light.constant + light.linear * d + light.quadratic * d * d);
+		assert_eq!(1.0, attenuation(0.0));
+		assert_eq!(0.5, attenuation(4.0));
+		assert!(attenuation(8.0) < 0.25);
+	}
+
+	#[test]
+	fn test_sun_and_sky() {
+		// Noon: overhead and white
+		let (sun, sky) = sun_and_sky(0.5);
+		assert!(sun.direction[1] > 0.9);
+		let close = |a: f32, b: f32| (a - b).abs() < 1e-5;
+		assert!(close(sun.color.0, 1.0) && close(sun.color.1, 1.0) && close(sun.color.2, 1.0));
+		assert!(close(sky.0, 0.5) && close(sky.1, 0.5) && close(sky.2, 1.0));
+
+		// Sunset: low in -X and warm
+		let (sun, _) = sun_and_sky(0.74);
+		assert!(sun.direction[0] < -0.9 && sun.direction[1] > 0.0);
+		assert!(sun.color.0 > sun.color.2);
+
+		// Midnight: the dim moon lights the scene from above
+		let (moon, sky) = sun_and_sky(0.0);
+		assert!(moon.direction[1] > 0.9);
+		assert!(moon.color.0 < 0.2);
+		assert!(sky.2 < 0.1);
+		let (_, wrapped) = sun_and_sky(1.0);
+		assert!(close(wrapped.2, sky.2));
+	}
+
+	#[test]
+	fn test_sky_tint() {
+		let close = |a: f32, b: f32| (a - b).abs() < 1e-5;
+		// Daytime skybox textures are untinted at noon
+		let (_, sky) = sun_and_sky(0.5);
+		let tint = sky_tint((sky.0, sky.1, sky.2));
+		assert!(close(tint.0, 1.0) && close(tint.1, 1.0) && close(tint.2, 1.0));
+		// ...warm at sunset...
+		let (_, sky) = sun_and_sky(0.74);
+		let tint = sky_tint((sky.0, sky.1, sky.2));
+		assert!(tint.0 > tint.2);
+		// ...and dark at night
+		let (_, sky) = sun_and_sky(0.0);
+		let tint = sky_tint((sky.0, sky.1, sky.2));
+		assert!(tint.0 < 0.1 && tint.1 < 0.1 && tint.2 < 0.1);
+	}
+
+	#[test]
+	fn test_overlay_order() {
+		let panel = |left| OverlayElement::Panel(
+			Rect { left: left, bottom: 0, width: 1, height: 1 },
+			(0.0, 0.0, 0.0, 1.0));
+		let mut overlay = Overlay::new();
+		overlay.add(2, panel(2));
+		overlay.add(0, panel(0));
+		overlay.add(1, panel(1));
+		overlay.add(0, panel(10));
+		let lefts: Vec<u32> = overlay.ordered().iter()
+			.map(|element| match **element {
+				OverlayElement::Panel(rect, _) => rect.left,
+				OverlayElement::Text(_) => panic!("no text was added"),
+			})
+			.collect();
+		assert_eq!(vec![0, 10, 1, 2], lefts);
+	}
+}
@@ -0,0 +1,92 @@
+//! Module for measuring frame times.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Frame time statistics over a rolling window of recent frames.
+#[derive(Clone, Debug)]
+pub struct FrameTimer {
+	history: VecDeque<Duration>,
+	window: usize,
+	last: Option<Instant>,
+}
+
+impl FrameTimer {
+
+	/// Create a new FrameTimer which keeps statistics over the last `window`
+	/// frames.
+	pub fn new(window: usize) -> FrameTimer {
+		FrameTimer {
+			history: VecDeque::with_capacity(window),
+			window: window,
+			last: None,
+		}
+	}
+
+	/// Mark the end of a frame at `now`. The first tick only starts timing.
+	pub fn tick(&mut self, now: Instant) {
+		if let Some(last) = self.last {
+			if self.history.len() >= self.window {
+				self.history.pop_front();
+			}
+			self.history.push_back(now.duration_since(last));
+		}
+		self.last = Some(now);
+	}
+
+	/// Get the average frames per second over the window, or 0 if no frames
+	/// have been timed.
+	pub fn fps(&self) -> f32 {
+		let avg = self.avg_frame_ms();
+		if avg > 0.0 { 1000.0 / avg } else { 0.0 }
+	}
+
+	/// Get the shortest frame time in the window, in milliseconds.
+	pub fn min_frame_ms(&self) -> f32 {
+		self.history.iter().min().map(|d| millis(*d)).unwrap_or(0.0)
+	}
+
+	/// Get the longest frame time in the window, in milliseconds.
+	pub fn max_frame_ms(&self) -> f32 {
+		self.history.iter().max().map(|d| millis(*d)).unwrap_or(0.0)
+	}
+
+	/// Get the average frame time over the window, in milliseconds.
+	pub fn avg_frame_ms(&self) -> f32 {
+		if self.history.is_empty() {
+			return 0.0;
+		}
+		let total = self.history.iter().fold(Duration::from_secs(0), |sum, d| sum + *d);
+		millis(total) / self.history.len() as f32
+	}
+}
+
+/// Convert a duration to milliseconds.
+pub fn millis(duration: Duration) -> f32 {
+	duration.as_secs() as f32 * 1000.0 + duration.subsec_nanos() as f32 / 1_000_000.0
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::{Duration, Instant};
+	use super::FrameTimer;
+
+	#[test]
+	fn test_frame_timer() {
+		let mut timer = FrameTimer::new(3);
+		let start = Instant::now();
+		timer.tick(start);
+		assert_eq!(0.0, timer.fps());
+
+		// 10ms, 20ms, 30ms, then 50ms pushes out the 10ms frame
+		let mut now = start;
+		for &ms in [10, 20, 30, 50].iter() {
+			now += Duration::from_millis(ms);
+			timer.tick(now);
+		}
+		assert_eq!(20.0, timer.min_frame_ms());
+		assert_eq!(50.0, timer.max_frame_ms());
+		assert!((timer.avg_frame_ms() - 100.0 / 3.0).abs() < 1e-3);
+		assert!((timer.fps() - 30.0).abs() < 1e-3);
+	}
+}
@@ -0,0 +1,71 @@
+//! Small general-purpose utilities.
+
+use std::ops::{Add, Mul, Sub};
+
+/// Exponential moving average of a signal sampled at irregular intervals.
+///
+/// Each update moves the smoothed value towards the input by an amount that
+/// depends on the time elapsed since the previous update, so the result
+/// converges at the same rate regardless of frame rate. After `time_constant`
+/// seconds of a constant input, the value has covered `1 - 1/e` (about 63%) of
+/// the distance to it.
+#[derive(Clone, Copy, Debug)]
+pub struct Smoother<T> {
+	value: T,
+	time_constant: f32,
+}
+
+impl<T> Smoother<T> where T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T> {
+	/// Create a new `Smoother` starting at `initial` and converging with the
+	/// given time constant, in seconds.
+	pub fn new(initial: T, time_constant: f32) -> Smoother<T> {
+		Smoother {
+			value: initial,
+			time_constant: time_constant,
+		}
+	}
+
+	/// Feed a new input sample, `dt` seconds after the previous one, and
+	/// return the updated smoothed value.
+	pub fn update(&mut self, input: T, dt: f32) -> T {
+		let alpha = 1.0 - (-dt / self.time_constant).exp();
+		self.value = self.value + (input - self.value) * alpha;
+		self.value
+	}
+
+	/// Get the current smoothed value.
+	pub fn value(&self) -> T {
+		self.value
+	}
+
+	/// Jump immediately to `value`, discarding history.
+	pub fn reset(&mut self, value: T) {
+		self.value = value;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::Smoother;
+
+	#[test]
+	fn test_smoother_step() {
+		for &steps in [1, 10, 1000].iter() {
+			let mut smoother = Smoother::new(0.0f32, 0.5);
+			let dt = 0.5 / steps as f32;
+			for _ in 0..steps {
+				smoother.update(1.0, dt);
+			}
+			let expected = 1.0 - (-1.0f32).exp();
+			assert!((smoother.value() - expected).abs() < 1e-4,
+				"{} steps: expected {}, got {}", steps, expected, smoother.value());
+		}
+
+		// And it should keep converging towards the input
+		let mut smoother = Smoother::new(0.0f32, 0.5);
+		for _ in 0..100 {
+			smoother.update(1.0, 0.1);
+		}
+		assert!((smoother.value() - 1.0).abs() < 1e-4);
+	}
+}